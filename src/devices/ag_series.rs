@@ -4,71 +4,87 @@
 //! - AG500 DIGITAL
 //! - AG620 DIGITAL
 
-use crate::monitor::cpu::Cpu;
-use super::{device_error, Mode, AUTO_MODE_INTERVAL};
-use hidapi::HidApi;
-use std::{thread::sleep, time::{Duration, Instant}};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{transport::Transport, keep_blank, AlarmSupport, Capabilities, Device, Mode, Options, AUTO_MODE_INTERVAL};
+use std::{io, time::Instant};
 
 pub const DEFAULT_MODE: Mode = Mode::CpuTemperature;
 pub const TEMP_LIMIT_C: u8 = 90;
 
 pub struct Display {
-    cpu: Cpu,
-    pub mode: Mode,
-    update: Duration,
-    alarm: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, mode: &Mode, update: Duration, alarm: bool) -> Self {
-        // Verify the display mode
-        let mode = match mode {
-            Mode::Default => DEFAULT_MODE,
-            Mode::Auto => Mode::Auto,
-            Mode::CpuTemperature => Mode::CpuTemperature,
-            Mode::CpuUsage => Mode::CpuUsage,
-            _ => mode.support_error(),
-        };
-
-        Display {
-            cpu,
-            mode,
-            update,
-            alarm,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[Mode::Auto, Mode::CpuTemperature, Mode::CpuUsage],
+            secondary: &[],
+            fahrenheit: false,
+            alarm: AlarmSupport::Configurable,
+            rotate: false,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::Celsius,
+            alarm: Alarm {
+                state: if self.options.alarm { AlarmState::On } else { AlarmState::Off },
+                temp_limit: TEMP_LIMIT_C,
+                temp_warning: 0,
+            },
+            update: self.options.update,
+        }
+    }
 
-        // Display warning if a required module is missing
-        self.cpu.warn_temp();
+    fn fields(&self) -> Fields {
+        match self.options.mode {
+            Mode::Off => Fields::NONE,
+            _ => Fields::CPU_USAGE | Fields::CPU_TEMP,
+        }
+    }
+
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
+        }
 
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
+        // Display warning if a required module is missing
+        sampler.cpu().warn_temp();
 
         // Display loop
-        match self.mode {
+        match self.options.mode {
             Mode::Auto => {
-                let mut initial_update = self.update;
-                let mut mode = Mode::CpuTemperature;
+                let mut initial_update = self.options.update;
+                let mut options = Options { mode: Mode::CpuTemperature, ..self.options };
                 loop {
                     // Initial update
-                    device.write(&self.status_message(&data, &mode, initial_update)).unwrap();
+                    device.write(&encode(&sampler.sample(initial_update), &options))?;
 
                     // Update until timeout
                     let timeout = Instant::now() + AUTO_MODE_INTERVAL;
-                    while Instant::now() + self.update < timeout {
-                        device.write(&self.status_message(&data, &mode, self.update)).unwrap();
+                    while Instant::now() + self.options.update < timeout {
+                        device.write(&encode(&sampler.sample(self.options.update), &options))?;
                     }
 
                     // Make the next initial update faster to fit the timeframe
                     initial_update = timeout - Instant::now();
 
                     // Switch to the next display mode
-                    mode = match mode {
+                    options.mode = match options.mode {
                         Mode::CpuTemperature => Mode::CpuUsage,
                         Mode::CpuUsage => Mode::CpuTemperature,
                         _ => DEFAULT_MODE,
@@ -76,46 +92,76 @@ impl Display {
                 }
             }
             _ => loop {
-                device.write(&self.status_message(&data, &self.mode, self.update)).unwrap();
+                device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
             }
         }
     }
 
-    /// Reads the CPU status information and returns the data packet.
-    fn status_message(&self, inital_data: &[u8; 64], mode: &Mode, update: Duration) -> [u8; 64] {
-        // Clone the data packet
-        let mut data = inital_data.clone();
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
 
-        if mode == &Mode::CpuUsage {
-            // Read CPU utilization
-            let cpu_instant = self.cpu.read_instant();
+/// Returns the packet that turns the digits and the alarm off.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+    data
+}
+
+/// Builds the data packet of the selected display mode.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
 
-            // Wait
-            sleep(update);
+    let usage = sample.cpu_usage;
+    let temp = sample.cpu_temperature(false);
 
-            // Calculate & write usage
-            let usage = self.cpu.get_usage(cpu_instant);
+    match options.mode {
+        Mode::CpuUsage => {
             data[1] = 76;
             data[3] = if usage < 100 { usage % 100 / 10 } else { 9 };
             data[4] = if usage < 100 { usage % 10 } else { 9 };
-        } else {
-            // If display mode is not usage, simply wait
-            sleep(update);
         }
-
-        // Calculate temperature
-        let temp = self.cpu.get_temp(false);
-
-        if mode == &Mode::CpuTemperature {
-            // Write temperature
+        Mode::CpuTemperature => {
             data[1] = 19;
             data[3] = if temp < 100 { temp % 100 / 10 } else { 9 };
             data[4] = if temp < 100 { temp % 10 } else { 9 };
         }
+        _ => (),
+    }
+
+    // Alarm
+    data[5] = (options.alarm && temp >= TEMP_LIMIT_C) as u8;
 
-        // Alarm
-        data[5] = (self.alarm && temp >= TEMP_LIMIT_C) as u8;
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn encode_temperature() {
+        assert_packet(&encode(&SAMPLE, &options(Mode::CpuTemperature)), &[16, 19, 0, 4, 5]);
+        // Two digits only
+        let sample = SystemSample { cpu_temp: 104.0, ..SAMPLE };
+        assert_packet(&encode(&sample, &options(Mode::CpuTemperature)), &[16, 19, 0, 9, 9]);
+    }
+
+    #[test]
+    fn encode_usage() {
+        assert_packet(&encode(&SAMPLE, &options(Mode::CpuUsage)), &[16, 76, 0, 5, 7]);
+        let sample = SystemSample { cpu_usage: 100, ..SAMPLE };
+        assert_packet(&encode(&sample, &options(Mode::CpuUsage)), &[16, 76, 0, 9, 9]);
+    }
 
-        data
+    #[test]
+    fn encode_alarm() {
+        let sample = SystemSample { cpu_temp: 90.0, ..SAMPLE };
+        let options = Options { alarm: true, ..options(Mode::CpuTemperature) };
+        assert_packet(&encode(&SAMPLE, &options), &[16, 19, 0, 4, 5]);
+        assert_packet(&encode(&sample, &options), &[16, 19, 0, 9, 0, 1]);
     }
 }
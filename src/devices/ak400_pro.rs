@@ -1,10 +1,12 @@
 //! Display module for:
 //! - AK400 DIGITAL PRO
 
-use crate::monitor::cpu::Cpu;
-use super::{device_error, Mode};
-use hidapi::{HidApi, HidDevice};
-use std::{thread::sleep, time::Duration};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{transport::Transport, keep_blank, checksum, AlarmSupport, Capabilities, Device, Mode, Options};
+use std::io;
 
 pub const DEFAULT_MODE: Mode = Mode::Auto;
 
@@ -15,90 +17,114 @@ pub const TEMP_LIMIT_C: u8 = 90;
 pub const TEMP_LIMIT_F: u8 = 194;
 
 pub struct Display {
-    cpu: Cpu,
-    update: Duration,
-    fahrenheit: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, update: Duration, fahrenheit: bool) -> Self {
-        Self {
-            cpu,
-            update,
-            fahrenheit,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[],
+            secondary: &[],
+            fahrenheit: true,
+            alarm: AlarmSupport::HardCoded,
+            rotate: false,
         }
     }
 
-    /// Original entrypoint (enumeration by VID/PID).
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
-        self.run_device(device);
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::Auto,
+                temp_limit: if self.options.fahrenheit { TEMP_LIMIT_F } else { TEMP_LIMIT_C },
+                temp_warning: if self.options.fahrenheit { TEMP_WARNING_F } else { TEMP_WARNING_C },
+            },
+            update: self.options.update,
+        }
     }
 
-    /// New entrypoint (already opened device, e.g. via --hidraw + open_path()).
-    pub fn run_device(&self, device: HidDevice) {
+    fn fields(&self) -> Fields {
+        match self.options.mode {
+            Mode::Off => Fields::NONE,
+            _ => Fields::CPU_POWER | Fields::CPU_TEMP | Fields::CPU_USAGE,
+        }
+    }
+
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
+        }
+
         // Warn once; do NOT abort on server CPUs
-        self.cpu.warn_temp();
-        self.cpu.warn_rapl();
-
-        // Base HID packet (constant part)
-        let base_data: [u8; 64] = {
-            let mut d = [0u8; 64];
-            d[0] = 16;
-            d[1] = 104;
-            d[2] = 1;
-            d[3] = 2;
-            d[4] = 11;
-            d[5] = 1;
-            d[6] = 2;
-            d[7] = 5;
-            d
-        };
+        sampler.cpu().warn_temp();
+        sampler.cpu().warn_rapl();
 
         loop {
-            // Start from base packet every iteration
-            let mut status_data = base_data;
+            device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
+        }
+    }
+
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
 
-            // CPU instant (always works)
-            let cpu_instant = self.cpu.read_instant();
+/// Returns the status packet with every reading at zero, as the display has no blank state.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=7].copy_from_slice(&[16, 104, 1, 2, 11, 1, 2, 5]);
+    data[16] = checksum(&data[1..=15]);
+    data[17] = 22;
+    data
+}
 
-            // Energy may be 0 on Xeon / server CPUs
-            let cpu_energy = self.cpu.read_energy();
+/// Builds the status packet of the display.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=7].copy_from_slice(&[16, 104, 1, 2, 11, 1, 2, 5]);
 
-            sleep(self.update);
+    // Power
+    data[8..=9].copy_from_slice(&sample.cpu_power.to_be_bytes());
 
-            // Power (safe for servers)
-            let power: u16 = if cpu_energy > 0 {
-                self.cpu.get_power(cpu_energy, self.update.as_millis() as u64)
-            } else {
-                0
-            };
+    // Temperature
+    data[10] = options.fahrenheit as u8;
+    data[11..=14].copy_from_slice(&(sample.cpu_temperature(options.fahrenheit) as f32).to_be_bytes());
 
-            let power_bytes = power.to_be_bytes();
-            status_data[8] = power_bytes[0];
-            status_data[9] = power_bytes[1];
+    // CPU usage
+    data[15] = sample.cpu_usage;
 
-            // Temperature
-            let temp = (self.cpu.get_temp(self.fahrenheit) as f32).to_be_bytes();
-            status_data[10] = if self.fahrenheit { 1 } else { 0 };
-            status_data[11] = temp[0];
-            status_data[12] = temp[1];
-            status_data[13] = temp[2];
-            status_data[14] = temp[3];
+    // Checksum & terminator
+    data[16] = checksum(&data[1..=15]);
+    data[17] = 22;
 
-            // CPU usage
-            status_data[15] = self.cpu.get_usage(cpu_instant);
+    data
+}
 
-            // Checksum & terminator
-            let checksum: u16 = status_data[1..=15]
-                .iter()
-                .map(|&x| x as u16)
-                .sum();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
 
-            status_data[16] = (checksum % 256) as u8;
-            status_data[17] = 22;
+    #[test]
+    fn encode_celsius() {
+        let packet = encode(&SAMPLE, &options(Mode::Auto));
+        // Power 300 W, 45.0 °C, 57%
+        assert_packet(&packet, &[16, 104, 1, 2, 11, 1, 2, 5, 1, 44, 0, 66, 52, 0, 0, 57, 90, 22]);
+    }
 
-            device.write(&status_data).unwrap();
-        }
+    #[test]
+    fn encode_fahrenheit() {
+        let packet = encode(&SAMPLE, &Options { fahrenheit: true, ..options(Mode::Auto) });
+        // 114.0 °F
+        assert_packet(&packet, &[16, 104, 1, 2, 11, 1, 2, 5, 1, 44, 1, 66, 228, 0, 0, 57, 11, 22]);
     }
 }
@@ -2,10 +2,12 @@
 //! - AK500 DIGITAL PRO
 //! - AK620 DIGITAL PRO
 
-use crate::monitor::cpu::Cpu;
-use super::{device_error, Mode};
-use hidapi::HidApi;
-use std::{thread::sleep, time::Duration};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{transport::Transport, keep_blank, checksum, AlarmSupport, Capabilities, Device, Mode, Options};
+use std::io;
 
 pub const DEFAULT_MODE: Mode = Mode::Auto;
 // The temperature limits are hard-coded in the device
@@ -15,79 +17,118 @@ pub const TEMP_LIMIT_C: u8 = 90;
 pub const TEMP_LIMIT_F: u8 = 194;
 
 pub struct Display {
-    cpu: Cpu,
-    update: Duration,
-    fahrenheit: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, update: Duration, fahrenheit: bool) -> Self {
-        Display {
-            cpu,
-            update,
-            fahrenheit,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[],
+            secondary: &[],
+            fahrenheit: true,
+            alarm: AlarmSupport::HardCoded,
+            rotate: false,
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::Auto,
+                temp_limit: if self.options.fahrenheit { TEMP_LIMIT_F } else { TEMP_LIMIT_C },
+                temp_warning: if self.options.fahrenheit { TEMP_WARNING_F } else { TEMP_WARNING_C },
+            },
+            update: self.options.update,
+        }
+    }
+
+    fn fields(&self) -> Fields {
+        match self.options.mode {
+            Mode::Off => Fields::NONE,
+            _ => Fields::CPU,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
+        }
 
         // Display warning if a required module is missing
-        self.cpu.warn_temp();
-        self.cpu.warn_rapl();
-
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
-        data[1] = 104;
-        data[2] = 1;
-        data[3] = 4;
-        data[4] = 13;
-        data[5] = 1;
-        data[6] = 2;
-        data[7] = 8;
+        sampler.cpu().warn_temp();
+        sampler.cpu().warn_rapl();
 
         // Display loop
         loop {
-            // Initialize the packet
-            let mut status_data = data.clone();
-
-            // Read CPU utilization & energy consumption
-            let cpu_instant = self.cpu.read_instant();
-            let cpu_energy = self.cpu.read_energy();
-
-            // Wait
-            sleep(self.update);
-
-            // ----- Write data to the package -----
-            // Power consumption
-            let power = (self.cpu.get_power(cpu_energy, self.update.as_millis() as u64)).to_be_bytes();
-            status_data[8] = power[0];
-            status_data[9] = power[1];
-
-            // Temperature
-            let temp = (self.cpu.get_temp(self.fahrenheit) as f32).to_be_bytes();
-            status_data[10] = if self.fahrenheit { 1 } else { 0 };
-            status_data[11] = temp[0];
-            status_data[12] = temp[1];
-            status_data[13] = temp[2];
-            status_data[14] = temp[3];
-
-            // Utilization
-            status_data[15] = self.cpu.get_usage(cpu_instant);
-
-            // Frequency
-            let frequency = (self.cpu.get_frequency()).to_be_bytes();
-            status_data[16] = frequency[0];
-            status_data[17] = frequency[1];
-
-            // Checksum & termination byte
-            let checksum: u16 = status_data[1..=17].iter().map(|&x| x as u16).sum();
-            status_data[18] = (checksum % 256) as u8;
-            status_data[19] = 22;
-
-            device.write(&status_data).unwrap();
+            device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
         }
     }
+
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
+
+/// Returns the status packet with every reading at zero, as the display has no blank state.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=7].copy_from_slice(&[16, 104, 1, 4, 13, 1, 2, 8]);
+    data[18] = checksum(&data[1..=17]);
+    data[19] = 22;
+    data
+}
+
+/// Builds the status packet of the display.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=7].copy_from_slice(&[16, 104, 1, 4, 13, 1, 2, 8]);
+
+    // Power consumption
+    data[8..=9].copy_from_slice(&sample.cpu_power.to_be_bytes());
+
+    // Temperature
+    data[10] = options.fahrenheit as u8;
+    data[11..=14].copy_from_slice(&(sample.cpu_temperature(options.fahrenheit) as f32).to_be_bytes());
+
+    // Utilization
+    data[15] = sample.cpu_usage;
+
+    // Frequency
+    data[16..=17].copy_from_slice(&sample.cpu_frequency.to_be_bytes());
+
+    // Checksum & termination byte
+    data[18] = checksum(&data[1..=17]);
+    data[19] = 22;
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn encode_celsius() {
+        let packet = encode(&SAMPLE, &options(Mode::Auto));
+        // Power 300 W, 45.0 °C, 57%, 4250 MHz
+        assert_packet(&packet, &[16, 104, 1, 4, 13, 1, 2, 8, 1, 44, 0, 66, 52, 0, 0, 57, 16, 154, 11, 22]);
+    }
+
+    #[test]
+    fn encode_fahrenheit() {
+        let packet = encode(&SAMPLE, &Options { fahrenheit: true, ..options(Mode::Auto) });
+        // 114.0 °F
+        assert_packet(&packet, &[16, 104, 1, 4, 13, 1, 2, 8, 1, 44, 1, 66, 228, 0, 0, 57, 16, 154, 188, 22]);
+    }
 }
@@ -1,85 +1,123 @@
 //! Display module for:
 //! - CH510 MESH DIGITAL
 
-use crate::monitor::{cpu::Cpu, gpu::Gpu};
-use super::{device_error, Mode};
-use hidapi::HidApi;
-use std::{thread::sleep, time::Duration};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{transport::Transport, keep_blank, AlarmSupport, Capabilities, Device, Mode, Options};
+use std::io;
 
 pub const DEFAULT_MODE: Mode = Mode::Cpu;
 
 pub struct Display {
-    cpu: Cpu,
-    gpu: Gpu,
-    pub mode: Mode,
-    update: Duration,
-    fahrenheit: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, gpu: Gpu, mode: &Mode, update: Duration, fahrenheit: bool) -> Self {
-        // Verify the display mode
-        let mode = match mode {
-            Mode::Default => DEFAULT_MODE,
-            Mode::Cpu => Mode::Cpu,
-            Mode::Gpu => Mode::Gpu,
-            _ => mode.support_error(),
-        };
-
-        Display {
-            cpu,
-            gpu,
-            mode,
-            update,
-            fahrenheit,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[Mode::Cpu, Mode::Gpu],
+            secondary: &[],
+            fahrenheit: true,
+            alarm: AlarmSupport::NotSupported,
+            rotate: false,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::NotSupported,
+                temp_limit: 0,
+                temp_warning: 0,
+            },
+            update: self.options.update,
+        }
+    }
+
+    fn fields(&self) -> Fields {
+        match self.options.mode {
+            Mode::Cpu => Fields::CPU_USAGE | Fields::CPU_TEMP,
+            Mode::Gpu => Fields::GPU_USAGE | Fields::GPU_TEMP,
+            _ => Fields::NONE,
+        }
+    }
+
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, blank_message().as_bytes());
+        }
 
         // Display warning if a required module is missing
-        match self.mode {
-            Mode::Cpu => self.cpu.warn_temp(),
-            Mode::Gpu => self.gpu.warn_missing(),
+        match self.options.mode {
+            Mode::Cpu => sampler.cpu().warn_temp(),
+            Mode::Gpu => sampler.gpu().warn_missing(),
             _ => (),
         }
 
-        // Get temperature unit
-        let unit = if self.fahrenheit { "F" } else { "C" };
-
         // Display loop
         loop {
-            let message = match self.mode {
-                Mode::Cpu => {
-                    // Get CPU instant & wait
-                    let cpu_instant = self.cpu.read_instant();
-                    sleep(self.update);
-
-                    // Return the message
-                    format!(
-                        "HLXDATA({},{},0,0,{})\r\n",
-                        self.cpu.get_usage(cpu_instant),
-                        self.cpu.get_temp(self.fahrenheit),
-                        unit,
-                    )
-                }
-                Mode::Gpu => {
-                    // Wait
-                    sleep(self.update);
-
-                    // Return the message
-                    format!(
-                        "HLXDATA({},{},0,0,{})\r\n",
-                        self.gpu.get_usage(),
-                        self.gpu.get_temp(self.fahrenheit),
-                        unit,
-                    )
-                }
-                _ => "".to_owned(),
-            };
-            device.write(message.as_bytes()).unwrap();
+            device.write(encode(&sampler.sample(self.options.update), &self.options).as_bytes())?;
         }
     }
+
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(blank_message().as_bytes())
+    }
+}
+
+/// Returns the status message with every reading at zero, as the display has no blank state.
+pub fn blank_message() -> &'static str {
+    "HLXDATA(0,0,0,0,C)\r\n"
+}
+
+/// Builds the status message of the selected display mode.
+pub fn encode(sample: &SystemSample, options: &Options) -> String {
+    // Get temperature unit
+    let unit = if options.fahrenheit { "F" } else { "C" };
+
+    match options.mode {
+        Mode::Cpu => format!(
+            "HLXDATA({},{},0,0,{})\r\n",
+            sample.cpu_usage,
+            sample.cpu_temperature(options.fahrenheit),
+            unit,
+        ),
+        Mode::Gpu => format!(
+            "HLXDATA({},{},0,0,{})\r\n",
+            sample.gpu_usage,
+            sample.gpu_temperature(options.fahrenheit),
+            unit,
+        ),
+        _ => "".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{options, SAMPLE};
+
+    #[test]
+    fn encode_examples() {
+        // The examples of the mapping table
+        let sample = SystemSample { cpu_usage: 30, cpu_temp: 36.0, ..SAMPLE };
+        assert_eq!(encode(&sample, &options(Mode::Cpu)), "HLXDATA(30,36,0,0,C)\r\n");
+        let sample = SystemSample { cpu_usage: 100, cpu_temp: 80.0, ..SAMPLE };
+        assert_eq!(encode(&sample, &options(Mode::Cpu)), "HLXDATA(100,80,0,0,C)\r\n");
+        let sample = SystemSample { gpu_usage: 100, gpu_temp: 93.4, ..SAMPLE };
+        let options = Options { fahrenheit: true, ..options(Mode::Gpu) };
+        assert_eq!(encode(&sample, &options), "HLXDATA(100,200,0,0,F)\r\n");
+    }
 }
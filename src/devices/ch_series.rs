@@ -3,95 +3,128 @@
 //! - CH560 DIGITAL
 //! - MORPHEUS
 
-use crate::monitor::{cpu::Cpu, gpu::Gpu};
-use super::{device_error, Mode, AUTO_MODE_INTERVAL};
-use hidapi::HidApi;
-use std::{thread::sleep, time::{Duration, Instant}};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{
+    transport::Transport, keep_blank, digits, status_bar, AlarmSupport, Capabilities,
+    Device, Mode, ModeError, Options, AUTO_MODE_INTERVAL,
+};
+use std::{io, time::Instant};
 
 pub const DEFAULT_MODE: Mode = Mode::CpuTemperature;
 
 pub struct Display {
-    cpu: Cpu,
-    gpu: Gpu,
-    pub mode: Mode,
-    pub secondary: Mode,
-    update: Duration,
-    fahrenheit: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, gpu: Gpu, mode: &Mode, secondary: &Mode, update: Duration, fahrenheit: bool) -> Self {
-        // Verify the display mode
-        let mode = match mode {
-            Mode::Default => DEFAULT_MODE,
-            Mode::Auto => Mode::Auto,
-            Mode::CpuTemperature => Mode::CpuTemperature,
-            Mode::CpuUsage => Mode::CpuUsage,
-            _ => mode.support_error(),
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[Mode::Auto, Mode::CpuTemperature, Mode::CpuUsage],
+            secondary: &[Mode::GpuTemperature, Mode::GpuUsage],
+            fahrenheit: true,
+            alarm: AlarmSupport::NotSupported,
+            rotate: false,
+        }
+    }
+
+    fn verify(options: &Options) -> Result<Options, ModeError> {
+        let capabilities = Self::capabilities();
+        let mut options = *options;
+
+        options.mode = match options.mode {
+            Mode::Default => capabilities.default_mode,
+            mode if mode == Mode::Off || capabilities.modes.contains(&mode) => mode,
+            mode => return Err(ModeError::Primary(mode)),
         };
 
-        let secondary = match secondary {
-            Mode::Default => match mode {
+        // The secondary display follows the main display by default
+        options.secondary = match options.secondary {
+            Mode::Default => match options.mode {
                 Mode::CpuTemperature => Mode::GpuTemperature,
                 Mode::CpuUsage => Mode::GpuUsage,
                 _ => Mode::Auto,
             },
-            Mode::GpuTemperature => Mode::GpuTemperature,
-            Mode::GpuUsage => Mode::GpuUsage,
-            _ => secondary.support_error_secondary(),
+            mode if capabilities.secondary.contains(&mode) => mode,
+            mode => return Err(ModeError::Secondary(mode)),
         };
 
-        Display {
-            cpu,
-            gpu,
-            mode,
-            secondary,
-            update,
-            fahrenheit,
+        Ok(options)
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: Some(self.options.secondary),
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::NotSupported,
+                temp_limit: 0,
+                temp_warning: 0,
+            },
+            update: self.options.update,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn fields(&self) -> Fields {
+        if self.options.mode == Mode::Off {
+            return Fields::NONE;
+        }
+        // The status bars always show the utilization
+        let mut fields = Fields::CPU_USAGE | Fields::GPU_USAGE;
+        let temperature = matches!(self.options.mode, Mode::CpuTemperature | Mode::Auto);
+        if temperature {
+            fields = fields | Fields::CPU_TEMP;
+        }
+        if self.options.secondary == Mode::GpuTemperature || (self.options.secondary == Mode::Auto && temperature) {
+            fields = fields | Fields::GPU_TEMP;
+        }
+        fields
+    }
 
-        // Display warning if a required module is missing
-        if matches!(self.mode, Mode::CpuTemperature) {
-            self.cpu.warn_temp();
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
         }
-        self.gpu.warn_missing();
 
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
+        // Display warning if a required module is missing
+        if matches!(self.options.mode, Mode::CpuTemperature) {
+            sampler.cpu().warn_temp();
+        }
+        sampler.gpu().warn_missing();
 
         // Init sequence
-        {
-            let mut init_data = data.clone();
-            init_data[1] = 170;
-            device.write(&init_data).unwrap();
-        }
+        device.write(&init_packet())?;
 
         // Display loop
-        match self.mode {
+        match self.options.mode {
             Mode::Auto => {
-                let mut initial_update = self.update;
-                let mut mode = Mode::CpuTemperature;
+                let mut initial_update = self.options.update;
+                let mut options = Options { mode: Mode::CpuTemperature, ..self.options };
                 loop {
                     // Initial update
-                    device.write(&self.status_message(&data, &mode, initial_update)).unwrap();
+                    device.write(&encode(&sampler.sample(initial_update), &options))?;
 
                     // Update until timeout
                     let timeout = Instant::now() + AUTO_MODE_INTERVAL;
-                    while Instant::now() + self.update < timeout {
-                        device.write(&self.status_message(&data, &mode, self.update)).unwrap();
+                    while Instant::now() + self.options.update < timeout {
+                        device.write(&encode(&sampler.sample(self.options.update), &options))?;
                     }
 
                     // Make the next initial update faster to fit the timeframe
                     initial_update = timeout - Instant::now();
 
                     // Switch to the next display mode
-                    mode = match mode {
+                    options.mode = match options.mode {
                         Mode::CpuTemperature => Mode::CpuUsage,
                         Mode::CpuUsage => Mode::CpuTemperature,
                         _ => DEFAULT_MODE,
@@ -99,83 +132,107 @@ impl Display {
                 }
             }
             _ => loop {
-                device.write(&self.status_message(&data, &self.mode, self.update)).unwrap();
+                device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
             }
         }
     }
 
-    /// Reads the CPU status information and returns the data packet.
-    fn status_message(&self, inital_data: &[u8; 64], mode: &Mode, update: Duration) -> [u8; 64] {
-        // Clone the data packet
-        let mut data = inital_data.clone();
-
-        // Read CPU utilization
-        let cpu_instant = self.cpu.read_instant();
-
-        // Wait
-        sleep(update);
-
-        // Calculate CPU & GPU usage
-        let cpu_usage = self.cpu.get_usage(cpu_instant);
-        let gpu_usage = self.gpu.get_usage();
-
-        // Main display
-        match mode {
-            Mode::CpuTemperature => {
-                // CPU
-                let unit = if self.fahrenheit { 35 } else { 19 };
-                let cpu_temp = self.cpu.get_temp(self.fahrenheit);
-                data[1] = unit;
-                data[3] = cpu_temp / 100;
-                data[4] = cpu_temp % 100 / 10;
-                data[5] = cpu_temp % 10;
-                // GPU
-                if self.secondary == Mode::Auto {
-                    let gpu_temp = self.gpu.get_temp(self.fahrenheit);
-                    data[6] = unit;
-                    data[8] = gpu_temp / 100;
-                    data[9] = gpu_temp % 100 / 10;
-                    data[10] = gpu_temp % 10;
-                }
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
+
+/// Returns the packet that starts the status bar animation.
+pub fn init_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+    data[1] = 170;
+    data
+}
+
+/// Returns the packet that turns the digits, the status bars, and the alarm off.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+    data
+}
+
+/// Builds the data packet of the selected display modes.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+
+    let unit = if options.fahrenheit { 35 } else { 19 };
+    let cpu_temp = sample.cpu_temperature(options.fahrenheit) as u16;
+    let gpu_temp = sample.gpu_temperature(options.fahrenheit) as u16;
+
+    // Main display
+    match options.mode {
+        Mode::CpuTemperature => {
+            // CPU
+            data[1] = unit;
+            data[3..=5].copy_from_slice(&digits(cpu_temp));
+            // GPU
+            if options.secondary == Mode::Auto {
+                data[6] = unit;
+                data[8..=10].copy_from_slice(&digits(gpu_temp));
             }
-            Mode::CpuUsage => {
-                // CPU
-                data[1] = 76;
-                data[3] = cpu_usage / 100;
-                data[4] = cpu_usage % 100 / 10;
-                data[5] = cpu_usage % 10;
-                // GPU
-                if self.secondary == Mode::Auto {
-                    data[6] = 76;
-                    data[8] = gpu_usage / 100;
-                    data[9] = gpu_usage % 100 / 10;
-                    data[10] = gpu_usage % 10;
-                }
+        }
+        Mode::CpuUsage => {
+            // CPU
+            data[1] = 76;
+            data[3..=5].copy_from_slice(&digits(sample.cpu_usage as u16));
+            // GPU
+            if options.secondary == Mode::Auto {
+                data[6] = 76;
+                data[8..=10].copy_from_slice(&digits(sample.gpu_usage as u16));
             }
-            _ => (),
         }
-        if data[6] == 0 {
-            match self.secondary {
-                Mode::GpuTemperature => {
-                    let gpu_temp = self.gpu.get_temp(self.fahrenheit);
-                    data[6] = if self.fahrenheit { 35 } else { 19 };
-                    data[8] = gpu_temp / 100;
-                    data[9] = gpu_temp % 100 / 10;
-                    data[10] = gpu_temp % 10;
-                }
-                Mode::GpuUsage => {
-                    data[6] = 76;
-                    data[8] = gpu_usage / 100;
-                    data[9] = gpu_usage % 100 / 10;
-                    data[10] = gpu_usage % 10;
-                }
-                _ => (),
+        _ => (),
+    }
+    if data[6] == 0 {
+        match options.secondary {
+            Mode::GpuTemperature => {
+                data[6] = unit;
+                data[8..=10].copy_from_slice(&digits(gpu_temp));
             }
+            Mode::GpuUsage => {
+                data[6] = 76;
+                data[8..=10].copy_from_slice(&digits(sample.gpu_usage as u16));
+            }
+            _ => (),
         }
-        // Status bar
-        data[2] = if cpu_usage < 15 { 1 } else { (cpu_usage as f32 / 10.0).round() as u8 };
-        data[7] = if gpu_usage < 15 { 1 } else { (gpu_usage as f32 / 10.0).round() as u8 };
+    }
+    // Status bar
+    data[2] = status_bar(sample.cpu_usage);
+    data[7] = status_bar(sample.gpu_usage);
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn encode_temperature() {
+        // CPU 45 °C, GPU 62 °C
+        let options = Options { secondary: Mode::Auto, ..options(Mode::CpuTemperature) };
+        assert_packet(&encode(&SAMPLE, &options), &[16, 19, 6, 0, 4, 5, 19, 9, 0, 6, 2]);
+
+        // CPU 114 °F, GPU 144 °F
+        let options = Options { fahrenheit: true, secondary: Mode::GpuTemperature, ..options };
+        assert_packet(&encode(&SAMPLE, &options), &[16, 35, 6, 1, 1, 4, 35, 9, 1, 4, 4]);
+    }
+
+    #[test]
+    fn encode_usage() {
+        let options = Options { secondary: Mode::GpuUsage, ..options(Mode::CpuUsage) };
+        assert_packet(&encode(&SAMPLE, &options), &[16, 76, 6, 0, 5, 7, 76, 9, 0, 8, 8]);
 
-        data
+        // Without a secondary mode, the GPU only shows its status bar
+        let options = Options { secondary: Mode::Default, ..options };
+        assert_packet(&encode(&SAMPLE, &options), &[16, 76, 6, 0, 5, 7, 0, 9]);
     }
 }
@@ -3,49 +3,72 @@
 //! - CH270 DIGITAL
 //! - CH690 DIGITAL
 
-use crate::{monitor::{cpu::Cpu, gpu::Gpu}, warning};
-use super::{device_error, Mode, AUTO_MODE_INTERVAL};
-use hidapi::HidApi;
-use std::{thread::sleep, time::{Duration, Instant}};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+    warning,
+};
+use super::{
+    transport::Transport, keep_blank, checksum, AlarmSupport, Capabilities, Device, Mode, Options,
+    AUTO_MODE_INTERVAL,
+};
+use std::{io, time::Instant};
 
 pub const DEFAULT_MODE: Mode = Mode::CpuFrequency;
 
 pub struct Display {
-    cpu: Cpu,
-    gpu: Gpu,
-    pub mode: Mode,
-    update: Duration,
-    fahrenheit: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, gpu: Gpu, mode: &Mode, update: Duration, fahrenheit: bool) -> Self {
-        // Verify the display mode
-        let mode = match mode {
-            Mode::Default => DEFAULT_MODE,
-            Mode::Auto => Mode::Auto,
-            Mode::CpuFrequency => Mode::CpuFrequency,
-            Mode::CpuFan => Mode::CpuFan,
-            Mode::Gpu => Mode::Gpu,
-            Mode::Psu => Mode::Psu,
-            _ => mode.support_error(),
-        };
-
-        Display {
-            cpu,
-            gpu,
-            mode,
-            update,
-            fahrenheit,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[Mode::Auto, Mode::CpuFrequency, Mode::CpuFan, Mode::Gpu, Mode::Psu],
+            secondary: &[],
+            fahrenheit: true,
+            alarm: AlarmSupport::NotSupported,
+            rotate: false,
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::NotSupported,
+                temp_limit: 0,
+                temp_warning: 0,
+            },
+            update: self.options.update,
+        }
+    }
+
+    fn fields(&self) -> Fields {
+        match self.options.mode {
+            Mode::CpuFrequency => Fields::CPU,
+            Mode::CpuFan => Fields::CPU_POWER | Fields::CPU_TEMP | Fields::CPU_USAGE,
+            Mode::Gpu => Fields::GPU,
+            Mode::Auto => Fields::CPU | Fields::GPU,
+            _ => Fields::NONE,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
+        }
 
         // Display warning to address limitated display modes
-        match self.mode {
+        match self.options.mode {
             Mode::CpuFan => { warning!("CPU fan speed monitoring is not yet supported"); }
             Mode::Psu => { warning!("PSU monitoring is not yet supported"); }
             Mode::Auto => { warning!("Display mode \"auto\" only cycles between fully supported modes"); }
@@ -53,44 +76,34 @@ impl Display {
         }
 
         // Display warning if a required module is missing
-        if matches!(self.mode, Mode::CpuFrequency | Mode::CpuFan | Mode::Auto) {
-            self.cpu.warn_temp();
-            self.cpu.warn_rapl();
+        if matches!(self.options.mode, Mode::CpuFrequency | Mode::CpuFan | Mode::Auto) {
+            sampler.cpu().warn_temp();
+            sampler.cpu().warn_rapl();
         }
-        if matches!(self.mode, Mode::Gpu | Mode::Auto) {
-            self.gpu.warn_missing();
+        if matches!(self.options.mode, Mode::Gpu | Mode::Auto) {
+            sampler.gpu().warn_missing();
         }
 
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
-        data[1] = 104;
-        data[2] = 1;
-        data[3] = 6;
-        data[4] = 35;
-        data[5] = 1;
-        data[9] = if self.fahrenheit { 1 } else { 0 };
-
         // Display loop
-        match self.mode {
+        match self.options.mode {
             Mode::Auto => {
-                let mut initial_update = self.update;
-                let mut mode = Mode::CpuFrequency;
+                let mut initial_update = self.options.update;
+                let mut options = Options { mode: Mode::CpuFrequency, ..self.options };
                 loop {
                     // Initial update
-                    device.write(&self.status_message(&data, &mode, initial_update)).unwrap();
+                    device.write(&encode(&sampler.sample(initial_update), &options))?;
 
                     // Update until timeout
                     let timeout = Instant::now() + AUTO_MODE_INTERVAL;
-                    while Instant::now() + self.update < timeout {
-                        device.write(&self.status_message(&data, &mode, self.update)).unwrap();
+                    while Instant::now() + self.options.update < timeout {
+                        device.write(&encode(&sampler.sample(self.options.update), &options))?;
                     }
 
                     // Make the next initial update faster to fit the timeframe
                     initial_update = timeout - Instant::now();
 
                     // Switch to the next display mode
-                    mode = match mode {
+                    options.mode = match options.mode {
                         Mode::CpuFrequency => Mode::Gpu,
                         Mode::Gpu => Mode::CpuFrequency,
                         _ => DEFAULT_MODE,
@@ -98,93 +111,107 @@ impl Display {
                 }
             }
             _ => loop {
-                device.write(&self.status_message(&data, &self.mode, self.update)).unwrap();
+                device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
             }
         }
     }
 
-    /// Reads the system status information and returns the data packet.
-    fn status_message(&self, inital_data: &[u8; 64], mode: &Mode, update: Duration) -> [u8; 64] {
-        // Clone the data packet
-        let mut data = inital_data.clone();
-
-        // Set the display mode
-        data[6] = match mode {
-            Mode::CpuFrequency => 2,
-            Mode::CpuFan => 3,
-            Mode::Gpu => 4,
-            Mode::Psu => 5,
-            _ => 0,
-        };
-
-        // Main display
-        match mode {
-            Mode::CpuFrequency | Mode::CpuFan => {
-                // Read CPU utilization & energy consumption
-                let cpu_instant = self.cpu.read_instant();
-                let cpu_energy = self.cpu.read_energy();
-
-                // Wait
-                sleep(update);
-
-                // Power consumption
-                let power = (self.cpu.get_power(cpu_energy, update.as_millis() as u64)).to_be_bytes();
-                data[7] = power[0];
-                data[8] = power[1];
-
-                // Temperature
-                let temp = (self.cpu.get_temp(self.fahrenheit) as f32).to_be_bytes();
-                data[10] = temp[0];
-                data[11] = temp[1];
-                data[12] = temp[2];
-                data[13] = temp[3];
-
-                // Utilization
-                data[14] = self.cpu.get_usage(cpu_instant);
-
-                // Frequency
-                if matches!(mode, Mode::CpuFrequency) {
-                    let frequency = (self.cpu.get_frequency()).to_be_bytes();
-                    data[15] = frequency[0];
-                    data[16] = frequency[1];
-                }
-            }
-            Mode::Gpu => {
-                // Wait
-                sleep(update);
-
-                // Power consumption
-                let power = (self.gpu.get_power()).to_be_bytes();
-                data[19] = power[0];
-                data[20] = power[1];
-
-                // Temperature
-                let temp = (self.gpu.get_temp(self.fahrenheit) as f32).to_be_bytes();
-                data[21] = temp[0];
-                data[22] = temp[1];
-                data[23] = temp[2];
-                data[24] = temp[3];
-
-                // Utilization
-                data[25] = self.gpu.get_usage();
-
-                // Frequency
-                let frequency = (self.gpu.get_frequency()).to_be_bytes();
-                data[26] = frequency[0];
-                data[27] = frequency[1];
-            }
-            Mode::Psu => {
-                // Wait
-                sleep(update);
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
+
+/// Returns the status packet with every reading at zero, as the display has no blank state.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=5].copy_from_slice(&[16, 104, 1, 6, 35, 1]);
+    data[40] = checksum(&data[1..=39]);
+    data[41] = 22;
+    data
+}
+
+/// Builds the data packet of the selected display mode.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=5].copy_from_slice(&[16, 104, 1, 6, 35, 1]);
+    data[9] = options.fahrenheit as u8;
+
+    // Set the display mode
+    data[6] = match options.mode {
+        Mode::CpuFrequency => 2,
+        Mode::CpuFan => 3,
+        Mode::Gpu => 4,
+        Mode::Psu => 5,
+        _ => 0,
+    };
+
+    // Main display
+    match options.mode {
+        Mode::CpuFrequency | Mode::CpuFan => {
+            // Power consumption
+            data[7..=8].copy_from_slice(&sample.cpu_power.to_be_bytes());
+
+            // Temperature
+            data[10..=13].copy_from_slice(&(sample.cpu_temperature(options.fahrenheit) as f32).to_be_bytes());
+
+            // Utilization
+            data[14] = sample.cpu_usage;
+
+            // Frequency
+            if matches!(options.mode, Mode::CpuFrequency) {
+                data[15..=16].copy_from_slice(&sample.cpu_frequency.to_be_bytes());
             }
-            _ => (),
         }
+        Mode::Gpu => {
+            // Power consumption
+            data[19..=20].copy_from_slice(&sample.gpu_power.to_be_bytes());
+
+            // Temperature
+            data[21..=24].copy_from_slice(&(sample.gpu_temperature(options.fahrenheit) as f32).to_be_bytes());
+
+            // Utilization
+            data[25] = sample.gpu_usage;
+
+            // Frequency
+            data[26..=27].copy_from_slice(&sample.gpu_frequency.to_be_bytes());
+        }
+        _ => (),
+    }
+
+    // Checksum & termination byte
+    data[40] = checksum(&data[1..=39]);
+    data[41] = 22;
 
-        // Checksum & termination byte
-        let checksum: u16 = data[1..=39].iter().map(|&x| x as u16).sum();
-        data[40] = (checksum % 256) as u8;
-        data[41] = 22;
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn encode_cpu() {
+        // Power 300 W, 45.0 °C, 57%, 4250 MHz
+        let mut expected = [0; 42];
+        expected[..17].copy_from_slice(&[16, 104, 1, 6, 35, 1, 2, 1, 44, 0, 66, 52, 0, 0, 57, 16, 154]);
+        expected[40..].copy_from_slice(&[27, 22]);
+        assert_packet(&encode(&SAMPLE, &options(Mode::CpuFrequency)), &expected);
+
+        // The fan speed is not sent
+        let mut expected = [0; 42];
+        expected[..15].copy_from_slice(&[16, 104, 1, 6, 35, 1, 3, 1, 44, 0, 66, 52, 0, 0, 57]);
+        expected[40..].copy_from_slice(&[114, 22]);
+        assert_packet(&encode(&SAMPLE, &options(Mode::CpuFan)), &expected);
+    }
 
-        data
+    #[test]
+    fn encode_gpu() {
+        // Power 215 W, 144.0 °F, 88%, 2100 MHz
+        let mut expected = [0; 42];
+        expected[..10].copy_from_slice(&[16, 104, 1, 6, 35, 1, 4, 0, 0, 1]);
+        expected[19..28].copy_from_slice(&[0, 215, 67, 16, 0, 0, 88, 8, 52]);
+        expected[40..].copy_from_slice(&[86, 22]);
+        assert_packet(&encode(&SAMPLE, &Options { fahrenheit: true, ..options(Mode::Gpu) }), &expected);
     }
 }
@@ -0,0 +1,313 @@
+//! Changing the display module of a running display loop, without closing the device.
+
+use super::{transport::Transport, Device, Family, Options};
+use crate::utils::lock::Lock;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Running display of a device, whose display module can be replaced by another thread.
+pub struct Slot {
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub family: Family,
+    /// Options the current display module was created from.
+    options: Mutex<Options>,
+    replacement: Mutex<Option<Box<dyn Device + Send>>>,
+    stopped: AtomicBool,
+    /// Blank the display once the loop stopped.
+    blank: AtomicBool,
+    /// A packet was written to the device.
+    written: AtomicBool,
+    /// Start of the packet being written.
+    writing: Mutex<Option<Instant>>,
+    /// Last time the display loop showed it is running, at every packet and while it waits to reconnect.
+    beat: Mutex<Instant>,
+    /// The display loop ended with a panic.
+    panicked: AtomicBool,
+    /// Lock of the device, held until the display loop returns.
+    lock: Mutex<Option<Lock>>,
+}
+
+impl Slot {
+    pub fn new(pid: u16, serial: Option<String>, family: Family, options: Options) -> Self {
+        Slot {
+            pid,
+            serial,
+            family,
+            options: Mutex::new(options),
+            replacement: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            blank: AtomicBool::new(false),
+            written: AtomicBool::new(false),
+            writing: Mutex::new(None),
+            beat: Mutex::new(Instant::now()),
+            panicked: AtomicBool::new(false),
+            lock: Mutex::new(None),
+        }
+    }
+
+    pub fn options(&self) -> Options {
+        *self.options.lock().unwrap()
+    }
+
+    /// Returns the options with the default modes resolved, the way the display module uses them.
+    pub fn resolved_options(&self) -> Options {
+        let options = self.options();
+        self.family.verify(&options).unwrap_or(options)
+    }
+
+    /// Makes the display loop restart with the new display module.
+    pub fn replace(&self, display: Box<dyn Device + Send>, options: Options) {
+        *self.replacement.lock().unwrap() = Some(display);
+        *self.options.lock().unwrap() = options;
+    }
+
+    /// Returns the display module waiting to replace the running one.
+    pub fn take_replacement(&self) -> Option<Box<dyn Device + Send>> {
+        self.replacement.lock().unwrap().take()
+    }
+
+    /// Makes the display loop return for good, blanking the display first if requested.
+    ///
+    /// Without blanking, the device is not written to anymore, so its lock is released at once.
+    /// A device removed and plugged in again can then be opened before the loop returned.
+    pub fn stop(&self, blank: bool) {
+        self.blank.store(blank, Ordering::Relaxed);
+        self.stopped.store(true, Ordering::Relaxed);
+        if !blank {
+            self.unlock();
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if the display has to be blanked after the loop stopped.
+    pub fn blanks(&self) -> bool {
+        self.blank.load(Ordering::Relaxed)
+    }
+
+    /// Keeps the lock of the device until `unlock` is called.
+    pub fn hold(&self, lock: Lock) {
+        *self.lock.lock().unwrap() = Some(lock);
+    }
+
+    /// Returns `true` if the slot holds the lock with the key.
+    pub fn holds(&self, key: &str) -> bool {
+        self.lock.lock().unwrap().as_ref().is_some_and(|lock| lock.key == key)
+    }
+
+    /// Releases the lock of the device, so another instance can drive it.
+    pub fn unlock(&self) {
+        *self.lock.lock().unwrap() = None;
+    }
+
+    /// Returns `true` once the first packet was written to the device.
+    pub fn is_ready(&self) -> bool {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Returns how long the packet being written has been blocking the display loop.
+    pub fn blocked_for(&self) -> Option<Duration> {
+        self.writing.lock().unwrap().map(|start| start.elapsed())
+    }
+
+    /// Records that the display loop is running.
+    pub fn beat(&self) {
+        *self.beat.lock().unwrap() = Instant::now();
+    }
+
+    /// Returns the time since the display loop last showed it is running.
+    pub fn since_beat(&self) -> Duration {
+        self.beat.lock().unwrap().elapsed()
+    }
+
+    /// Returns why the display loop is not running, if it panicked or did not beat within the limit.
+    pub fn stalled(&self, limit: Duration) -> Option<&'static str> {
+        if self.has_panicked() {
+            Some("stopped after an error")
+        } else if self.since_beat() < limit {
+            None
+        } else if self.blocked_for().is_some() {
+            Some("blocked in a write")
+        } else {
+            Some("stuck")
+        }
+    }
+
+    /// Marks the display loop as ended by a panic, and releases the device.
+    pub fn fail(&self) {
+        self.panicked.store(true, Ordering::Relaxed);
+        self.unlock();
+    }
+
+    pub fn has_panicked(&self) -> bool {
+        self.panicked.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns `true` once every running display wrote its first packet.
+///
+/// Without any running display, only when the program is `waiting` for devices to be plugged in.
+pub fn all_ready(slots: &[Arc<Slot>], waiting: bool) -> bool {
+    if slots.is_empty() {
+        waiting
+    } else {
+        slots.iter().all(|slot| slot.is_ready())
+    }
+}
+
+/// Transport that stops accepting packets while a replacement is waiting or the slot is stopped,
+/// so the display loop returns.
+pub struct Interruptible<'a> {
+    pub device: Box<dyn Transport>,
+    slot: &'a Slot,
+}
+
+impl<'a> Interruptible<'a> {
+    pub fn new(device: Box<dyn Transport>, slot: &'a Slot) -> Self {
+        Interruptible { device, slot }
+    }
+}
+
+impl Transport for Interruptible<'_> {
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        if self.slot.is_stopped() || self.slot.replacement.lock().unwrap().is_some() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "display loop interrupted"));
+        }
+        self.slot.beat();
+        *self.slot.writing.lock().unwrap() = Some(Instant::now());
+        let result = self.device.write(data);
+        *self.slot.writing.lock().unwrap() = None;
+        if result.is_ok() {
+            self.slot.written.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        devices::{transport::Recorder, Mode},
+        utils::lock::LockError,
+    };
+    use std::{env::temp_dir, fs, path::Path, process, thread};
+
+    fn slot() -> Slot {
+        let options = Options {
+            mode: Mode::Default,
+            secondary: Mode::Default,
+            update: Duration::from_millis(1000),
+            fahrenheit: false,
+            alarm: false,
+            rotate: 0,
+            keep_on_exit: false,
+        };
+        Slot::new(4, None, Family::AkSeries, options)
+    }
+
+    #[test]
+    fn removed_then_added() {
+        let dir = temp_dir().join(format!("deepcool-slot-{}", process::id()));
+        let socket = Path::new("/tmp/deepcool.sock");
+
+        // The device is driven, then removed while its loop still sleeps
+        let removed = slot();
+        removed.hold(Lock::acquire_in(&dir, "hidraw3", socket).unwrap());
+        assert!(removed.holds("hidraw3"));
+        assert!(matches!(Lock::acquire_in(&dir, "hidraw3", socket), Err(LockError::Held(_))));
+        removed.stop(false);
+        assert!(!removed.holds("hidraw3"));
+
+        // Plugged in again under the same key
+        let added = slot();
+        added.hold(Lock::acquire_in(&dir, "hidraw3", socket).unwrap());
+        assert!(added.holds("hidraw3"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn heartbeat() {
+        let slot = slot();
+        let device = Interruptible::new(Box::new(Recorder::new(2)), &slot);
+        thread::sleep(Duration::from_millis(20));
+        assert!(slot.since_beat() >= Duration::from_millis(20));
+
+        // Every packet is a heartbeat, even one the device rejects
+        device.write(&[1]).unwrap();
+        assert!(slot.since_beat() < Duration::from_millis(20));
+        assert!(slot.is_ready());
+        thread::sleep(Duration::from_millis(20));
+        device.write(&[2]).unwrap();
+        assert!(device.write(&[3]).is_err());
+        assert!(slot.since_beat() < Duration::from_millis(20));
+
+        // A stopped loop does not write anymore
+        slot.stop(false);
+        thread::sleep(Duration::from_millis(20));
+        assert!(device.write(&[4]).is_err());
+        assert!(slot.since_beat() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn ready() {
+        // No display is running yet
+        assert!(!all_ready(&[], false));
+        assert!(all_ready(&[], true));
+
+        let slots = [Arc::new(slot()), Arc::new(slot())];
+        Interruptible::new(Box::new(Recorder::new(1)), &slots[0]).write(&[1]).unwrap();
+        assert!(!all_ready(&slots, true));
+        Interruptible::new(Box::new(Recorder::new(1)), &slots[1]).write(&[1]).unwrap();
+        assert!(all_ready(&slots, false));
+    }
+
+    #[test]
+    fn stalled() {
+        let slot = slot();
+        assert_eq!(slot.stalled(Duration::from_millis(20)), None);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(slot.stalled(Duration::from_millis(20)), Some("stuck"));
+        slot.beat();
+        assert_eq!(slot.stalled(Duration::from_millis(20)), None);
+
+        // A write that does not return
+        *slot.writing.lock().unwrap() = Some(Instant::now());
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(slot.stalled(Duration::from_millis(20)), Some("blocked in a write"));
+        *slot.writing.lock().unwrap() = None;
+
+        // A panic releases the device at once
+        let dir = temp_dir().join(format!("deepcool-panic-{}", process::id()));
+        let socket = Path::new("/tmp/deepcool.sock");
+        slot.hold(Lock::acquire_in(&dir, "hidraw3", socket).unwrap());
+        slot.beat();
+        slot.fail();
+        assert!(slot.has_panicked() && !slot.holds("hidraw3"));
+        assert_eq!(slot.stalled(Duration::from_secs(10)), Some("stopped after an error"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn kept_while_blanking() {
+        let dir = temp_dir().join(format!("deepcool-blank-{}", process::id()));
+        let socket = Path::new("/tmp/deepcool.sock");
+        let slot = slot();
+        slot.hold(Lock::acquire_in(&dir, "hidraw3", socket).unwrap());
+        // The display loop still writes the blank packet
+        slot.stop(true);
+        assert!(slot.holds("hidraw3"));
+        slot.unlock();
+        assert!(Lock::acquire_in(&dir, "hidraw3", socket).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
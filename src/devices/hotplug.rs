@@ -0,0 +1,107 @@
+//! Hotplug events of hidraw devices, received from udev through a netlink socket.
+
+use std::{
+    io,
+    mem::{size_of, zeroed},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+/// Multicast group of the events that were already processed by udev.
+const UDEV_EVENT_GROUP: u32 = 2;
+/// Prefix of the messages sent by udev, followed by a header.
+const UDEV_PREFIX: &[u8] = b"libudev\0";
+
+pub enum Event {
+    /// A hidraw node was created.
+    Add,
+    /// A hidraw node was removed, named like `hidraw3`.
+    Remove(String),
+}
+
+/// Listens to the hidraw events broadcast by udev.
+pub struct Monitor {
+    socket: OwnedFd,
+}
+
+impl Monitor {
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: plain socket syscalls, the descriptor is owned right after it is created
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            );
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = OwnedFd::from_raw_fd(fd);
+
+            let mut address: libc::sockaddr_nl = zeroed();
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = UDEV_EVENT_GROUP;
+            let bound = libc::bind(
+                socket.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if bound < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Monitor { socket })
+        }
+    }
+
+    /// Blocks until the next hidraw node is added or removed.
+    pub fn next_event(&self) -> io::Result<Event> {
+        let mut buffer = [0u8; 8192];
+        loop {
+            // SAFETY: the buffer outlives the call and its length is passed along
+            let len = unsafe {
+                libc::recv(self.socket.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0)
+            };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if let Some(event) = parse(&buffer[..len as usize]) {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+/// Reads a udev message, ignoring everything but hidraw add/remove events.
+fn parse(message: &[u8]) -> Option<Event> {
+    // Step 1: skip the udev header
+    if !message.starts_with(UDEV_PREFIX) || message.len() < 24 {
+        return None;
+    }
+    let offset = u32::from_ne_bytes(message[16..20].try_into().ok()?) as usize;
+    let properties = message.get(offset..)?;
+
+    // Step 2: read the `KEY=value` properties
+    let (mut action, mut subsystem, mut name) = (None, None, None);
+    for property in properties.split(|&byte| byte == 0) {
+        let property = std::str::from_utf8(property).ok()?;
+        match property.split_once('=') {
+            Some(("ACTION", value)) => action = Some(value),
+            Some(("SUBSYSTEM", value)) => subsystem = Some(value),
+            Some(("DEVNAME", value)) => name = Some(value.rsplit('/').next().unwrap_or(value).to_owned()),
+            _ => (),
+        }
+    }
+    if subsystem != Some("hidraw") {
+        return None;
+    }
+
+    match action? {
+        "add" => Some(Event::Add),
+        "remove" => Some(Event::Remove(name?)),
+        _ => None,
+    }
+}
@@ -2,10 +2,12 @@
 //! - LD240
 //! - LD360
 
-use crate:: monitor::cpu::Cpu;
-use super::{device_error, Mode};
-use hidapi::HidApi;
-use std::{thread::sleep, time::Duration};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{transport::Transport, keep_blank, checksum, AlarmSupport, Capabilities, Device, Mode, Options};
+use std::io;
 
 pub const DEFAULT_MODE: Mode = Mode::Auto;
 // The temperature limits are hard-coded in the device
@@ -13,88 +15,138 @@ pub const TEMP_LIMIT_C: u8 = 85;
 pub const TEMP_LIMIT_F: u8 = 185;
 
 pub struct Display {
-    cpu: Cpu,
-    update: Duration,
-    fahrenheit: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, update: Duration, fahrenheit: bool) -> Self {
-        Display {
-            cpu,
-            update,
-            fahrenheit,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[],
+            secondary: &[],
+            fahrenheit: true,
+            alarm: AlarmSupport::HardCoded,
+            rotate: false,
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::Auto,
+                temp_limit: if self.options.fahrenheit { TEMP_LIMIT_F } else { TEMP_LIMIT_C },
+                temp_warning: 0,
+            },
+            update: self.options.update,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn fields(&self) -> Fields {
+        match self.options.mode {
+            Mode::Off => Fields::NONE,
+            _ => Fields::CPU_POWER | Fields::CPU_TEMP | Fields::CPU_USAGE,
+        }
+    }
 
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
         // Display warning if a required module is missing
-        self.cpu.warn_temp();
-        self.cpu.warn_rapl();
-
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
-        data[1] = 104;
-        data[2] = 1;
-        data[3] = 1;
+        sampler.cpu().warn_temp();
+        sampler.cpu().warn_rapl();
 
         // Init sequence
-        {
-            let mut init_data = data.clone();
-            init_data[4] = 2;
-            init_data[5] = 3;
-            init_data[6] = 1;
-            init_data[7] = 112;
-            init_data[8] = 22;
-            device.write(&init_data).unwrap();
-            init_data[5] = 2;
-            init_data[7] = 111;
-            device.write(&init_data).unwrap();
+        for packet in init_packets() {
+            device.write(&packet)?;
+        }
+
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
         }
 
         // Display loop
-        data[4] = 11;
-        data[5] = 1;
-        data[6] = 2;
-        data[7] = 5;
         loop {
-            // Initialize the packet
-            let mut status_data = data.clone();
-
-            // Read CPU utilization & energy consumption
-            let cpu_instant = self.cpu.read_instant();
-            let cpu_energy = self.cpu.read_energy();
-
-            // Wait
-            sleep(self.update);
-
-            // ----- Write data to the package -----
-            // Power consumption
-            let power = (self.cpu.get_power(cpu_energy, self.update.as_millis() as u64)).to_be_bytes();
-            status_data[8] = power[0];
-            status_data[9] = power[1];
-
-            // Temperature
-            let temp = (self.cpu.get_temp(self.fahrenheit) as f32).to_be_bytes();
-            status_data[10] = if self.fahrenheit { 1 } else { 0 };
-            status_data[11] = temp[0];
-            status_data[12] = temp[1];
-            status_data[13] = temp[2];
-            status_data[14] = temp[3];
-
-            // Utilization
-            status_data[15] = self.cpu.get_usage(cpu_instant);
-
-            // Checksum & termination byte
-            let checksum: u16 = status_data[1..=15].iter().map(|&x| x as u16).sum();
-            status_data[16] = (checksum % 256) as u8;
-            status_data[17] = 22;
-
-            device.write(&status_data).unwrap();
+            device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
         }
     }
+
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
+
+/// Returns the packets of the init sequence.
+pub fn init_packets() -> [[u8; 64]; 2] {
+    let mut first = [0; 64];
+    first[..=8].copy_from_slice(&[16, 104, 1, 1, 2, 3, 1, 112, 22]);
+    let mut second = first;
+    second[5] = 2;
+    second[7] = 111;
+    [first, second]
+}
+
+/// Returns the status packet with every reading at zero, as the display has no blank state.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=7].copy_from_slice(&[16, 104, 1, 1, 11, 1, 2, 5]);
+    data[16] = checksum(&data[1..=15]);
+    data[17] = 22;
+    data
+}
+
+/// Builds the status packet of the display.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=7].copy_from_slice(&[16, 104, 1, 1, 11, 1, 2, 5]);
+
+    // Power consumption
+    data[8..=9].copy_from_slice(&sample.cpu_power.to_be_bytes());
+
+    // Temperature
+    data[10] = options.fahrenheit as u8;
+    data[11..=14].copy_from_slice(&(sample.cpu_temperature(options.fahrenheit) as f32).to_be_bytes());
+
+    // Utilization
+    data[15] = sample.cpu_usage;
+
+    // Checksum & termination byte
+    data[16] = checksum(&data[1..=15]);
+    data[17] = 22;
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn encode_celsius() {
+        let packet = encode(&SAMPLE, &options(Mode::Auto));
+        // Power 300 W, 45.0 °C, 57%
+        assert_packet(&packet, &[16, 104, 1, 1, 11, 1, 2, 5, 1, 44, 0, 66, 52, 0, 0, 57, 89, 22]);
+    }
+
+    #[test]
+    fn encode_fahrenheit() {
+        let packet = encode(&SAMPLE, &Options { fahrenheit: true, ..options(Mode::Auto) });
+        // 114.0 °F
+        assert_packet(&packet, &[16, 104, 1, 1, 11, 1, 2, 5, 1, 44, 1, 66, 228, 0, 0, 57, 10, 22]);
+    }
+
+    #[test]
+    fn fixed_packets() {
+        assert_eq!(init_packets().map(|packet| packet[..9].to_vec()), [
+            [16, 104, 1, 1, 2, 3, 1, 112, 22],
+            [16, 104, 1, 1, 2, 2, 1, 111, 22],
+        ]);
+    }
 }
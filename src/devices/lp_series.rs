@@ -2,11 +2,12 @@
 //! - LP240
 //! - LP360
 
-use crate::monitor::{cpu::Cpu, gpu::Gpu};
-use super::{device_error, Mode};
-use cpu_monitor::CpuInstant;
-use hidapi::HidApi;
-use std::{thread::sleep, time::Duration};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{transport::Transport, keep_blank, checksum, AlarmSupport, Capabilities, Device, Mode, Options};
+use std::io;
 
 /// Helper module for the LP Series.
 mod dot_matrix {
@@ -215,172 +216,202 @@ mod dot_matrix {
         bytes
     }
 }
-
 pub const DEFAULT_MODE: Mode = Mode::CpuUsage;
 
 pub struct Display {
-    cpu: Cpu,
-    gpu: Gpu,
-    pub mode: Mode,
-    pub secondary: Option<Mode>,
-    update: Duration,
-    fahrenheit: bool,
-    rotate: u16,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, gpu: Gpu, mode: &Mode, secondary: &Mode, update: Duration, fahrenheit: bool, rotate: u16) -> Self {
-        // Verify the display mode
-        let mode = match mode {
-            Mode::Default => DEFAULT_MODE,
-            Mode::CpuUsage => Mode::CpuUsage,
-            Mode::CpuTemperature => Mode::CpuTemperature,
-            Mode::CpuPower => Mode::CpuPower,
-            Mode::GpuUsage => Mode::GpuUsage,
-            Mode::GpuTemperature => Mode::GpuTemperature,
-            Mode::GpuPower => Mode::GpuPower,
-            _ => mode.support_error(),
-        };
-
-        let secondary = match secondary {
-            Mode::Default => None,
-            Mode::CpuUsage => Some(Mode::CpuUsage),
-            Mode::CpuTemperature => Some(Mode::CpuTemperature),
-            Mode::CpuPower => Some(Mode::CpuPower),
-            Mode::GpuUsage => Some(Mode::GpuUsage),
-            Mode::GpuTemperature => Some(Mode::GpuTemperature),
-            Mode::GpuPower => Some(Mode::GpuPower),
-            _ => Some(secondary.support_error_secondary()),
-        };
-
-        Display {
-            cpu,
-            gpu,
-            mode,
-            secondary,
-            update,
-            fahrenheit,
-            rotate,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        const MODES: &[Mode] = &[
+            Mode::CpuUsage,
+            Mode::CpuTemperature,
+            Mode::CpuPower,
+            Mode::GpuUsage,
+            Mode::GpuTemperature,
+            Mode::GpuPower,
+        ];
+
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: MODES,
+            secondary: MODES,
+            fahrenheit: true,
+            alarm: AlarmSupport::NotSupported,
+            rotate: true,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: secondary(&self.options),
+            rotation: Some(self.options.rotate),
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: AlarmState::NotSupported,
+                temp_limit: 0,
+                temp_warning: 0,
+            },
+            update: self.options.update,
+        }
+    }
+
+    fn fields(&self) -> Fields {
+        self.options.mode.fields() | self.options.secondary.fields()
+    }
+
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
+        }
+
+        let mode = self.options.mode;
+        let secondary = secondary(&self.options);
 
         // Display warning if a required module is missing
-        if matches!(self.mode, Mode::CpuTemperature) || matches!(self.secondary, Some(Mode::CpuTemperature)) {
-            self.cpu.warn_temp();
+        if matches!(mode, Mode::CpuTemperature) || matches!(secondary, Some(Mode::CpuTemperature)) {
+            sampler.cpu().warn_temp();
         }
-        if matches!(self.mode, Mode::CpuPower) || matches!(self.secondary, Some(Mode::CpuPower)) {
-            self.cpu.warn_rapl();
+        if matches!(mode, Mode::CpuPower) || matches!(secondary, Some(Mode::CpuPower)) {
+            sampler.cpu().warn_rapl();
         }
-        if
-            matches!(self.mode, Mode::GpuUsage | Mode::GpuTemperature | Mode::GpuPower) ||
-            matches!(self.secondary, Some(Mode::GpuUsage) | Some(Mode::GpuTemperature) | Some(Mode::GpuPower))
-        {
-            self.gpu.warn_missing();
+        if uses_gpu(&self.options) {
+            sampler.gpu().warn_missing();
         }
 
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
-        data[1] = 104;
-        data[2] = 1;
-        data[3] = 5;
-        data[4] = 29;
-        data[5] = 1;
-
         // Display loop
         loop {
-            // Initialize the packet
-            let mut status_data = data.clone();
-            let mut matrix = [[false; 14]; 14];
-
-            // Get initial CPU readings & wait
-            let cpu_instant = self.cpu.read_instant();
-            let cpu_energy = self.cpu.read_energy();
-            sleep(self.update);
-
-            // Set the pixels and calculate the bytes for the display
-            match &self.secondary {
-                Some(secondary) => {
-                    self.insert_data_to_matrix(
-                        &mut matrix,
-                        1,
-                        self.get_system_info(&self.mode, cpu_instant, cpu_energy)
-                    );
-                    self.insert_data_to_matrix(
-                        &mut matrix,
-                        8,
-                        self.get_system_info(secondary, cpu_instant, cpu_energy)
-                    );
-                }
-                None => {
-                    self.insert_data_to_matrix(
-                        &mut matrix,
-                        5,
-                        self.get_system_info(&self.mode, cpu_instant, cpu_energy)
-                    );
-                }
-            }
-            if self.rotate > 0 {
-                dot_matrix::rotate_matrix(&mut matrix, self.rotate);
-            }
-            status_data[6..=33].copy_from_slice(&dot_matrix::matrix_to_bytes(matrix));
+            device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
+        }
+    }
+
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
 
-            // Checksum & termination byte
-            let checksum: u16 = status_data[1..=33].iter().map(|&x| x as u16).sum();
-            status_data[34] = (checksum % 256) as u8;
-            status_data[35] = 22;
+/// Returns the secondary display mode, if one is set.
+fn secondary(options: &Options) -> Option<Mode> {
+    if options.secondary == Mode::Default { None } else { Some(options.secondary) }
+}
+
+/// Checks if any of the display modes show GPU information.
+fn uses_gpu(options: &Options) -> bool {
+    const GPU_MODES: [Mode; 3] = [Mode::GpuUsage, Mode::GpuTemperature, Mode::GpuPower];
+    GPU_MODES.contains(&options.mode) || GPU_MODES.contains(&options.secondary)
+}
+
+/// Returns the packet with an empty dot matrix.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=5].copy_from_slice(&[16, 104, 1, 5, 29, 1]);
+    data[34] = checksum(&data[1..=33]);
+    data[35] = 22;
+    data
+}
+
+/// Builds the data packet with the dot matrix of the selected display modes.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[..=5].copy_from_slice(&[16, 104, 1, 5, 29, 1]);
+    let mut matrix = [[false; 14]; 14];
 
-            device.write(&status_data).unwrap();
+    // Set the pixels and calculate the bytes for the display
+    match secondary(options) {
+        Some(secondary) => {
+            insert_data_to_matrix(&mut matrix, 1, get_system_info(sample, &options.mode, options.fahrenheit));
+            insert_data_to_matrix(&mut matrix, 8, get_system_info(sample, &secondary, options.fahrenheit));
         }
+        None => {
+            insert_data_to_matrix(&mut matrix, 5, get_system_info(sample, &options.mode, options.fahrenheit));
+        }
+    }
+    if options.rotate > 0 {
+        dot_matrix::rotate_matrix(&mut matrix, options.rotate);
     }
+    data[6..=33].copy_from_slice(&dot_matrix::matrix_to_bytes(matrix));
 
-    fn get_system_info(&self, mode: &Mode, cpu_instant: CpuInstant, cpu_energy: u64) -> (u16, dot_matrix::Unit) {
-        match mode {
-            Mode::CpuUsage => (
-                self.cpu.get_usage(cpu_instant) as u16,
-                dot_matrix::Unit::Percent
-            ),
-            Mode::CpuTemperature => (
-                self.cpu.get_temp(self.fahrenheit) as u16,
-                if self.fahrenheit { dot_matrix::Unit::Fahrenheit } else { dot_matrix::Unit::Celsius }
-            ),
-            Mode::CpuPower => (
-                self.cpu.get_power(cpu_energy, self.update.as_millis() as u64),
-                dot_matrix::Unit::Watt
-            ),
-            Mode::GpuUsage => (
-                self.gpu.get_usage() as u16,
-                dot_matrix::Unit::Percent
-            ),
-            Mode::GpuTemperature => (
-                self.gpu.get_temp(self.fahrenheit) as u16,
-                if self.fahrenheit { dot_matrix::Unit::Fahrenheit } else { dot_matrix::Unit::Celsius }
-            ),
-            Mode::GpuPower => (
-                self.gpu.get_power(),
-                dot_matrix::Unit::Watt
-            ),
-            _ => (0, dot_matrix::Unit::Empty),
-        }
+    // Checksum & termination byte
+    data[34] = checksum(&data[1..=33]);
+    data[35] = 22;
+
+    data
+}
+
+fn get_system_info(sample: &SystemSample, mode: &Mode, fahrenheit: bool) -> (u16, dot_matrix::Unit) {
+    let temp_unit = if fahrenheit { dot_matrix::Unit::Fahrenheit } else { dot_matrix::Unit::Celsius };
+    match mode {
+        Mode::CpuUsage => (sample.cpu_usage as u16, dot_matrix::Unit::Percent),
+        Mode::CpuTemperature => (sample.cpu_temperature(fahrenheit) as u16, temp_unit),
+        Mode::CpuPower => (sample.cpu_power, dot_matrix::Unit::Watt),
+        Mode::GpuUsage => (sample.gpu_usage as u16, dot_matrix::Unit::Percent),
+        Mode::GpuTemperature => (sample.gpu_temperature(fahrenheit) as u16, temp_unit),
+        Mode::GpuPower => (sample.gpu_power, dot_matrix::Unit::Watt),
+        _ => (0, dot_matrix::Unit::Empty),
     }
+}
 
-    fn insert_data_to_matrix(&self, matrix: &mut [[bool; 14]; 14], row_id: usize, data: (u16, dot_matrix::Unit)) {
-        let (value, unit) = data;
-        if value / 100 < 1 {
-            // 2-digit number
-            dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value / 10) as u8), row_id, 1);
-            dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value % 10) as u8), row_id, 5);
-            dot_matrix::insert_pattern(matrix, unit.get_pattern(), 5, 9);
-        } else {
-            // 3-digit number
-            dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value / 100) as u8), row_id, 1);
-            dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value % 100 / 10) as u8), row_id, 5);
-            dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value % 10) as u8), row_id, 9);
-            dot_matrix::insert_pattern(matrix, unit.get_pattern(), 5, 13);
-        }
+fn insert_data_to_matrix(matrix: &mut [[bool; 14]; 14], row_id: usize, data: (u16, dot_matrix::Unit)) {
+    let (value, unit) = data;
+    if value / 100 < 1 {
+        // 2-digit number
+        dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value / 10) as u8), row_id, 1);
+        dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value % 10) as u8), row_id, 5);
+        dot_matrix::insert_pattern(matrix, unit.get_pattern(), 5, 9);
+    } else {
+        // 3-digit number
+        dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value / 100) as u8), row_id, 1);
+        dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value % 100 / 10) as u8), row_id, 5);
+        dot_matrix::insert_pattern(matrix, dot_matrix::get_number_pattern((value % 10) as u8), row_id, 9);
+        dot_matrix::insert_pattern(matrix, unit.get_pattern(), 5, 13);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn pixel_bytes() {
+        // Odd rows go from the first column, even rows from the last one
+        let mut matrix = [[false; 14]; 14];
+        let mut expected = [0; 28];
+        // Row 1, column 1
+        matrix[0][0] = true;
+        expected[0] = 16;
+        // Row 9, column 2
+        matrix[8][1] = true;
+        expected[1] = 1;
+        // Row 2, column 1
+        matrix[1][0] = true;
+        expected[27] = 16;
+        // Row 14, column 14
+        matrix[13][13] = true;
+        expected[14] = 4;
+        assert_eq!(dot_matrix::matrix_to_bytes(matrix), expected);
+    }
+
+    #[test]
+    fn encode_header() {
+        let packet = encode(&SAMPLE, &options(Mode::CpuUsage));
+        assert_eq!(packet[..6], [16, 104, 1, 5, 29, 1]);
+        assert_eq!(packet[34], checksum(&packet[1..=33]));
+        assert_eq!(packet[35], 22);
+        assert!(packet[6..=33].iter().any(|&byte| byte != 0));
+        assert!(packet[36..].iter().all(|&byte| byte == 0));
+
+        // An empty matrix
+        let mut expected = [0; 36];
+        expected[..6].copy_from_slice(&[16, 104, 1, 5, 29, 1]);
+        expected[34..].copy_from_slice(&[140, 22]);
+        assert_packet(&blank_packet(), &expected);
     }
 }
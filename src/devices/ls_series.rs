@@ -2,82 +2,97 @@
 //! - LS520 SE DIGITAL
 //! - LS720 SE DIGITAL
 
-use crate::monitor::cpu::Cpu;
-use super::{device_error, Mode, AUTO_MODE_INTERVAL};
-use hidapi::HidApi;
-use std::{thread::sleep, time::{Duration, Instant}};
+use crate::{
+    monitor::{Fields, Sampler, SystemSample},
+    utils::status::{Alarm, AlarmState, Status, TemperatureUnit},
+};
+use super::{
+    transport::Transport, keep_blank, digits, status_bar, AlarmSupport, Capabilities,
+    Device, Mode, Options, AUTO_MODE_INTERVAL,
+};
+use std::{io, time::Instant};
 
 pub const DEFAULT_MODE: Mode = Mode::CpuTemperature;
 pub const TEMP_LIMIT_C: u8 = 90;
 pub const TEMP_LIMIT_F: u8 = 194;
 
 pub struct Display {
-    cpu: Cpu,
-    pub mode: Mode,
-    update: Duration,
-    fahrenheit: bool,
-    alarm: bool,
+    options: Options,
 }
 
-impl Display {
-    pub fn new(cpu: Cpu, mode: &Mode, update: Duration, fahrenheit: bool, alarm: bool) -> Self {
-        // Verify the display mode
-        let mode = match mode {
-            Mode::Default => DEFAULT_MODE,
-            Mode::Auto => Mode::Auto,
-            Mode::CpuTemperature => Mode::CpuTemperature,
-            Mode::CpuPower => Mode::CpuPower,
-            _ => mode.support_error(),
-        };
-
-        Display {
-            cpu,
-            mode,
-            update,
-            fahrenheit,
-            alarm,
+impl Device for Display {
+    fn new(options: &Options) -> Self {
+        Display { options: *options }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            default_mode: DEFAULT_MODE,
+            modes: &[Mode::Auto, Mode::CpuTemperature, Mode::CpuPower],
+            secondary: &[],
+            fahrenheit: true,
+            alarm: AlarmSupport::Configurable,
+            rotate: false,
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            mode: self.options.mode,
+            secondary: None,
+            rotation: None,
+            temp_unit: TemperatureUnit::get(self.options.fahrenheit),
+            alarm: Alarm {
+                state: if self.options.alarm { AlarmState::On } else { AlarmState::Off },
+                temp_limit: if self.options.fahrenheit { TEMP_LIMIT_F } else { TEMP_LIMIT_C },
+                temp_warning: 0,
+            },
+            update: self.options.update,
         }
     }
 
-    pub fn run(&self, api: &HidApi, vid: u16, pid: u16) {
-        // Connect to device
-        let device = api.open(vid, pid).unwrap_or_else(|_| device_error());
+    fn fields(&self) -> Fields {
+        // The status bar and the alarm are always shown
+        match self.options.mode {
+            Mode::Off => Fields::NONE,
+            Mode::Auto => Fields::CPU_USAGE | Fields::CPU_TEMP | Fields::CPU_POWER,
+            mode => Fields::CPU_USAGE | Fields::CPU_TEMP | mode.fields(),
+        }
+    }
 
-        // Display warning if a required module is missing
-        self.cpu.warn_temp();
-        self.cpu.warn_rapl();
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()> {
+        // Keep the display dark
+        if self.options.mode == Mode::Off {
+            return keep_blank(device, &blank_packet());
+        }
 
-        // Data packet
-        let mut data: [u8; 64] = [0; 64];
-        data[0] = 16;
+        // Display warning if a required module is missing
+        sampler.cpu().warn_temp();
+        sampler.cpu().warn_rapl();
 
         // Init sequence
-        {
-            let mut init_data = data.clone();
-            init_data[1] = 170;
-            device.write(&init_data).unwrap();
-        }
+        device.write(&init_packet())?;
 
         // Display loop
-        match self.mode {
+        match self.options.mode {
             Mode::Auto => {
-                let mut initial_update = self.update;
-                let mut mode = Mode::CpuTemperature;
+                let mut initial_update = self.options.update;
+                let mut options = Options { mode: Mode::CpuTemperature, ..self.options };
                 loop {
                     // Initial update
-                    device.write(&self.status_message(&data, &mode, initial_update)).unwrap();
+                    device.write(&encode(&sampler.sample(initial_update), &options))?;
 
                     // Update until timeout
                     let timeout = Instant::now() + AUTO_MODE_INTERVAL;
-                    while Instant::now() + self.update < timeout {
-                        device.write(&self.status_message(&data, &mode, self.update)).unwrap();
+                    while Instant::now() + self.options.update < timeout {
+                        device.write(&encode(&sampler.sample(self.options.update), &options))?;
                     }
 
                     // Make the next initial update faster to fit the timeframe
                     initial_update = timeout - Instant::now();
 
                     // Switch to the next display mode
-                    mode = match mode {
+                    options.mode = match options.mode {
                         Mode::CpuTemperature => Mode::CpuPower,
                         Mode::CpuPower => Mode::CpuTemperature,
                         _ => DEFAULT_MODE,
@@ -85,49 +100,79 @@ impl Display {
                 }
             }
             _ => loop {
-                device.write(&self.status_message(&data, &self.mode, self.update)).unwrap();
+                device.write(&encode(&sampler.sample(self.options.update), &self.options))?;
             }
         }
     }
 
-    /// Reads the CPU status information and returns the data packet.
-    fn status_message(&self, inital_data: &[u8; 64], mode: &Mode, update: Duration) -> [u8; 64] {
-        // Clone the data packet
-        let mut data = inital_data.clone();
-
-        // Read CPU utilization & energy consumption (if needed)
-        let cpu_instant = self.cpu.read_instant();
-        let cpu_energy = if mode == &Mode::CpuPower { self.cpu.read_energy() } else { 0 };
-
-        // Wait
-        sleep(update);
-
-        // Calculate usage & temperature
-        let usage = self.cpu.get_usage(cpu_instant);
-        let temp = self.cpu.get_temp(self.fahrenheit);
-
-        // Main display
-        match mode {
-            Mode::CpuTemperature => {
-                data[1] = if self.fahrenheit { 35 } else { 19 };
-                data[3] = temp / 100;
-                data[4] = temp % 100 / 10;
-                data[5] = temp % 10;
-            }
-            Mode::CpuPower => {
-                let power = self.cpu.get_power(cpu_energy, update.as_millis() as u64);
-                data[1] = 76;
-                data[3] = (power / 100) as u8;
-                data[4] = (power % 100 / 10) as u8;
-                data[5] = (power % 10) as u8;
-            }
-            _ => (),
+    fn blank(&self, device: &dyn Transport) -> io::Result<()> {
+        device.write(&blank_packet())
+    }
+}
+
+/// Returns the packet that starts the status bar animation.
+pub fn init_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+    data[1] = 170;
+    data
+}
+
+/// Returns the packet that turns the digits, the status bar, and the alarm off.
+pub fn blank_packet() -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+    data
+}
+
+/// Builds the data packet of the selected display mode.
+pub fn encode(sample: &SystemSample, options: &Options) -> [u8; 64] {
+    let mut data = [0; 64];
+    data[0] = 16;
+
+    let temp = sample.cpu_temperature(options.fahrenheit);
+
+    // Main display
+    match options.mode {
+        Mode::CpuTemperature => {
+            data[1] = if options.fahrenheit { 35 } else { 19 };
+            data[3..=5].copy_from_slice(&digits(temp as u16));
         }
-        // Status bar
-        data[2] = if usage < 15 { 1 } else { (usage as f32 / 10.0).round() as u8 };
-        // Alarm
-        data[6] = (self.alarm && temp >= if self.fahrenheit { TEMP_LIMIT_F } else { TEMP_LIMIT_C }) as u8;
+        Mode::CpuPower => {
+            data[1] = 76;
+            data[3..=5].copy_from_slice(&digits(sample.cpu_power));
+        }
+        _ => (),
+    }
+    // Status bar
+    data[2] = status_bar(sample.cpu_usage);
+    // Alarm
+    data[6] = (options.alarm && temp >= if options.fahrenheit { TEMP_LIMIT_F } else { TEMP_LIMIT_C }) as u8;
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::tests::{assert_packet, options, SAMPLE};
+
+    #[test]
+    fn encode_temperature() {
+        assert_packet(&encode(&SAMPLE, &options(Mode::CpuTemperature)), &[16, 19, 6, 0, 4, 5]);
+        let options = Options { fahrenheit: true, ..options(Mode::CpuTemperature) };
+        assert_packet(&encode(&SAMPLE, &options), &[16, 35, 6, 1, 1, 4]);
+    }
+
+    #[test]
+    fn encode_power() {
+        assert_packet(&encode(&SAMPLE, &options(Mode::CpuPower)), &[16, 76, 6, 3, 0, 0]);
+    }
 
-        data
+    #[test]
+    fn encode_alarm() {
+        let sample = SystemSample { cpu_temp: 90.0, ..SAMPLE };
+        let options = Options { alarm: true, ..options(Mode::CpuPower) };
+        assert_packet(&encode(&sample, &options), &[16, 76, 6, 3, 0, 0, 1]);
     }
 }
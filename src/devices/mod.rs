@@ -5,22 +5,146 @@ pub mod ak620_pro;
 pub mod ch_series;
 pub mod ch_series_gen2;
 pub mod ch510;
+pub mod control;
+pub mod hotplug;
 pub mod ld_series;
 pub mod lp_series;
 pub mod lq_series;
 pub mod ls_series;
+pub mod transport;
 
-use crate::error;
-use std::{process::exit, time::Duration};
-use hidapi::HidDevice;
+use crate::{
+    error,
+    monitor::{Fields, Sampler},
+    utils::status::Status,
+};
+use transport::Transport;
+use std::{fmt, io, process::exit, thread::sleep, time::Duration};
 
 pub const DEFAULT_VENDOR_ID: u16 = 13875;
 pub const CH510_VENDOR_ID: u16 = 13523;
 pub const CH510_PRODUCT_ID: u16 = 4352;
 
 pub const AUTO_MODE_INTERVAL: Duration = Duration::from_millis(5000);
+/// Interval of the blank packets in `Mode::Off`, only sent to keep the display dark.
+pub const OFF_MODE_INTERVAL: Duration = Duration::from_millis(2000);
 
-#[derive(PartialEq)]
+/// Device families that share the same display module.
+#[derive(PartialEq, Clone, Copy)]
+pub enum Family {
+    AgSeries,
+    AkSeries,
+    Ak400Pro,
+    Ak620Pro,
+    ChSeries,
+    ChSeriesGen2,
+    Ch510,
+    LdSeries,
+    LpSeries,
+    LqSeries,
+    LsSeries,
+}
+
+impl Family {
+    /// Returns the features supported by the devices of the family.
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            Family::AgSeries => ag_series::Display::capabilities(),
+            Family::AkSeries => ak_series::Display::capabilities(),
+            Family::Ak400Pro => ak400_pro::Display::capabilities(),
+            Family::Ak620Pro => ak620_pro::Display::capabilities(),
+            Family::ChSeries => ch_series::Display::capabilities(),
+            Family::ChSeriesGen2 => ch_series_gen2::Display::capabilities(),
+            Family::Ch510 => ch510::Display::capabilities(),
+            Family::LdSeries => ld_series::Display::capabilities(),
+            Family::LpSeries => lp_series::Display::capabilities(),
+            Family::LqSeries => lq_series::Display::capabilities(),
+            Family::LsSeries => ls_series::Display::capabilities(),
+        }
+    }
+
+    /// Verifies the options like the display module of the family does.
+    pub fn verify(self, options: &Options) -> Result<Options, ModeError> {
+        match self {
+            Family::AgSeries => ag_series::Display::verify(options),
+            Family::AkSeries => ak_series::Display::verify(options),
+            Family::Ak400Pro => ak400_pro::Display::verify(options),
+            Family::Ak620Pro => ak620_pro::Display::verify(options),
+            Family::ChSeries => ch_series::Display::verify(options),
+            Family::ChSeriesGen2 => ch_series_gen2::Display::verify(options),
+            Family::Ch510 => ch510::Display::verify(options),
+            Family::LdSeries => ld_series::Display::verify(options),
+            Family::LpSeries => lp_series::Display::verify(options),
+            Family::LqSeries => lq_series::Display::verify(options),
+            Family::LsSeries => ls_series::Display::verify(options),
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Family::AgSeries => "AG Series",
+            Family::AkSeries => "AK Series",
+            Family::Ak400Pro => "AK400 DIGITAL PRO",
+            Family::Ak620Pro => "AK500/AK620 DIGITAL PRO",
+            Family::ChSeries => "CH Series",
+            Family::ChSeriesGen2 => "CH Series (Gen 2)",
+            Family::Ch510 => "CH510 MESH DIGITAL",
+            Family::LdSeries => "LD Series",
+            Family::LpSeries => "LP Series",
+            Family::LqSeries => "LQ Series",
+            Family::LsSeries => "LS Series",
+        }
+    }
+}
+
+/// Maps a USB VID/PID pair to its device family.
+pub struct Product {
+    pub vid: u16,
+    pub pid: u16,
+    pub family: Family,
+}
+
+/// All supported products, see `device-list/README.md`.
+pub const PRODUCTS: [Product; 21] = [
+    Product { vid: DEFAULT_VENDOR_ID, pid: 1, family: Family::AkSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 2, family: Family::AkSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 3, family: Family::AkSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 4, family: Family::AkSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 5, family: Family::ChSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 6, family: Family::LsSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 7, family: Family::ChSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 8, family: Family::AgSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 10, family: Family::LdSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 12, family: Family::LpSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 13, family: Family::LqSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 15, family: Family::LqSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 16, family: Family::Ak400Pro },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 17, family: Family::Ak620Pro },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 18, family: Family::Ak620Pro },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 19, family: Family::ChSeriesGen2 },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 21, family: Family::ChSeries },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 22, family: Family::ChSeriesGen2 },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 27, family: Family::ChSeriesGen2 },
+    Product { vid: DEFAULT_VENDOR_ID, pid: 31, family: Family::LqSeries },
+    Product { vid: CH510_VENDOR_ID, pid: CH510_PRODUCT_ID, family: Family::Ch510 },
+];
+
+/// Looks up the device family of a USB device.
+pub fn find_family(vid: u16, pid: u16) -> Option<Family> {
+    PRODUCTS.iter().find(|p| p.vid == vid && p.pid == pid).map(|p| p.family)
+}
+
+/// Returns the Vendor ID that belongs to the Product ID.
+pub fn vendor_id_of(pid: u16) -> u16 {
+    if pid == CH510_PRODUCT_ID { CH510_VENDOR_ID } else { DEFAULT_VENDOR_ID }
+}
+
+/// Returns `true` if the USB device is a known or a possible (not yet added) DeepCool device.
+pub fn is_deepcool(vid: u16, pid: u16) -> bool {
+    vid == DEFAULT_VENDOR_ID || find_family(vid, pid).is_some()
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Mode {
     Default,
     Auto,
@@ -35,9 +159,42 @@ pub enum Mode {
     Cpu,
     Gpu,
     Psu,
+    /// Blank display, supported by every device.
+    Off,
 }
 
 impl Mode {
+    /// Every mode that can be selected with `--mode` or `--secondary`.
+    pub const ALL: [Mode; 13] = [
+        Mode::Auto,
+        Mode::CpuTemperature,
+        Mode::CpuUsage,
+        Mode::CpuPower,
+        Mode::CpuFrequency,
+        Mode::CpuFan,
+        Mode::GpuTemperature,
+        Mode::GpuUsage,
+        Mode::GpuPower,
+        Mode::Cpu,
+        Mode::Gpu,
+        Mode::Psu,
+        Mode::Off,
+    ];
+
+    /// Returns the reading shown in a mode that displays a single value.
+    pub fn fields(&self) -> Fields {
+        match self {
+            Mode::CpuTemperature => Fields::CPU_TEMP,
+            Mode::CpuUsage => Fields::CPU_USAGE,
+            Mode::CpuPower => Fields::CPU_POWER,
+            Mode::CpuFrequency => Fields::CPU_FREQUENCY,
+            Mode::GpuTemperature => Fields::GPU_TEMP,
+            Mode::GpuUsage => Fields::GPU_USAGE,
+            Mode::GpuPower => Fields::GPU_POWER,
+            _ => Fields::NONE,
+        }
+    }
+
     pub const fn symbol(&self) -> &'static str {
         match self {
             Mode::Default => "",
@@ -53,6 +210,7 @@ impl Mode {
             Mode::Cpu => "cpu",
             Mode::Gpu => "gpu",
             Mode::Psu => "psu",
+            Mode::Off => "off",
         }
     }
 
@@ -70,24 +228,289 @@ impl Mode {
             "cpu" => Some(Self::Cpu),
             "gpu" => Some(Self::Gpu),
             "psu" => Some(Self::Psu),
+            "off" => Some(Self::Off),
             _ => None,
         }
     }
+}
 
-    pub fn support_error(&self) -> Mode {
-        error!(format!("Display mode \"{}\" is not supported on your device", self.symbol()));
-        exit(1);
+/// Error returned when a display mode is not supported by the device.
+pub enum ModeError {
+    Primary(Mode),
+    Secondary(Mode),
+}
+
+impl fmt::Display for ModeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModeError::Primary(mode) => {
+                write!(f, "Display mode \"{}\" is not supported on your device", mode.symbol())
+            }
+            ModeError::Secondary(mode) => {
+                write!(f, "Secondary display mode \"{}\" is not supported on your device", mode.symbol())
+            }
+        }
     }
+}
+
+/// Display settings requested by the user.
+#[derive(PartialEq, Clone, Copy)]
+pub struct Options {
+    pub mode: Mode,
+    pub secondary: Mode,
+    pub update: Duration,
+    pub fahrenheit: bool,
+    pub alarm: bool,
+    pub rotate: u16,
+    /// Leave the last values on the display when the program exits, instead of blanking it.
+    pub keep_on_exit: bool,
+}
+
+#[derive(PartialEq)]
+pub enum AlarmSupport {
+    /// The alarm can be turned on and off with `--alarm`.
+    Configurable,
+    /// The alarm is always active and the temperature limits are set by the device.
+    HardCoded,
+    NotSupported,
+}
+
+/// Features of a device that can be changed by the user.
+pub struct Capabilities {
+    pub default_mode: Mode,
+    /// Selectable display modes, empty if the display mode cannot be changed.
+    pub modes: &'static [Mode],
+    /// Selectable secondary display modes, empty if not supported.
+    pub secondary: &'static [Mode],
+    pub fahrenheit: bool,
+    pub alarm: AlarmSupport,
+    pub rotate: bool,
+}
+
+/// Common interface of the display modules.
+pub trait Device {
+    /// Creates the display driver from already verified options.
+    fn new(options: &Options) -> Self
+    where
+        Self: Sized;
+
+    /// Returns the features supported by the device.
+    fn capabilities() -> Capabilities
+    where
+        Self: Sized;
 
-    pub fn support_error_secondary(&self) -> Mode {
-        error!(format!("Secondary display mode \"{}\" is not supported on your device", self.symbol()));
-        exit(1);
+    /// Verifies the requested display modes and resolves `Mode::Default` to the device default.
+    fn verify(options: &Options) -> Result<Options, ModeError>
+    where
+        Self: Sized,
+    {
+        let capabilities = Self::capabilities();
+        let mut options = *options;
+
+        if options.mode == Mode::Off {
+            // Every device can be blanked
+        } else if options.mode == Mode::Default || capabilities.modes.is_empty() {
+            options.mode = capabilities.default_mode;
+        } else if !capabilities.modes.contains(&options.mode) {
+            return Err(ModeError::Primary(options.mode));
+        }
+
+        if capabilities.secondary.is_empty() {
+            options.secondary = Mode::Default;
+        } else if options.secondary != Mode::Default && !capabilities.secondary.contains(&options.secondary) {
+            return Err(ModeError::Secondary(options.secondary));
+        }
+
+        Ok(options)
+    }
+
+    /// Returns the active settings of the display.
+    fn status(&self) -> Status;
+
+    /// Returns the readings shown on the display, the only ones the sampler reads for it.
+    fn fields(&self) -> Fields;
+
+    /// Runs the display loop on an already opened device, fed by the shared sampler.
+    ///
+    /// The init sequence is sent first, so the loop can be restarted after reconnecting.
+    /// Returns only when the device stops accepting packets.
+    fn run(&self, device: &dyn Transport, sampler: &Sampler) -> io::Result<()>;
+
+    /// Leaves the display in a defined idle state, sent before the program exits.
+    fn blank(&self, device: &dyn Transport) -> io::Result<()>;
+}
+
+/// Sends the blank packet at a low rate, so the display stays dark until it is stopped.
+pub fn keep_blank(device: &dyn Transport, blank: &[u8]) -> io::Result<()> {
+    loop {
+        device.write(blank)?;
+        sleep(OFF_MODE_INTERVAL);
     }
 }
 
-pub fn device_error() -> HidDevice {
-    error!("Failed to access the USB device");
-    eprintln!("       Try to run the program as root or give permission to the neccesary resources.");
-    eprintln!("       You can find instructions about rootless mode on GitHub.");
+/// Splits a number into its hundreds, tens, and ones digits.
+pub const fn digits(value: u16) -> [u8; 3] {
+    [(value / 100) as u8, (value % 100 / 10) as u8, (value % 10) as u8]
+}
+
+/// Converts the utilization into the value of the 10-segment status bar.
+pub fn status_bar(usage: u8) -> u8 {
+    if usage < 15 { 1 } else { (usage as f32 / 10.0).round() as u8 }
+}
+
+/// Calculates the checksum byte of the data (sum of all bytes, modulo 256).
+pub fn checksum(data: &[u8]) -> u8 {
+    let sum: u16 = data.iter().map(|&x| x as u16).sum();
+    (sum % 256) as u8
+}
+
+pub fn device_error() -> ! {
+    error!(
+        "Failed to access the USB device\n\
+         Try to run the program as root or give permission to the neccesary resources.\n\
+         You can find instructions about rootless mode on GitHub."
+    );
     exit(1);
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::{transport::Recorder, *};
+    use crate::monitor::{cpu::Cpu, fixture::Root, gpu::Gpu, SystemSample};
+    use std::{cell::RefCell, thread};
+
+    /// Records the packets, and the readings of the sampler when each one was written.
+    struct Tap<'a> {
+        recorder: Recorder,
+        sampler: &'a Sampler,
+        samples: RefCell<Vec<SystemSample>>,
+    }
+
+    impl Transport for Tap<'_> {
+        fn write(&self, data: &[u8]) -> io::Result<()> {
+            self.recorder.write(data)?;
+            self.samples.borrow_mut().push(self.sampler.last());
+            Ok(())
+        }
+    }
+
+    /// Readings of the encoder tests, with values that take both bytes of the `U16` fields.
+    pub const SAMPLE: SystemSample = SystemSample {
+        cpu_usage: 57,
+        cpu_temp: 45.4,
+        cpu_power: 300,
+        cpu_frequency: 4250,
+        gpu_usage: 88,
+        gpu_temp: 62.0,
+        gpu_power: 215,
+        gpu_frequency: 2100,
+    };
+
+    /// Asserts that the packet starts with the expected bytes, and the rest is not used.
+    pub fn assert_packet(packet: &[u8], expected: &[u8]) {
+        assert_eq!(&packet[..expected.len()], expected);
+        assert!(packet[expected.len()..].iter().all(|&byte| byte == 0), "unused bytes are set: {packet:?}");
+    }
+
+    /// Returns the options of the device defaults.
+    ///
+    /// The update interval is long enough for every update to take new readings.
+    pub fn options(mode: Mode) -> Options {
+        Options {
+            mode,
+            secondary: Mode::Default,
+            update: Duration::from_millis(100),
+            fahrenheit: false,
+            alarm: false,
+            rotate: 0,
+            keep_on_exit: false,
+        }
+    }
+
+    /// Runs the display on the CPU sensors of `Root::cpu` until it wrote `count` packets.
+    ///
+    /// Returns the packets, and the readings of the last packet.
+    pub fn record(display: &dyn Device, count: usize) -> (Vec<Vec<u8>>, SystemSample) {
+        let name = thread::current().name().unwrap_or("record").replace("::", "-");
+        let root = Root::cpu(&name);
+        let sampler = Sampler::new(Cpu::new(root.path()), Gpu::None);
+        let _demand = sampler.subscribe(display.fields());
+        let tap = Tap { recorder: Recorder::new(count), sampler: &sampler, samples: RefCell::new(Vec::new()) };
+        let err = display.run(&tap, &sampler).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        let sample = tap.samples.borrow().last().copied().unwrap_or_default();
+        (tap.recorder.packets(), sample)
+    }
+
+    /// Runs the display with the defaults of the device.
+    ///
+    /// Returns the packets it wrote, and the ones expected: the init packets, then the readings of the last packet.
+    fn run_display<D: Device>(
+        init: Vec<Vec<u8>>,
+        encode: fn(&SystemSample, &Options) -> Vec<u8>,
+    ) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        let options = D::verify(&options(Mode::Default)).unwrap_or_else(|err| panic!("{err}"));
+        let display = D::new(&options);
+        let (packets, sample) = record(&display, init.len() + 1);
+        // The readings come from the sensors of the root
+        if display.fields().contains(Fields::CPU_TEMP) {
+            assert_eq!(sample.cpu_temp, 45.0);
+        }
+        let mut expected = init;
+        expected.push(encode(&sample, &options));
+        (packets, expected)
+    }
+
+    fn run_family(family: Family) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        match family {
+            Family::AgSeries => run_display::<ag_series::Display>(vec![], |s, o| ag_series::encode(s, o).to_vec()),
+            Family::AkSeries => run_display::<ak_series::Display>(vec![ak_series::init_packet().to_vec()], |s, o| {
+                ak_series::encode(s, o).to_vec()
+            }),
+            Family::Ak400Pro => run_display::<ak400_pro::Display>(vec![], |s, o| ak400_pro::encode(s, o).to_vec()),
+            Family::Ak620Pro => run_display::<ak620_pro::Display>(vec![], |s, o| ak620_pro::encode(s, o).to_vec()),
+            Family::ChSeries => run_display::<ch_series::Display>(vec![ch_series::init_packet().to_vec()], |s, o| {
+                ch_series::encode(s, o).to_vec()
+            }),
+            Family::ChSeriesGen2 => {
+                run_display::<ch_series_gen2::Display>(vec![], |s, o| ch_series_gen2::encode(s, o).to_vec())
+            }
+            Family::Ch510 => run_display::<ch510::Display>(vec![], |s, o| ch510::encode(s, o).into_bytes()),
+            Family::LdSeries => {
+                let init = ld_series::init_packets().iter().map(|packet| packet.to_vec()).collect();
+                run_display::<ld_series::Display>(init, |s, o| ld_series::encode(s, o).to_vec())
+            }
+            Family::LpSeries => run_display::<lp_series::Display>(vec![], |s, o| lp_series::encode(s, o).to_vec()),
+            Family::LqSeries => run_display::<lq_series::Display>(vec![], |s, o| lq_series::encode(s, o).to_vec()),
+            Family::LsSeries => run_display::<ls_series::Display>(vec![ls_series::init_packet().to_vec()], |s, o| {
+                ls_series::encode(s, o).to_vec()
+            }),
+        }
+    }
+
+    #[test]
+    fn run() {
+        // Every display loop writes its init packets, then the encoding of the readings
+        let mut families = Vec::new();
+        for product in &PRODUCTS {
+            if !families.contains(&product.family) {
+                families.push(product.family);
+                let (packets, expected) = run_family(product.family);
+                assert_eq!(packets, expected, "PID {}", product.pid);
+            }
+        }
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!(digits(7), [0, 0, 7]);
+        assert_eq!(digits(45), [0, 4, 5]);
+        assert_eq!(digits(999), [9, 9, 9]);
+        assert_eq!(status_bar(0), 1);
+        assert_eq!(status_bar(57), 6);
+        assert_eq!(status_bar(100), 10);
+        // Sum of the bytes, modulo 256
+        assert_eq!(checksum(&[104, 1, 2]), 107);
+        assert_eq!(checksum(&[200, 100]), 44);
+    }
+}
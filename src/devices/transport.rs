@@ -0,0 +1,167 @@
+//! Destinations the display packets can be written to:
+//! - hidapi device
+//! - in-memory recorder
+//! - hex dump to a file or `stdout`
+
+use hidapi::{HidApi, HidDevice};
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    fmt,
+    fs::File,
+    io::{self, stdout, Write},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// First delay between two reconnection attempts, doubled after every failure.
+pub const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+/// Longest delay between two reconnection attempts.
+pub const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+/// Interval of the checks for cancellation during a reconnection delay.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub trait Transport: Send {
+    /// Sends a single packet to the device.
+    fn write(&self, data: &[u8]) -> io::Result<()>;
+}
+
+impl Transport for HidDevice {
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        HidDevice::write(self, data).map(|_| ()).map_err(io::Error::other)
+    }
+}
+
+/// Keeps every written packet in memory, so the driver output can be inspected in tests.
+#[cfg(test)]
+pub struct Recorder {
+    packets: RefCell<Vec<Vec<u8>>>,
+    /// Number of packets accepted before the device is lost, so the display loop returns.
+    limit: usize,
+}
+
+#[cfg(test)]
+impl Recorder {
+    pub fn new(limit: usize) -> Self {
+        Recorder { packets: RefCell::new(Vec::new()), limit }
+    }
+
+    /// Returns the packets written so far.
+    pub fn packets(&self) -> Vec<Vec<u8>> {
+        self.packets.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Transport for Recorder {
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        let mut packets = self.packets.borrow_mut();
+        if packets.len() == self.limit {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        }
+        packets.push(data.to_vec());
+        Ok(())
+    }
+}
+
+/// Writes every packet as a line of hex bytes.
+pub struct HexSink {
+    writer: RefCell<Box<dyn Write + Send>>,
+}
+
+impl HexSink {
+    /// Opens the sink at the given path, `-` stands for `stdout`.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> =
+            if path == "-" { Box::new(stdout()) } else { Box::new(File::create(path)?) };
+        Ok(HexSink { writer: RefCell::new(writer) })
+    }
+}
+
+impl Transport for HexSink {
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        let line = data.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ");
+        let mut writer = self.writer.borrow_mut();
+        writeln!(writer, "{line}")?;
+        writer.flush()
+    }
+}
+
+/// Describes how a transport can be opened again after the device was lost.
+pub enum Endpoint {
+    /// USB device found by enumeration, reopened by its serial number if it has one.
+    Hid { vid: u16, pid: u16, serial: Option<String> },
+    /// Specific hidraw device given with `--hidraw`.
+    HidPath(CString),
+    /// Hex dump given with `--output`.
+    Output(String),
+}
+
+impl Endpoint {
+    /// Opens a new transport to the endpoint.
+    pub fn open(&self) -> io::Result<Box<dyn Transport>> {
+        match self {
+            Endpoint::Hid { vid, pid, serial } => {
+                let api = HidApi::new().map_err(io::Error::other)?;
+                let device = match serial {
+                    Some(serial) => api.open_serial(*vid, *pid, serial),
+                    None => api.open(*vid, *pid),
+                };
+                Ok(Box::new(device.map_err(io::Error::other)?))
+            }
+            Endpoint::HidPath(path) => {
+                let api = HidApi::new().map_err(io::Error::other)?;
+                Ok(Box::new(api.open_path(path).map_err(io::Error::other)?))
+            }
+            Endpoint::Output(path) => Ok(Box::new(HexSink::open(path)?)),
+        }
+    }
+
+    /// Keeps opening the endpoint with an increasing delay until it succeeds, or gives up once `cancelled`.
+    pub fn reconnect(&self, cancelled: &dyn Fn() -> bool) -> Option<Box<dyn Transport>> {
+        let mut delay = RECONNECT_DELAY;
+        loop {
+            // A stopped slot, like a released device, does not wait for the whole delay
+            let retry = Instant::now() + delay;
+            while let Some(left) = retry.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+                if cancelled() {
+                    return None;
+                }
+                sleep(left.min(CANCEL_INTERVAL));
+            }
+            if cancelled() {
+                return None;
+            }
+            if let Ok(transport) = self.open() {
+                return Some(transport);
+            }
+            delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Hid { pid, .. } => write!(f, "PID {pid}"),
+            Endpoint::HidPath(path) => write!(f, "hidraw={}", path.to_string_lossy()),
+            Endpoint::Output(path) => write!(f, "output={path}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_cancelled_during_delay() {
+        // The file cannot be created, so every attempt fails
+        let endpoint = Endpoint::Output("/nonexistent/deepcool/output".to_owned());
+        let start = Instant::now();
+        let stop = start + Duration::from_millis(200);
+        // The first delay is 500ms, the cancellation is seen before it ends
+        assert!(endpoint.reconnect(&|| Instant::now() >= stop).is_none());
+        assert!(start.elapsed() < RECONNECT_DELAY);
+    }
+}
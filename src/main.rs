@@ -3,56 +3,115 @@ mod monitor;
 mod utils;
 
 use colored::*;
-use devices::*;
-use hidapi::{HidApi, HidDevice};
-use monitor::{cpu, gpu};
-use std::ffi::CString;
+use devices::{
+    control::{all_ready, Interruptible, Slot},
+    transport::{Endpoint, HexSink, Transport},
+    hotplug, *,
+};
+use hidapi::{HidApi, HidDevice, HidError};
+use monitor::{cpu, gpu, Sampler};
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fmt::{self, Display, Formatter},
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, Scope},
+    time::{Duration, Instant},
+};
 use std::process::exit;
-use utils::{args::Args, status::*};
+use utils::{
+    args::{Args, DeviceArgs},
+    dbus::{self, BUS_NAME},
+    json::{object, Value},
+    lock::{self, Lock, LockError},
+    log, resume,
+    signals::{self, Signal},
+    socket::{Change, Request, Server},
+    status::*,
+    systemd,
+};
 
 /// Common warning checks for command arguments.
 mod common_warnings {
-    use crate::{devices::Mode, utils::args::Args, warning};
+    use crate::{
+        devices::{Mode, Options},
+        warning,
+    };
 
-    pub fn mode_change(args: &Args) {
-        if args.mode != Mode::Default {
+    pub fn mode_change(options: &Options) {
+        if options.mode != Mode::Default && options.mode != Mode::Off {
             warning!("Display mode cannot be changed, value will be ignored");
         }
     }
 
-    pub fn secondary_mode(args: &Args) {
-        if args.secondary != Mode::Default {
+    pub fn secondary_mode(options: &Options) {
+        if options.secondary != Mode::Default {
             warning!("Secondary display mode is not supported, value will be ignored");
         }
     }
 
-    pub fn fahrenheit(args: &Args) {
-        if args.fahrenheit {
+    pub fn fahrenheit(options: &Options) {
+        if options.fahrenheit {
             warning!("Displaying ˚F is not supported, value will be ignored");
         }
     }
 
-    pub fn alarm_hardcoded(args: &Args) {
-        if args.alarm {
+    pub fn alarm(options: &Options) {
+        if options.alarm {
+            warning!("The alarm is not supported, value will be ignored");
+        }
+    }
+
+    pub fn alarm_hardcoded(options: &Options) {
+        if options.alarm {
             warning!("The alarm is hard-coded in your device, value will be ignored");
         }
     }
 
-    pub fn rotate(args: &Args) {
-        if args.rotate > 0 {
+    pub fn rotate(options: &Options) {
+        if options.rotate > 0 {
             warning!("Display rotation is not supported, value will be ignored");
         }
     }
 }
 
+/// Longest time to wait for the displays to be blanked before exiting.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval of the checks of the displays reported to systemd.
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(500);
+/// Interval of the checks for a resume from suspend.
+const RESUME_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Display module, the way to reach its device, the opened device, and the slot to replace the display module.
+type Connection = (Box<dyn Device + Send>, Endpoint, Box<dyn Transport>, Arc<Slot>);
+
 fn main() {
     let args = Args::read();
-    println!("--- Deepcool Digital Linux ---");
+    log::set_level(args.log_level);
+    // The hex dump is not mixed with the messages
+    if args.output.as_deref() == Some("-") {
+        log::use_stderr();
+    }
+    info!("--- Deepcool Digital Linux ---");
+
+    // Signals are received by their own thread, the other threads must not be interrupted by them
+    signals::block().unwrap_or_else(|err| {
+        error!(format!("Failed to block the signals: {err}"));
+        exit(1);
+    });
 
     /* ================= GPU ================= */
 
     let pci_device = {
-        let gpus = gpu::pci::get_gpu_list();
+        let gpus = gpu::pci::get_gpu_list(&args.root).unwrap_or_else(|err| {
+            error!(err);
+            exit(1);
+        });
 
         if gpus.is_empty() {
             None
@@ -90,138 +149,811 @@ fn main() {
         }
     };
 
-    match cpu::get_name() {
-        Some(name) => println!("CPU MON.: {}", name.bright_green()),
-        None => println!("CPU MON.: {}", "Unknown CPU".bright_green()),
+    match cpu::get_name(&args.root) {
+        Some(name) => info!("CPU MON.: {}", name.bright_green()),
+        None => info!("CPU MON.: {}", "Unknown CPU".bright_green()),
     }
 
     match &pci_device {
-        Some(gpu) => println!("GPU MON.: {}", gpu.name.bright_green()),
-        None => println!("GPU MON.: {}", "none".bright_black()),
+        Some(gpu) => info!("GPU MON.: {}", gpu.name.bright_green()),
+        None => info!("GPU MON.: {}", "none".bright_black()),
     }
 
-    println!("-----");
+    info!("-----");
 
-    /* ================= HID ================= */
-
-    let api = HidApi::new().unwrap_or_else(|e| {
-        error!(e);
+    let gpu = gpu::Gpu::new(&args.root, pci_device).unwrap_or_else(|err| {
+        error!(err);
         exit(1);
     });
+    let sampler = Sampler::new(cpu::Cpu::new(&args.root), gpu);
+
+    /* ================= HID ================= */
+
+    let mut displays: Vec<Connection> = Vec::new();
+
+    if let Some(path) = &args.output {
+        let target = single_target(&args, "--output");
+
+        let Some(family) = find_family(vendor_id_of(target.pid), target.pid) else {
+            error!("No supported device has the specified PID");
+            exit(1);
+        };
+
+        let sink = HexSink::open(path).unwrap_or_else(|err| {
+            error!(format!("Failed to open {path}: {err}"));
+            exit(1);
+        });
+
+        info!("Device found: {}", format!("output={path}").bright_green());
+        let display = launch_family(family, &target.options(), true).unwrap_or_else(|err| {
+            error!(err);
+            exit(1);
+        });
+        let slot = Slot::new(target.pid, None, family, target.options());
+        displays.push((display, Endpoint::Output(path.clone()), Box::new(sink), Arc::new(slot)));
+    } else {
+        let api = HidApi::new().unwrap_or_else(|e| {
+            error!(e);
+            exit(1);
+        });
 
-    let (product_id, forced_device): (u16, Option<HidDevice>) =
         if let Some(path) = &args.hidraw {
-            if args.pid == 0 {
-                error!("--hidraw requires --pid (e.g. --pid 16)");
-                exit(1);
-            }
+            let target = single_target(&args, "--hidraw");
 
             let cpath = CString::new(path.as_str()).unwrap_or_else(|_| {
                 error!("Invalid --hidraw path");
                 exit(1);
             });
 
-            let dev = api
+            let device = api
                 .open_path(cpath.as_c_str())
                 .unwrap_or_else(|_| device_error());
+            let info = device.get_device_info().unwrap_or_else(|_| device_error());
+            let key = lock::key(info.vendor_id(), info.product_id(), info.serial_number(), path);
+            let lock = lock_device(&key, &args).unwrap_or_else(|err| {
+                error!(format!("Cannot drive {path}: {err}"));
+                exit(1);
+            });
 
-            println!("Device found: {}", format!("hidraw={path}").bright_green());
+            info!("Device found: {}", format!("hidraw={path}").bright_green());
 
-            (args.pid, Some(dev))
-        } else {
-            let mut pid = 0u16;
-
-            for d in api.device_list() {
-                if d.vendor_id() == DEFAULT_VENDOR_ID
-                    && (args.pid == 0 || d.product_id() == args.pid)
-                {
-                    pid = d.product_id();
-                    println!(
-                        "Device found: {}",
-                        d.product_string().unwrap_or("Unknown").bright_green()
-                    );
-                    break;
+            let Some(family) = find_family(vendor_id_of(target.pid), target.pid) else {
+                print_unsupported(&device);
+                return;
+            };
+            let display = launch_family(family, &target.options(), true).unwrap_or_else(|err| {
+                error!(err);
+                exit(1);
+            });
+            let slot = Slot::new(target.pid, None, family, target.options());
+            slot.hold(lock);
+            displays.push((display, Endpoint::HidPath(cpath), Box::new(device), Arc::new(slot)));
+        } else if !args.hotplug {
+            let targets = select_targets(&api, &args, &[]);
+
+            if let Some(missing) =
+                args.devices.iter().find(|t| !targets.iter().any(|f| t.matches(f.args.pid, f.serial.as_deref())))
+            {
+                let key = match &missing.serial {
+                    Some(serial) => format!("serial ({serial})"),
+                    None => format!("PID ({})", missing.pid),
+                };
+                error!(format!("No DeepCool device was found with the specified {key}"));
+                exit(1);
+            }
+            if targets.is_empty() {
+                error!("No DeepCool device was found");
+                exit(1);
+            }
+
+            for target in &targets {
+                match open_target(&api, target, &args) {
+                    Ok(Some(connection)) => displays.push(connection),
+                    Ok(None) => (),
+                    Err(OpenError::Hid(_)) => device_error(),
+                    Err(OpenError::Lock(err)) => {
+                        error!(format!("Cannot drive PID {}: {err}", target.args.pid));
+                        exit(1);
+                    }
+                    Err(OpenError::Mode(err)) => {
+                        error!(err);
+                        exit(1);
+                    }
                 }
             }
+        }
+    }
+
+    /* ================= DISPATCH ================= */
+
+    if displays.is_empty() && !args.hotplug {
+        return;
+    }
+    info!("\nPress {} to terminate", "Ctrl+C".bold());
+
+    // Other programs can change the running displays through the control socket
+    let server = match Server::bind(&args.socket) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            warning!(format!("Failed to create the control socket {}: {err}", args.socket.display()));
+            None
+        }
+    };
+
+    // Desktop widgets and scripts can follow the devices and readings on D-Bus
+    let service = args.dbus.and_then(|bus| match dbus::Service::connect(bus) {
+        Ok(service) => Some(service),
+        Err(err) => {
+            warning!(format!("Failed to publish {BUS_NAME} on D-Bus: {err}"));
+            None
+        }
+    });
+
+    let hotplug = args.hotplug;
+    let args = RwLock::new(args);
+    let slots = Mutex::new(Vec::new());
+    // The devices of --hotplug are found by its first scan
+    let started = AtomicBool::new(!hotplug);
+
+    // Every display runs on its own thread, fed by the shared sampler
+    thread::scope(|scope| {
+        for connection in displays {
+            start(scope, connection, &sampler, &slots);
+        }
+        scope.spawn(|| handle_signals(&args, &slots, server.as_ref()));
+        scope.spawn(|| watch_resume(&slots));
+        if let Some(server) = &server {
+            scope.spawn(|| server.serve(|request| control(request, &args, &slots, &sampler)));
+        }
+        if let Some(service) = &service {
+            scope.spawn(|| service.run(&slots, &sampler, update_slots));
+        }
+        if systemd::enabled() {
+            scope.spawn(|| {
+                if let Err(err) = supervise(&slots, &started, hotplug) {
+                    warning!(format!("Failed to notify systemd: {err}"));
+                }
+            });
+        }
+        if hotplug {
+            watch_hotplug(scope, &args, &sampler, &slots, &started);
+        }
+    });
+}
+
+/// Registers the display, and runs it on a new thread until its slot is stopped.
+///
+/// A display that panics is left in the list, flagged, so the watchdog is not fed anymore.
+fn start<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    connection: Connection,
+    sampler: &'scope Sampler,
+    slots: &'scope Mutex<Vec<Arc<Slot>>>,
+) {
+    let (display, endpoint, device, slot) = connection;
+    slots.lock().unwrap().push(slot.clone());
+    scope.spawn(move || {
+        if panic::catch_unwind(AssertUnwindSafe(|| drive(display, &endpoint, device, sampler, &slot))).is_err() {
+            warning!(format!("The display of PID {} stopped after an unexpected error", slot.pid));
+            slot.fail();
+            return;
+        }
+        slot.unlock();
+        slots.lock().unwrap().retain(|s| !Arc::ptr_eq(s, &slot));
+    });
+}
 
-            if pid == 0 {
-                if args.pid > 0 {
-                    error!("No DeepCool device was found with the specified PID");
-                } else {
-                    error!("No DeepCool device was found");
+/// Waits for the signals, and handles them until the process ends.
+fn handle_signals(args: &RwLock<Args>, slots: &Mutex<Vec<Arc<Slot>>>, server: Option<&Server>) {
+    loop {
+        match signals::wait() {
+            Ok(Signal::Reload) => {
+                if let Err(err) = reload(args, slots) {
+                    warning!(format!("Failed to reload the configuration: {err}"));
                 }
+            }
+            Ok(Signal::Terminate) => terminate(slots, server),
+            Err(err) => {
+                // Without this thread the blocked signals could not end the process
+                error!(format!("Failed to receive signals: {err}"));
                 exit(1);
             }
+        }
+    }
+}
+
+/// Stops every display, waits for them to be blanked, and exits.
+fn terminate(slots: &Mutex<Vec<Arc<Slot>>>, server: Option<&Server>) -> ! {
+    info!("\nExiting...");
+    let _ = systemd::notify("STOPPING=1");
+    if let Some(server) = server {
+        server.remove();
+    }
+    for slot in slots.lock().unwrap().iter() {
+        slot.stop(!slot.options().keep_on_exit);
+    }
+
+    // The display threads unregister themselves once they are done
+    let timeout = Instant::now() + EXIT_TIMEOUT;
+    while !slots.lock().unwrap().is_empty() && Instant::now() < timeout {
+        thread::sleep(Duration::from_millis(10));
+    }
+    log::flush_all();
+    exit(0);
+}
+
+/// Tells systemd once every display wrote its first packet and what the displays show,
+/// and feeds its watchdog as long as every display loop is running.
+fn supervise(slots: &Mutex<Vec<Arc<Slot>>>, started: &AtomicBool, hotplug: bool) -> io::Result<()> {
+    let watchdog = systemd::watchdog_timeout();
+    let mut ready = false;
+    let mut status = String::new();
+    let mut fed: Option<Instant> = None;
+    let mut stuck = false;
+    loop {
+        let running = slots.lock().unwrap().clone();
+
+        // With --hotplug, the program is ready without devices once those found at startup are started
+        if !ready && started.load(Ordering::Relaxed) && all_ready(&running, hotplug) {
+            systemd::notify("READY=1")?;
+            ready = true;
+        }
 
-            (pid, None)
+        let current = if running.is_empty() {
+            "Waiting for devices".to_owned()
+        } else {
+            let displays: Vec<String> = running
+                .iter()
+                .map(|slot| {
+                    if slot.has_panicked() {
+                        return format!("PID {} ({}): stopped after an error", slot.pid, slot.family.name());
+                    }
+                    let options = slot.resolved_options();
+                    let mode = match options.secondary {
+                        Mode::Default => options.mode.symbol().to_owned(),
+                        secondary => format!("{} | {}", options.mode.symbol(), secondary.symbol()),
+                    };
+                    format!("PID {} ({}): {mode}", slot.pid, slot.family.name())
+                })
+                .collect();
+            displays.join(", ")
         };
+        if current != status {
+            systemd::notify(&format!("STATUS={current}"))?;
+            status = current;
+        }
 
-    let cpu = cpu::Cpu::new();
-    let gpu = gpu::Gpu::new(pci_device);
+        // A display loop that panicked, or did not beat for half of the timeout, stops the feeding,
+        // so systemd restarts the program
+        if let Some(timeout) = watchdog {
+            match running.iter().find_map(|slot| slot.stalled(timeout / 2).map(|state| (slot, state))) {
+                Some((slot, state)) => {
+                    if !stuck {
+                        warning!(format!("The display of PID {} is {state}", slot.pid));
+                        stuck = true;
+                    }
+                }
+                None if fed.is_none_or(|fed| fed.elapsed() >= timeout / 2) => {
+                    systemd::notify("WATCHDOG=1")?;
+                    fed = Some(Instant::now());
+                    stuck = false;
+                }
+                None => stuck = false,
+            }
+        }
 
-    /* ================= DISPATCH ================= */
+        thread::sleep(SUPERVISE_INTERVAL);
+    }
+}
 
-    match product_id {
-        /* ===== AK400 DIGITAL PRO ===== */
-        16 => {
-            println!("Supported modes: {}", "auto".bold());
-
-            let ak400 =
-                devices::ak400_pro::Display::new(cpu, args.update, args.fahrenheit);
-
-            print_device_status(
-                &devices::ak400_pro::DEFAULT_MODE,
-                None,
-                None,
-                if args.fahrenheit {
-                    TemperatureUnit::Fahrenheit
-                } else {
-                    TemperatureUnit::Celsius
-                },
-                Alarm {
-                    state: AlarmState::Auto,
-                    temp_limit: if args.fahrenheit {
-                        devices::ak400_pro::TEMP_LIMIT_F
-                    } else {
-                        devices::ak400_pro::TEMP_LIMIT_C
-                    },
-                    temp_warning: if args.fahrenheit {
-                        devices::ak400_pro::TEMP_WARNING_F
-                    } else {
-                        devices::ak400_pro::TEMP_WARNING_C
-                    },
-                },
-                args.update,
-            );
+/// Restarts every display after the machine resumed from suspend,
+/// since the devices lost their state and only take packets after the init sequence.
+fn watch_resume(slots: &Mutex<Vec<Arc<Slot>>>) {
+    let mut detector = match resume::Detector::start() {
+        Ok(detector) => detector,
+        Err(err) => {
+            warning!(format!("Failed to read the clocks, a resume from suspend is not detected: {err}"));
+            return;
+        }
+    };
+    loop {
+        thread::sleep(RESUME_INTERVAL);
+        if !detector.resumed() {
+            continue;
+        }
+
+        info!("\nResumed from suspend");
+        for slot in slots.lock().unwrap().iter() {
+            let options = slot.options();
+            match launch_family(slot.family, &options, false) {
+                Ok(display) => slot.replace(display, options),
+                Err(err) => {
+                    warning!(format!("Failed to restart the display of PID {}: {err}", slot.pid));
+                }
+            }
+        }
+    }
+}
 
-            common_warnings::mode_change(&args);
-            common_warnings::secondary_mode(&args);
-            common_warnings::alarm_hardcoded(&args);
-            common_warnings::rotate(&args);
+/// Reads the configuration again, and replaces the display modules whose options changed.
+fn reload(args: &RwLock<Args>, slots: &Mutex<Vec<Arc<Slot>>>) -> Result<(), String> {
+    let reloaded = Args::reload()?;
+    info!("\nConfiguration reloaded");
+
+    for slot in slots.lock().unwrap().iter() {
+        let target = reloaded
+            .devices
+            .iter()
+            .find(|t| t.matches(slot.pid, slot.serial.as_deref()))
+            .unwrap_or(&reloaded.defaults);
+        let options = target.options();
+        // Unchanged displays keep running undisturbed
+        if options == slot.options() {
+            continue;
+        }
 
-            if let Some(dev) = forced_device {
-                ak400.run_device(dev);
-            } else {
-                ak400.run(&api, DEFAULT_VENDOR_ID, product_id);
+        info!("Device updated: {}", format!("PID {}", slot.pid).bright_green());
+        match launch_family(slot.family, &options, false) {
+            Ok(display) => slot.replace(display, options),
+            Err(err) => {
+                warning!(format!("{err}, the previous settings are kept"));
             }
         }
+    }
 
-        /* ===== UNSUPPORTED ===== */
-        _ => {
-            println!("Device not yet supported!");
+    *args.write().unwrap() = reloaded;
+    Ok(())
+}
 
-            let dev = api
-                .open(DEFAULT_VENDOR_ID, product_id)
-                .unwrap_or_else(|_| device_error());
+/// Answers a request of the control socket.
+fn control(
+    request: Request,
+    args: &RwLock<Args>,
+    slots: &Mutex<Vec<Arc<Slot>>>,
+    sampler: &Sampler,
+) -> Result<Value, String> {
+    match request {
+        Request::Status => Ok(device_status(slots, sampler)),
+        Request::Set(change) => change_settings(&change, slots).map(|_| Value::Null),
+        Request::Reload => reload(args, slots).map(|_| Value::Null),
+        Request::Release(key) => release(&key, slots).map(|_| Value::Null),
+    }
+}
+
+/// Stops driving the device with the lock, so the instance that asked for it can take it over.
+fn release(key: &str, slots: &Mutex<Vec<Arc<Slot>>>) -> Result<(), String> {
+    let slots = slots.lock().unwrap();
+    let Some(slot) = slots.iter().find(|slot| slot.holds(key)) else {
+        return Err(format!("no running device has the lock {key}"));
+    };
+    info!("Device released: {}", format!("PID {}", slot.pid).bright_green());
+    // The other instance initializes the display again
+    slot.stop(false);
+    Ok(())
+}
+
+/// Returns the active settings of the running devices, and the last readings.
+fn device_status(slots: &Mutex<Vec<Arc<Slot>>>, sampler: &Sampler) -> Value {
+    let devices = slots
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|slot| {
+            let capabilities = slot.family.capabilities();
+            let options = slot.resolved_options();
+            let alarm = match capabilities.alarm {
+                AlarmSupport::Configurable => Some(options.alarm),
+                AlarmSupport::HardCoded => Some(true),
+                AlarmSupport::NotSupported => None,
+            };
+            object([
+                ("pid", slot.pid.into()),
+                ("serial", slot.serial.clone().into()),
+                ("name", slot.family.name().into()),
+                ("mode", options.mode.symbol().into()),
+                ("secondary", Some(options.secondary).filter(|&s| s != Mode::Default).map(|s| s.symbol()).into()),
+                ("rotate", Some(options.rotate).filter(|_| capabilities.rotate).into()),
+                ("alarm", alarm.into()),
+                ("fahrenheit", (options.fahrenheit && capabilities.fahrenheit).into()),
+                ("update", (options.update.as_millis() as u64).into()),
+            ])
+        })
+        .collect();
+
+    let sample = sampler.last();
+    let temperature = |celsius: f32| Value::Number((celsius as f64 * 10.0).round() / 10.0);
+    object([
+        ("devices", Value::Array(devices)),
+        (
+            "readings",
+            object([
+                ("cpu_usage", sample.cpu_usage.into()),
+                ("cpu_temp", temperature(sample.cpu_temp)),
+                ("cpu_power", sample.cpu_power.into()),
+                ("cpu_frequency", sample.cpu_frequency.into()),
+                ("gpu_usage", sample.gpu_usage.into()),
+                ("gpu_temp", temperature(sample.gpu_temp)),
+                ("gpu_power", sample.gpu_power.into()),
+                ("gpu_frequency", sample.gpu_frequency.into()),
+            ]),
+        ),
+    ])
+}
+
+/// Applies the settings received on the control socket to the selected devices.
+fn change_settings(change: &Change, slots: &Mutex<Vec<Arc<Slot>>>) -> Result<(), String> {
+    let slots = slots.lock().unwrap();
+    let targets: Vec<&Arc<Slot>> = slots.iter().filter(|s| change.pid.is_none_or(|pid| pid == s.pid)).collect();
+    if targets.is_empty() {
+        return Err(match change.pid {
+            Some(pid) => format!("no running device has PID {pid}"),
+            None => "no device is running".to_owned(),
+        });
+    }
+    update_slots(change, &targets)
+}
+
+/// Restarts the displays with the changed settings.
+///
+/// Nothing is changed unless every device supports the new settings.
+fn update_slots(change: &Change, targets: &[&Arc<Slot>]) -> Result<(), String> {
+    // Step 1: verify the new settings of every device
+    let mut updates = Vec::new();
+    for &slot in targets {
+        let capabilities = slot.family.capabilities();
+        let mut options = slot.options();
+        if let Some(mode) = change.mode {
+            if mode != Mode::Off && capabilities.modes.is_empty() {
+                return Err(format!("the display mode of PID {} cannot be changed", slot.pid));
+            }
+            options.mode = mode;
+        }
+        if let Some(secondary) = change.secondary {
+            if capabilities.secondary.is_empty() {
+                return Err(format!("PID {} has no secondary display", slot.pid));
+            }
+            options.secondary = secondary;
+        }
+        if let Some(rotate) = change.rotate {
+            if !capabilities.rotate {
+                return Err(format!("PID {} does not support display rotation", slot.pid));
+            }
+            options.rotate = rotate;
+        }
+        if let Some(alarm) = change.alarm {
+            if capabilities.alarm != AlarmSupport::Configurable {
+                return Err(format!("the alarm of PID {} cannot be changed", slot.pid));
+            }
+            options.alarm = alarm;
+        }
+        slot.family.verify(&options).map_err(|err| format!("PID {}: {err}", slot.pid))?;
+        updates.push((slot, options));
+    }
+
+    // Step 2: restart the displays whose settings changed
+    for (slot, options) in updates {
+        if options == slot.options() {
+            continue;
+        }
+        info!("Device updated: {}", format!("PID {}", slot.pid).bright_green());
+        let display = launch_family(slot.family, &options, false).map_err(|err| err.to_string())?;
+        slot.replace(display, options);
+    }
+    Ok(())
+}
+
+/// Runs the display loop, and reconnects to the device whenever it gets lost until the slot is stopped.
+///
+/// The loop is restarted on the same device when the display module is replaced.
+fn drive(
+    mut display: Box<dyn Device + Send>,
+    endpoint: &Endpoint,
+    device: Box<dyn Transport>,
+    sampler: &Sampler,
+    slot: &Slot,
+) {
+    let mut device = Interruptible::new(device, slot);
+    let mut _demand = sampler.subscribe(display.fields());
+    loop {
+        let result = display.run(&device, sampler);
+        if let Some(replacement) = slot.take_replacement() {
+            display = replacement;
+            _demand = sampler.subscribe(display.fields());
+            continue;
+        }
+        if slot.is_stopped() {
+            // Leave the display in a defined state before the program exits
+            if slot.blanks() {
+                if let Err(err) = display.blank(device.device.as_ref()) {
+                    warning!(format!("Failed to blank the display ({endpoint}): {err}"));
+                }
+            }
+            return;
+        }
+        let Err(err) = result else {
+            return;
+        };
+
+        // Close the handle before looking for the device again
+        drop(device);
+        warning!(format!("Lost connection to the device ({endpoint}): {err}"));
+
+        let Some(transport) = endpoint.reconnect(&|| {
+            slot.beat();
+            slot.is_stopped()
+        }) else {
+            return;
+        };
+        device = Interruptible::new(transport, slot);
+
+        info!("Device reconnected: {}", endpoint.to_string().bright_green());
+    }
+}
+
+/// Drives the selected devices that are connected now or plugged in later, and drops them when they are removed.
+fn watch_hotplug<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    args: &'scope RwLock<Args>,
+    sampler: &'scope Sampler,
+    slots: &'scope Mutex<Vec<Arc<Slot>>>,
+    started: &AtomicBool,
+) {
+    // Subscribe before the first scan, so no device can be missed in between
+    let monitor = hotplug::Monitor::new().unwrap_or_else(|err| {
+        error!(format!("Failed to listen to udev events: {err}"));
+        exit(1);
+    });
+
+    // Attached devices by their hidraw name, with their slot if they are supported
+    let mut attached: HashMap<String, (DeviceId, Option<Arc<Slot>>)> = HashMap::new();
+    let mut event = None;
+    loop {
+        match event {
+            // Look for new devices at startup, and whenever a hidraw node appears
+            None | Some(hotplug::Event::Add) => match HidApi::new() {
+                Ok(api) => {
+                    let busy: Vec<DeviceId> = attached.values().map(|(id, _)| id.clone()).collect();
+                    let args = args.read().unwrap();
+                    let targets = select_targets(&api, &args, &busy);
+                    for target in targets {
+                        let slot = match open_target(&api, &target, &args) {
+                            Ok(Some(connection)) => {
+                                let slot = connection.3.clone();
+                                start(scope, connection, sampler, slots);
+                                Some(slot)
+                            }
+                            // Unsupported devices are kept as well, so they are only reported once
+                            Ok(None) => None,
+                            Err(OpenError::Hid(err)) => {
+                                warning!(format!("Failed to open the device (PID {}): {err}", target.args.pid));
+                                continue;
+                            }
+                            // Tried again when a device is plugged in
+                            Err(OpenError::Lock(err)) => {
+                                warning!(format!("Cannot drive PID {}: {err}", target.args.pid));
+                                continue;
+                            }
+                            // The other displays keep running
+                            Err(OpenError::Mode(err)) => {
+                                warning!(format!("Cannot drive PID {}: {err}", target.args.pid));
+                                continue;
+                            }
+                        };
+                        attached.insert(target.hidraw, ((target.args.pid, target.serial), slot));
+                    }
+                }
+                Err(err) => {
+                    warning!(format!("Failed to enumerate the devices: {err}"));
+                }
+            },
+            Some(hotplug::Event::Remove(name)) => {
+                if let Some((_, slot)) = attached.remove(&name) {
+                    info!("Device removed: {}", name.bright_black());
+                    if let Some(slot) = slot {
+                        slot.stop(false);
+                        // Its display loop is not there to remove it anymore
+                        if slot.has_panicked() {
+                            slots.lock().unwrap().retain(|s| !Arc::ptr_eq(s, &slot));
+                        }
+                    }
+                }
+            }
+        }
+        started.store(true, Ordering::Relaxed);
+
+        event = match monitor.next_event() {
+            Ok(event) => Some(event),
+            Err(err) => {
+                // Events may have been lost, look for new devices again
+                warning!(format!("Failed to receive udev events: {err}"));
+                None
+            }
+        };
+    }
+}
 
-            let info = dev.get_device_info().unwrap();
+/// Connected device selected to be driven.
+struct Target {
+    vid: u16,
+    serial: Option<String>,
+    /// Name of the hidraw node, like `hidraw3`.
+    hidraw: String,
+    args: DeviceArgs,
+}
+
+/// Product ID and serial number that tell the devices apart.
+type DeviceId = (u16, Option<String>);
+
+/// Finds the connected devices to drive, skipping the ones that are already driven.
+fn select_targets(api: &HidApi, args: &Args, busy: &[DeviceId]) -> Vec<Target> {
+    let mut targets: Vec<Target> = Vec::new();
+    for d in api.device_list() {
+        let (vid, pid) = (d.vendor_id(), d.product_id());
+        let serial = d.serial_number().filter(|s| !s.is_empty()).map(str::to_owned);
+        // Devices with several interfaces are listed more than once
+        let id = (pid, serial);
+        if !is_deepcool(vid, pid)
+            || busy.contains(&id)
+            || targets.iter().any(|t| t.args.pid == pid && t.serial == id.1)
+        {
+            continue;
+        }
+        let (pid, serial) = id;
+        let target = match args.devices.iter().find(|t| t.matches(pid, serial.as_deref())) {
+            Some(target) => DeviceArgs { pid, ..target.clone() },
+            None if args.all || (args.devices.is_empty() && busy.is_empty() && targets.is_empty()) => {
+                DeviceArgs { pid, ..args.defaults.clone() }
+            }
+            None => continue,
+        };
+        info!(
+            "Device found: {}",
+            d.product_string().unwrap_or("Unknown").bright_green()
+        );
+        let path = d.path().to_string_lossy();
+        let hidraw = path.rsplit('/').next().unwrap_or(&path).to_owned();
+        targets.push(Target { vid, serial, hidraw, args: target });
+    }
+    targets
+}
+
+/// Reasons a selected device cannot be driven.
+enum OpenError {
+    Hid(HidError),
+    Lock(LockError),
+    /// The device does not support the selected options.
+    Mode(ModeError),
+}
+
+impl Display for OpenError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OpenError::Hid(err) => write!(f, "{err}"),
+            OpenError::Lock(err) => write!(f, "{err}"),
+            OpenError::Mode(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Takes the lock of the device, from the running instance that holds it with `--takeover`.
+fn lock_device(key: &str, args: &Args) -> Result<Lock, LockError> {
+    if args.takeover {
+        Lock::take_over(key, &args.socket)
+    } else {
+        Lock::acquire(key, &args.socket)
+    }
+}
+
+/// Locks and opens the selected device, and creates its display module if it is supported.
+fn open_target(api: &HidApi, target: &Target, args: &Args) -> Result<Option<Connection>, OpenError> {
+    let key = lock::key(target.vid, target.args.pid, target.serial.as_deref(), &target.hidraw);
+    let lock = lock_device(&key, args).map_err(OpenError::Lock)?;
+    let device = match &target.serial {
+        Some(serial) => api.open_serial(target.vid, target.args.pid, serial),
+        None => api.open(target.vid, target.args.pid),
+    }
+    .map_err(OpenError::Hid)?;
+
+    let Some(family) = find_family(target.vid, target.args.pid) else {
+        print_unsupported(&device);
+        return Ok(None);
+    };
+    let options = target.args.options();
+    let display = launch_family(family, &options, true).map_err(OpenError::Mode)?;
+    let endpoint = Endpoint::Hid { vid: target.vid, pid: target.args.pid, serial: target.serial.clone() };
+    let slot = Slot::new(target.args.pid, target.serial.clone(), family, options);
+    slot.hold(lock);
+    Ok(Some((display, endpoint, Box::new(device), Arc::new(slot))))
+}
 
-            println!("Vendor ID: {}", info.vendor_id());
-            println!("Product ID: {}", info.product_id());
-            println!(
-                "Device: {}",
-                info.product_string().unwrap_or("unknown")
-            );
+/// Returns the only selected device for options that work with a single device.
+fn single_target(args: &Args, option: &str) -> DeviceArgs {
+    if args.hotplug {
+        error!(format!("{option} cannot be used with --hotplug"));
+        exit(1);
+    }
+    match args.devices.as_slice() {
+        [target] if !args.all => target.clone(),
+        _ => {
+            error!(format!("{option} requires exactly one --pid (e.g. --pid 16)"));
+            exit(1);
         }
     }
 }
+
+/// Prints the identifiers of a device that has no display module.
+fn print_unsupported(device: &HidDevice) {
+    info!("Device not yet supported!");
+
+    let info = device.get_device_info().unwrap();
+
+    info!("Vendor ID: {}", info.vendor_id());
+    info!("Product ID: {}", info.product_id());
+    info!(
+        "Device: {}",
+        info.product_string().unwrap_or("unknown")
+    );
+}
+
+/// Creates the display module of the device family.
+///
+/// The supported modes and the device status are only printed the `first` time, not when the display is restarted.
+fn launch_family(family: Family, options: &Options, first: bool) -> Result<Box<dyn Device + Send>, ModeError> {
+    match family {
+        Family::AgSeries => launch::<ag_series::Display>(options, first),
+        Family::AkSeries => launch::<ak_series::Display>(options, first),
+        Family::Ak400Pro => launch::<ak400_pro::Display>(options, first),
+        Family::Ak620Pro => launch::<ak620_pro::Display>(options, first),
+        Family::ChSeries => launch::<ch_series::Display>(options, first),
+        Family::ChSeriesGen2 => launch::<ch_series_gen2::Display>(options, first),
+        Family::Ch510 => launch::<ch510::Display>(options, first),
+        Family::LdSeries => launch::<ld_series::Display>(options, first),
+        Family::LpSeries => launch::<lp_series::Display>(options, first),
+        Family::LqSeries => launch::<lq_series::Display>(options, first),
+        Family::LsSeries => launch::<ls_series::Display>(options, first),
+    }
+}
+
+/// Verifies the options, prints the device status the `first` time, and creates the display.
+fn launch<D: Device + Send + 'static>(options: &Options, first: bool) -> Result<Box<dyn Device + Send>, ModeError> {
+    let capabilities = D::capabilities();
+
+    let modes = if capabilities.modes.is_empty() {
+        std::slice::from_ref(&capabilities.default_mode)
+    } else {
+        capabilities.modes
+    };
+    if first {
+        info!(
+            "Supported modes: {}",
+            modes.iter().chain([&Mode::Off]).map(|m| m.symbol().bold().to_string()).collect::<Vec<_>>().join(" ")
+        );
+    }
+
+    let verified = D::verify(options)?;
+    let display = D::new(&verified);
+
+    if first {
+        print_device_status(&display.status());
+    }
+
+    if capabilities.modes.is_empty() {
+        common_warnings::mode_change(options);
+    }
+    if capabilities.secondary.is_empty() {
+        common_warnings::secondary_mode(options);
+    }
+    if !capabilities.fahrenheit {
+        common_warnings::fahrenheit(options);
+    }
+    match capabilities.alarm {
+        AlarmSupport::Configurable => (),
+        AlarmSupport::HardCoded => common_warnings::alarm_hardcoded(options),
+        AlarmSupport::NotSupported => common_warnings::alarm(options),
+    }
+    if !capabilities.rotate {
+        common_warnings::rotate(options);
+    }
+
+    Ok(Box::new(display))
+}
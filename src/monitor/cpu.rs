@@ -1,63 +1,62 @@
 //! Reads live CPU data from the Linux kernel.
 
-use crate::{error, warning};
+use super::MonitorError;
+use crate::warning;
 use cpu_monitor::CpuInstant;
 use std::{
     fs::{read_dir, read_to_string, File},
     io::{BufRead, BufReader},
-    process::exit,
+    path::{Path, PathBuf},
 };
 
+const RAPL_DIR: &str = "sys/class/powercap/intel-rapl/intel-rapl:0";
+
 pub struct Cpu {
     temp_sensor: Option<String>,
     rapl_max_uj: u64,
+    energy_file: PathBuf,
+    cpuinfo_file: PathBuf,
 }
 
 impl Cpu {
-    pub fn new() -> Self {
+    /// Looks for the sensors under the root of the file system (`/` by default).
+    pub fn new(root: &Path) -> Self {
         Self {
-            temp_sensor: find_temp_sensor(),
-            rapl_max_uj: get_max_energy(),
+            temp_sensor: find_temp_sensor(root),
+            rapl_max_uj: get_max_energy(root),
+            energy_file: root.join(RAPL_DIR).join("energy_uj"),
+            cpuinfo_file: root.join("proc/cpuinfo"),
         }
     }
 
     /// Warn once if temperature sensor is missing.
     pub fn warn_temp(&self) {
         if self.temp_sensor.is_none() {
-            warning!("No supported CPU temperature sensor was found");
-            eprintln!("         CPU temperature will not be displayed, and alarm will be disabled.");
-            eprintln!("         Supported kernel modules: asusec, coretemp, k10temp, zenpower.");
+            warning!(
+                "No supported CPU temperature sensor was found\n\
+                 CPU temperature will not be displayed, and alarm will be disabled.\n\
+                 Supported kernel modules: asusec, coretemp, k10temp, zenpower."
+            );
         }
     }
 
     /// Warn once if RAPL is missing.
     pub fn warn_rapl(&self) {
         if self.rapl_max_uj == 0 {
-            warning!("RAPL module was not found");
-            eprintln!("         CPU power consumption will not be displayed.");
+            warning!("RAPL module was not found\nCPU power consumption will not be displayed.");
         }
     }
 
-    /// Returns CPU temperature in °C or °F. Safe fallback: 0.
-    pub fn get_temp(&self, fahrenheit: bool) -> u8 {
+    /// Returns CPU temperature in °C. Returns 0 if no sensor was found.
+    pub fn get_temp(&self) -> Result<f32, MonitorError> {
         let Some(sensor) = &self.temp_sensor else {
-            return 0;
-        };
-
-        let Ok(data) = read_to_string(sensor) else {
-            error!("Failed to get CPU temperature");
-            return 0;
-        };
-
-        let Ok(mut temp) = data.trim_end().parse::<u32>() else {
-            return 0;
+            return Ok(0.0);
         };
 
-        if fahrenheit {
-            temp = temp * 9 / 5 + 32_000;
-        }
+        let data = read_to_string(sensor).map_err(|err| MonitorError::Read("CPU temperature", err))?;
+        let temp = data.trim_end().parse::<u32>().map_err(|_| MonitorError::Parse("CPU temperature"))?;
 
-        ((temp as f32) / 1000.0).round() as u8
+        Ok(temp as f32 / 1000.0)
     }
 
     /// Reads CPU energy (µJ). Safe fallback: 0.
@@ -66,9 +65,7 @@ impl Cpu {
             return 0;
         }
 
-        if let Ok(data) =
-            read_to_string("/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj")
-        {
+        if let Ok(data) = read_to_string(&self.energy_file) {
             return data.trim_end().parse::<u64>().unwrap_or(0);
         }
 
@@ -78,13 +75,8 @@ impl Cpu {
     /// Calculates CPU power in Watts. Safe fallback: 0.
     ///
     /// Formula: `W = ΔµJ / (Δms * 1000)`
-    pub fn get_power(&self, initial_energy: u64, delta_millisec: u64) -> u16 {
-        if self.rapl_max_uj == 0 || initial_energy == 0 || delta_millisec == 0 {
-            return 0;
-        }
-
-        let current_energy = self.read_energy();
-        if current_energy == 0 {
+    pub fn get_power(&self, initial_energy: u64, current_energy: u64, delta_millisec: u64) -> u16 {
+        if self.rapl_max_uj == 0 || initial_energy == 0 || current_energy == 0 || delta_millisec == 0 {
             return 0;
         }
 
@@ -100,48 +92,45 @@ impl Cpu {
             .min(999.0) as u16
     }
 
-    /// Reads CPU instant (usage baseline). Fatal if system API is broken.
-    pub fn read_instant(&self) -> CpuInstant {
-        CpuInstant::now().unwrap_or_else(|_| {
-            error!("Failed to get CPU usage");
-            exit(1);
-        })
+    /// Reads CPU instant (usage baseline).
+    pub fn read_instant(&self) -> Result<CpuInstant, MonitorError> {
+        CpuInstant::now().map_err(|err| MonitorError::Read("CPU usage", err))
     }
 
-    /// Returns CPU usage 0–100%.
-    pub fn get_usage(&self, initial_instant: CpuInstant) -> u8 {
-        let usage = (self.read_instant() - initial_instant).non_idle() * 100.0;
+    /// Returns CPU usage 0–100% between the two instants.
+    pub fn get_usage(&self, initial_instant: CpuInstant, current_instant: CpuInstant) -> u8 {
+        let usage = (current_instant - initial_instant).non_idle() * 100.0;
         usage.round().clamp(0.0, 100.0) as u8
     }
 
-    /// Returns highest core frequency in MHz. Fatal only if `/proc/cpuinfo` is broken.
-   pub fn get_frequency(&self) -> u16 {
-       let cpuinfo = read_to_string("/proc/cpuinfo").unwrap_or_else(|_| {
-           error!("Failed to get CPU clock");
-           exit(1);
-       });
+    /// Returns highest core frequency in MHz.
+    pub fn get_frequency(&self) -> Result<u16, MonitorError> {
+        let cpuinfo = read_to_string(&self.cpuinfo_file).map_err(|err| MonitorError::Read("CPU clock", err))?;
 
-       let mut highest: f32 = 0.0;
+        let mut highest: f32 = 0.0;
 
-       for line in cpuinfo.lines() {
-           if let Some(rest) = line.strip_prefix("cpu MHz") {
-               if let Some(v) = rest.split(':').nth(1) {
-                   if let Ok(mhz) = v.trim().parse::<f32>() {
-                       highest = highest.max(mhz);
-                   }
-               }
-           }
-       }
+        for line in cpuinfo.lines() {
+            if let Some(rest) = line.strip_prefix("cpu MHz") {
+                if let Some(v) = rest.split(':').nth(1) {
+                    if let Ok(mhz) = v.trim().parse::<f32>() {
+                        highest = highest.max(mhz);
+                    }
+                }
+            }
+        }
 
-       highest.round() as u16
-   }
+        Ok(highest.round() as u16)
+    }
 }
 
 /// Finds a supported hwmon temperature sensor.
-fn find_temp_sensor() -> Option<String> {
-    for sensor in read_dir("/sys/class/hwmon").ok()? {
+fn find_temp_sensor(root: &Path) -> Option<String> {
+    for sensor in read_dir(root.join("sys/class/hwmon")).ok()? {
         let path = sensor.ok()?.path();
-        let name = read_to_string(path.join("name")).ok()?;
+        // Some entries have no name, they are skipped
+        let Ok(name) = read_to_string(path.join("name")) else {
+            continue;
+        };
         if ["asusec", "coretemp", "k10temp", "zenpower"].contains(&name.trim()) {
             return Some(path.join("temp1_input").to_string_lossy().to_string());
         }
@@ -150,21 +139,113 @@ fn find_temp_sensor() -> Option<String> {
 }
 
 /// Reads max RAPL energy range (µJ). Returns 0 if unavailable.
-fn get_max_energy() -> u64 {
-    read_to_string("/sys/class/powercap/intel-rapl/intel-rapl:0/max_energy_range_uj")
+fn get_max_energy(root: &Path) -> u64 {
+    read_to_string(root.join(RAPL_DIR).join("max_energy_range_uj"))
         .ok()
         .and_then(|s| s.trim().parse::<u64>().ok())
         .unwrap_or(0)
 }
 
 /// Gets CPU model name.
-pub fn get_name() -> Option<String> {
-    let file = File::open("/proc/cpuinfo").ok()?;
+pub fn get_name(root: &Path) -> Option<String> {
+    let file = File::open(root.join("proc/cpuinfo")).ok()?;
     let reader = BufReader::new(file);
-    for line in reader.lines().flatten() {
+    for line in reader.lines().map_while(Result::ok) {
         if let Some(rest) = line.strip_prefix("model name") {
             return rest.split(':').nth(1).map(|s| s.trim().to_string());
         }
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::fixture::Root;
+
+    #[test]
+    fn k10temp() {
+        let root = Root::cpu("k10temp");
+        let cpu = Cpu::new(root.path());
+        assert_eq!(cpu.get_temp().unwrap(), 45.0);
+        assert_eq!(cpu.get_frequency().unwrap(), 3600);
+    }
+
+    #[test]
+    fn coretemp() {
+        let root = Root::new("coretemp");
+        root.write("sys/class/hwmon/hwmon0/name", "nvme\n")
+            .write("sys/class/hwmon/hwmon0/temp1_input", "38000\n")
+            .write("sys/class/hwmon/hwmon1/temp1_input", "40000\n")
+            .write("sys/class/hwmon/hwmon2/name", "coretemp\n")
+            .write("sys/class/hwmon/hwmon2/temp1_input", "61500\n");
+        let cpu = Cpu::new(root.path());
+        assert_eq!(cpu.get_temp().unwrap(), 61.5);
+
+        // The sensor returns an error while it is not ready
+        root.write("sys/class/hwmon/hwmon2/temp1_input", "\n");
+        assert!(matches!(cpu.get_temp(), Err(MonitorError::Parse(_))));
+    }
+
+    #[test]
+    fn no_temp_sensor() {
+        let root = Root::new("no-sensor");
+        root.write("sys/class/hwmon/hwmon0/name", "nvme\n")
+            .write("sys/class/hwmon/hwmon0/temp1_input", "38000\n");
+        assert_eq!(Cpu::new(root.path()).get_temp().unwrap(), 0.0);
+        // Without hwmon at all
+        assert_eq!(Cpu::new(Root::new("no-hwmon").path()).get_temp().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn rapl() {
+        let root = Root::cpu("rapl");
+        let cpu = Cpu::new(root.path());
+        assert_eq!(cpu.read_energy(), 1_000_000);
+        assert_eq!(cpu.get_power(1_000_000, 31_000_000, 1000), 30);
+        assert_eq!(cpu.get_power(1_000_000, 1_250_000, 100), 3);
+        // The counter wraps around at its range
+        assert_eq!(cpu.get_power(262_143_328_850 - 1_000_000, 2_000_000, 1000), 3);
+        // Missing readings are not turned into power
+        assert_eq!(cpu.get_power(0, 31_000_000, 1000), 0);
+        assert_eq!(cpu.get_power(1_000_000, 31_000_000, 0), 0);
+        assert_eq!(cpu.get_power(0, 100_000_000_000, 1), 0);
+        assert_eq!(cpu.get_power(1, 100_000_000_000, 1), 999);
+    }
+
+    #[test]
+    fn rapl_missing() {
+        let root = Root::new("no-rapl");
+        root.write("proc/cpuinfo", "cpu MHz\t\t: 800.000\n");
+        let cpu = Cpu::new(root.path());
+        assert_eq!(cpu.read_energy(), 0);
+        assert_eq!(cpu.get_power(1_000_000, 31_000_000, 1000), 0);
+
+        // Only the energy counter, without its range
+        root.write("sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj", "1000000\n");
+        let cpu = Cpu::new(root.path());
+        assert_eq!(cpu.read_energy(), 0);
+    }
+
+    #[test]
+    fn frequency() {
+        let root = Root::new("frequency");
+        root.write(
+            "proc/cpuinfo",
+            "processor\t: 0\n\
+             model name\t: AMD Ryzen 7 7800X3D 8-Core Processor\n\
+             cpu MHz\t\t: 3012.412\n\
+             \n\
+             processor\t: 1\n\
+             model name\t: AMD Ryzen 7 7800X3D 8-Core Processor\n\
+             cpu MHz\t\t: 4849.671\n",
+        );
+        let cpu = Cpu::new(root.path());
+        assert_eq!(cpu.get_frequency().unwrap(), 4850);
+        assert_eq!(get_name(root.path()).as_deref(), Some("AMD Ryzen 7 7800X3D 8-Core Processor"));
+
+        let root = Root::new("no-cpuinfo");
+        assert!(matches!(Cpu::new(root.path()).get_frequency(), Err(MonitorError::Read(..))));
+        assert_eq!(get_name(root.path()), None);
+    }
+}
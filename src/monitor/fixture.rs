@@ -0,0 +1,52 @@
+//! Fake roots of the file system, so the sensors can be read from known values in tests.
+
+use std::{
+    env::temp_dir,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Directory standing for `/`, removed when it is dropped.
+pub struct Root {
+    path: PathBuf,
+}
+
+impl Root {
+    /// Creates an empty root, named after the test.
+    pub fn new(name: &str) -> Root {
+        let path = temp_dir().join(format!("deepcool-root-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Root { path }
+    }
+
+    /// Creates a root with the CPU sensors: a k10temp sensor at 45°C, RAPL, and a clock of 3600 MHz.
+    pub fn cpu(name: &str) -> Root {
+        let root = Root::new(name);
+        root.write("sys/class/hwmon/hwmon0/name", "k10temp\n")
+            .write("sys/class/hwmon/hwmon0/temp1_input", "45000\n")
+            .write("sys/class/powercap/intel-rapl/intel-rapl:0/max_energy_range_uj", "262143328850\n")
+            .write("sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj", "1000000\n")
+            .write("proc/cpuinfo", "processor\t: 0\ncpu MHz\t\t: 3600.000\n");
+        root
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the file under the root, creating its directories.
+    pub fn write(&self, path: &str, content: &str) -> &Root {
+        let path = self.path.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        self
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
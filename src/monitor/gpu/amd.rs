@@ -1,7 +1,7 @@
 //! Reads live GPU data from the Linux kernel. Supports both GPUs and iGPUs (APU).
 
-use crate::error;
-use std::{fs::read_dir, fs::read_to_string, process::exit};
+use crate::monitor::MonitorError;
+use std::{fs::read_dir, fs::read_to_string, path::Path};
 
 pub struct Gpu {
     usage_file: String,
@@ -9,78 +9,51 @@ pub struct Gpu {
 }
 
 impl Gpu {
-    pub fn new(pci_address: &str) -> Self {
-        let path = format!("/sys/bus/pci/devices/{pci_address}");
-
-        let usage_file = match find_card(&path) {
-            Some(file) => file,
-            None => {
-                error!(format!("Failed access GPU (AMD) PCI_ADDR={pci_address}"));
-                exit(1);
-            }
-        };
-
-        let hwmon_dir = match find_hwmon_dir(&path) {
-            Some(dir) => dir,
-            None => {
-                error!("Failed to locate GPU temperature sensor (AMD)");
-                exit(1);
-            }
-        };
-
-        Gpu { usage_file, hwmon_dir }
+    pub fn new(root: &Path, pci_address: &str) -> Result<Self, MonitorError> {
+        let path = root.join("sys/bus/pci/devices").join(pci_address).to_string_lossy().into_owned();
+
+        let usage_file = find_card(&path)
+            .ok_or_else(|| MonitorError::Access(format!("Failed access GPU (AMD) PCI_ADDR={pci_address}")))?;
+
+        let hwmon_dir = find_hwmon_dir(&path)
+            .ok_or_else(|| MonitorError::Access("Failed to locate GPU temperature sensor (AMD)".to_owned()))?;
+
+        Ok(Gpu { usage_file, hwmon_dir })
     }
 
-    /// Reads the value of the GPU temperature sensor and calculates it to be `˚C` or `˚F`.
-    pub fn get_temp(&self, fahrenheit: bool) -> u8 {
-        // Read sensor data
-        let data = read_to_string(format!("{}/temp1_input", &self.hwmon_dir)).unwrap_or_else(|_| {
-            error!("Failed to get GPU temperature (AMD)");
-            exit(1);
-        });
-
-        // Calculate temperature
-        let mut temp = data.trim_end().parse::<u32>().unwrap();
-        if fahrenheit {
-            temp = temp * 9 / 5 + 32000
-        }
+    /// Reads the value of the GPU temperature sensor in `˚C`.
+    pub fn get_temp(&self) -> Result<f32, MonitorError> {
+        let temp: u32 = read_value(&format!("{}/temp1_input", &self.hwmon_dir), "GPU temperature (AMD)")?;
 
-        (temp as f32 / 1000.0).round() as u8
+        Ok(temp as f32 / 1000.0)
     }
 
     /// Reads the value of the GPU usage in percentage.
-    pub fn get_usage(&self) -> u8 {
-        let data = read_to_string(&self.usage_file).unwrap_or_else(|_| {
-            error!("Failed to get GPU usage (AMD)");
-            exit(1);
-        });
-
-        data.trim_end().parse::<u8>().unwrap()
+    pub fn get_usage(&self) -> Result<u8, MonitorError> {
+        read_value(&self.usage_file, "GPU usage (AMD)")
     }
 
     /// Reads the value of the GPU power consumption in Watts.
-    pub fn get_power(&self) -> u16 {
-        let data = read_to_string(format!("{}/power1_average", &self.hwmon_dir)).unwrap_or_else(|_| {
-            error!("Failed to get GPU power (AMD)");
-            exit(1);
-        });
-        let power = data.trim_end().parse::<u64>().unwrap();
-
-        (power / 1_000_000) as u16
+    pub fn get_power(&self) -> Result<u16, MonitorError> {
+        let power: u64 = read_value(&format!("{}/power1_average", &self.hwmon_dir), "GPU power (AMD)")?;
+
+        Ok((power / 1_000_000) as u16)
     }
 
     /// Reads the value of the GPU core frequency in MHz.
-    pub fn get_frequency(&self) -> u16 {
-        let data = read_to_string(format!("{}/freq1_input", &self.hwmon_dir)).unwrap_or_else(|_| {
-            error!("Failed to get GPU core frequency (AMD)");
-            exit(1);
-        });
-        let frequency = data.trim_end().parse::<u64>().unwrap();
-
-        (frequency / 1_000_000) as u16
+    pub fn get_frequency(&self) -> Result<u16, MonitorError> {
+        let frequency: u64 = read_value(&format!("{}/freq1_input", &self.hwmon_dir), "GPU core frequency (AMD)")?;
+
+        Ok((frequency / 1_000_000) as u16)
     }
 }
 
+/// Reads and parses the numeric value of a sensor file.
+fn read_value<T: std::str::FromStr>(file: &str, sensor: &'static str) -> Result<T, MonitorError> {
+    let data = read_to_string(file).map_err(|err| MonitorError::Read(sensor, err))?;
+    data.trim_end().parse::<T>().map_err(|_| MonitorError::Parse(sensor))
+}
+
 /// Confirms that the specified path belongs to an AMD GPU and returns the path of the "GPU Usage" file.
 fn find_card(path: &str) -> Option<String> {
     if let Ok(data) = read_to_string(format!("{path}/uevent")) {
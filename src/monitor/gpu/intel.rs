@@ -1,9 +1,9 @@
 //! Reads live GPU data from the Linux kernel.
 
-use crate::error;
+use crate::monitor::MonitorError;
 use std::{
     fs::{read_dir, read_to_string},
-    process::exit,
+    path::Path,
 };
 
 pub struct Gpu {
@@ -12,37 +12,24 @@ pub struct Gpu {
 }
 
 impl Gpu {
-    pub fn new(pci_address: &str) -> Self {
-        let path = format!("/sys/bus/pci/devices/{pci_address}");
-
-        let drm_dir = match find_drm_dir(&path) {
-            Some(dir) => dir,
-            None => {
-                error!(format!("Failed access GPU (Intel) PCI_ADDR={pci_address}"));
-                exit(1);
-            }
-        };
+    pub fn new(root: &Path, pci_address: &str) -> Result<Self, MonitorError> {
+        let path = root.join("sys/bus/pci/devices").join(pci_address).to_string_lossy().into_owned();
 
-        let hwmon_dir = match find_hwmon_dir(&path) {
-            Some(dir) => dir,
-            None => {
-                error!("Failed to locate GPU temperature sensor (Intel)");
-                exit(1);
-            }
-        };
+        let drm_dir = find_drm_dir(&path)
+            .ok_or_else(|| MonitorError::Access(format!("Failed access GPU (Intel) PCI_ADDR={pci_address}")))?;
 
-        Gpu { drm_dir, hwmon_dir }
+        let hwmon_dir = find_hwmon_dir(&path)
+            .ok_or_else(|| MonitorError::Access("Failed to locate GPU temperature sensor (Intel)".to_owned()))?;
+
+        Ok(Gpu { drm_dir, hwmon_dir })
     }
 
     /// Reads GPU temperature (A-series + B-series)
-    pub fn get_temp(&self, fahrenheit: bool) -> u8 {
+    pub fn get_temp(&self) -> Result<f32, MonitorError> {
         // ===== A-series (unchanged) =====
         if let Ok(data) = read_to_string(format!("{}/temp1_input", &self.hwmon_dir)) {
-            let mut temp = data.trim().parse::<u32>().unwrap_or(0);
-            if fahrenheit {
-                temp = temp * 9 / 5 + 32000;
-            }
-            return (temp as f32 / 1000.0).round() as u8;
+            let temp = data.trim().parse::<u32>().unwrap_or(0);
+            return Ok(temp as f32 / 1000.0);
         }
 
         // ===== B-series (pkg temp) =====
@@ -52,21 +39,17 @@ impl Gpu {
 
             if let (Ok(label), Ok(data)) = (label, data) {
                 if label.trim() == "pkg" {
-                    let mut temp = data.trim().parse::<u32>().unwrap_or(0);
-                    if fahrenheit {
-                        temp = temp * 9 / 5 + 32000;
-                    }
-                    return (temp as f32 / 1000.0).round() as u8;
+                    let temp = data.trim().parse::<u32>().unwrap_or(0);
+                    return Ok(temp as f32 / 1000.0);
                 }
             }
         }
 
-        error!("Failed to get GPU temperature");
-        exit(1);
+        Err(MonitorError::Unavailable("GPU temperature (Intel)"))
     }
 
     /// Estimates GPU usage (A-series + B-series)
-    pub fn get_usage(&self) -> u8 {
+    pub fn get_usage(&self) -> Result<u8, MonitorError> {
         // ===== A-series (unchanged) =====
         let cur = read_to_string(format!("{}/device/gt_cur_freq_mhz", &self.drm_dir))
         .ok()
@@ -78,7 +61,7 @@ impl Gpu {
 
         if let (Some(cur), Some(max)) = (cur, max) {
             if max > 0 {
-                return ((cur as f32 / max as f32) * 100.0).round() as u8;
+                return Ok(((cur as f32 / max as f32) * 100.0).round() as u8);
             }
         }
 
@@ -95,34 +78,28 @@ impl Gpu {
 
         if let (Some(cur), Some(max)) = (cur, max) {
             if max > 0 {
-                return ((cur as f32 / max as f32) * 100.0).round() as u8;
+                return Ok(((cur as f32 / max as f32) * 100.0).round() as u8);
             }
         }
 
-        0
+        Ok(0)
     }
 
     /// Reads GPU power in Watts
-    pub fn get_power(&self) -> u16 {
+    pub fn get_power(&self) -> Result<u16, MonitorError> {
         let data = read_to_string(format!("{}/power1_average", &self.hwmon_dir))
         .or_else(|_| read_to_string(format!("{}/power/average", &self.hwmon_dir)))
-        .unwrap_or_else(|_| {
-            error!("Failed to get GPU power");
-            exit(1);
-        });
+        .map_err(|err| MonitorError::Read("GPU power (Intel)", err))?;
 
-        (data.trim().parse::<u64>().unwrap_or(0) / 1_000_000) as u16
+        Ok((data.trim().parse::<u64>().unwrap_or(0) / 1_000_000) as u16)
     }
 
     /// Reads GPU frequency (A-series only)
-    pub fn get_frequency(&self) -> u16 {
+    pub fn get_frequency(&self) -> Result<u16, MonitorError> {
         let data = read_to_string(format!("{}/freq1_input", &self.hwmon_dir))
-        .unwrap_or_else(|_| {
-            error!("Failed to get GPU core frequency");
-            exit(1);
-        });
+        .map_err(|err| MonitorError::Read("GPU core frequency (Intel)", err))?;
 
-        (data.trim().parse::<u64>().unwrap_or(0) / 1_000_000) as u16
+        Ok((data.trim().parse::<u64>().unwrap_or(0) / 1_000_000) as u16)
     }
 }
 
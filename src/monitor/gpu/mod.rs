@@ -5,7 +5,11 @@ mod intel;
 mod nvidia;
 pub mod pci;
 
-use crate::{monitor::gpu::pci::PciDevice, warning};
+use crate::{
+    monitor::{gpu::pci::PciDevice, MonitorError},
+    warning,
+};
+use std::path::Path;
 
 pub enum Gpu {
     Amd(amd::Gpu),
@@ -15,57 +19,56 @@ pub enum Gpu {
 }
 
 impl Gpu {
-    pub fn new(pci_device: Option<PciDevice>) -> Self {
-        match pci_device {
+    pub fn new(root: &Path, pci_device: Option<PciDevice>) -> Result<Self, MonitorError> {
+        Ok(match pci_device {
             Some(gpu) => match gpu.vendor {
-                pci::Vendor::Amd => Gpu::Amd(amd::Gpu::new(&gpu.address)),
-                pci::Vendor::Intel => Gpu::Intel(intel::Gpu::new(&gpu.address)),
-                pci::Vendor::Nvidia => Gpu::Nvidia(nvidia::Gpu::new(&gpu.address)),
+                pci::Vendor::Amd => Gpu::Amd(amd::Gpu::new(root, &gpu.address)?),
+                pci::Vendor::Intel => Gpu::Intel(intel::Gpu::new(root, &gpu.address)?),
+                pci::Vendor::Nvidia => Gpu::Nvidia(nvidia::Gpu::new(&gpu.address)?),
             }
             None => Gpu::None,
-        }
+        })
     }
 
     pub fn warn_missing(&self) {
         if matches!(self, Gpu::None) {
-            warning!("No supported GPU was found");
-            eprintln!("         GPU information will not be displayed.");
+            warning!("No supported GPU was found\nGPU information will not be displayed.");
         }
     }
 
-    pub fn get_temp(&self, fahrenheit: bool) -> u8 {
+    pub fn get_temp(&self) -> Result<f32, MonitorError> {
         match &self {
-            Gpu::Amd(amd) => amd.get_temp(fahrenheit),
-            Gpu::Intel(intel) => intel.get_temp(fahrenheit),
-            Gpu::Nvidia(nvidia) => nvidia.get_temp(fahrenheit),
-            Gpu::None => 0,
+            Gpu::Amd(amd) => amd.get_temp(),
+            Gpu::Intel(intel) => intel.get_temp(),
+            Gpu::Nvidia(nvidia) => nvidia.get_temp(),
+            Gpu::None => Ok(0.0),
         }
     }
 
-    pub fn get_usage(&self) -> u8 {
+    pub fn get_usage(&self) -> Result<u8, MonitorError> {
         match &self {
             Gpu::Amd(amd) => amd.get_usage(),
             Gpu::Intel(intel) => intel.get_usage(),
             Gpu::Nvidia(nvidia) => nvidia.get_usage(),
-            Gpu::None => 0,
+            Gpu::None => Ok(0),
         }
     }
 
-    pub fn get_power(&self) -> u16 {
+    pub fn get_power(&self) -> Result<u16, MonitorError> {
         match &self {
             Gpu::Amd(amd) => amd.get_power(),
             Gpu::Intel(intel) => intel.get_power(),
             Gpu::Nvidia(nvidia) => nvidia.get_power(),
-            Gpu::None => 0,
+            Gpu::None => Ok(0),
         }
     }
 
-    pub fn get_frequency(&self) -> u16 {
+    pub fn get_frequency(&self) -> Result<u16, MonitorError> {
         match &self {
             Gpu::Amd(amd) => amd.get_frequency(),
             Gpu::Intel(intel) => intel.get_frequency(),
             Gpu::Nvidia(nvidia) => nvidia.get_frequency(),
-            Gpu::None => 0,
+            Gpu::None => Ok(0),
         }
     }
 }
@@ -1,8 +1,8 @@
 //! Reads live GPU data from the `libnvidia-ml` shared library.
 
-use crate::error;
+use crate::monitor::MonitorError;
 use libloading::{Library, Symbol};
-use std::{path::Path, process::exit, ptr::null_mut};
+use std::{path::Path, ptr::null_mut};
 
 type NvmlInit = unsafe extern "C" fn() -> u16;
 type NvmlDeviceGetHandleByPciBusId = unsafe extern "C" fn(pci_bus_id: *const u8, device: *mut *mut u8) -> u16;
@@ -37,103 +37,111 @@ pub struct Gpu {
     device: *mut u8,
 }
 
+// NVML is thread-safe, the device handle can be shared between the display threads
+unsafe impl Send for Gpu {}
+unsafe impl Sync for Gpu {}
+
 impl Gpu {
     /// Initializes NVML with the GPU specified by its PCI address.
-    pub fn new(pci_address: &str) -> Self {
+    pub fn new(pci_address: &str) -> Result<Self, MonitorError> {
         unsafe {
             // Try to open `libnvidia-ml.so` directly, on error use `LIB_PATHS` as fallback
-            let lib = Library::new("libnvidia-ml.so").unwrap_or_else(|_| {
-                LIB_PATHS
-                    .iter()
-                    .find_map(|path| {
+            let lib = Library::new("libnvidia-ml.so")
+                .ok()
+                .or_else(|| {
+                    LIB_PATHS.iter().find_map(|path| {
                         if Path::new(path).exists() {
                             Library::new(*path).ok()
                         } else {
                             None
                         }
                     })
-                    .unwrap_or_else(|| {
-                        error!("NVIDIA GPU library was not found");
-                        exit(1);
-                    })
-            });
+                })
+                .ok_or_else(|| MonitorError::Access("NVIDIA GPU library was not found".to_owned()))?;
 
             // Initialize the library
-            let init: Symbol<NvmlInit> = lib.get(b"nvmlInit_v2").unwrap();
+            let init: Symbol<NvmlInit> = lib.get(b"nvmlInit_v2").map_err(|_| init_error())?;
             if init() != 0 {
-                error!("Failed to initialize NVML");
-                exit(1);
+                return Err(init_error());
             }
 
             // Get device handle at the specified PCI address
             let mut device: *mut u8 = null_mut();
-            let get_handle: Symbol<NvmlDeviceGetHandleByPciBusId> = lib.get(b"nvmlDeviceGetHandleByPciBusId_v2").unwrap();
+            let get_handle: Symbol<NvmlDeviceGetHandleByPciBusId> =
+                lib.get(b"nvmlDeviceGetHandleByPciBusId_v2").map_err(|_| init_error())?;
             if get_handle(pci_address.as_ptr(), &mut device as *mut *mut u8) != 0 {
-                error!(format!("Failed access GPU (NVIDIA) PCI_ADDR={pci_address}"));
-                exit(1);
+                return Err(MonitorError::Access(format!("Failed access GPU (NVIDIA) PCI_ADDR={pci_address}")));
             }
 
-            Gpu { lib, device }
+            Ok(Gpu { lib, device })
         }
     }
 
-    /// Reads the GPU temperature from the API and calculates it to be `˚C` or `˚F`.
-    pub fn get_temp(&self, fahrenheit: bool) -> u8 {
+    /// Reads the GPU temperature from the API in `˚C`.
+    pub fn get_temp(&self) -> Result<f32, MonitorError> {
+        const SENSOR: &str = "GPU temperature (NVIDIA)";
         let mut temp: u32 = 0;
         unsafe {
-            let get_temp: Symbol<NvmlDeviceGetTemperature> = self.lib.get(b"nvmlDeviceGetTemperature").unwrap();
-            if get_temp(self.device, 0, &mut temp as *mut u32) != 0 {
-                error!("Failed to get GPU temperature (NVIDIA)");
-                exit(1);
+            let get_temp: Symbol<NvmlDeviceGetTemperature> =
+                self.lib.get(b"nvmlDeviceGetTemperature").map_err(|_| MonitorError::Unavailable(SENSOR))?;
+            let code = get_temp(self.device, 0, &mut temp as *mut u32);
+            if code != 0 {
+                return Err(MonitorError::Nvml(SENSOR, code));
             }
         }
-        if fahrenheit {
-            temp = (temp as f32 * 9.0 / 5.0 + 32.0).round() as u32;
-        }
 
-        temp as u8
+        Ok(temp as f32)
     }
 
     /// Reads the GPU utilization from the API.
-    pub fn get_usage(&self) -> u8 {
+    pub fn get_usage(&self) -> Result<u8, MonitorError> {
+        const SENSOR: &str = "GPU usage (NVIDIA)";
         let mut utilization = Utilization { gpu: 0, memory: 0 };
         unsafe {
             let get_usage: Symbol<NvmlDeviceGetUtilizationRates> =
-                self.lib.get(b"nvmlDeviceGetUtilizationRates").unwrap();
-            if get_usage(self.device, &mut utilization as *mut Utilization) != 0 {
-                error!("Failed to get GPU usage (NVIDIA)");
-                exit(1);
+                self.lib.get(b"nvmlDeviceGetUtilizationRates").map_err(|_| MonitorError::Unavailable(SENSOR))?;
+            let code = get_usage(self.device, &mut utilization as *mut Utilization);
+            if code != 0 {
+                return Err(MonitorError::Nvml(SENSOR, code));
             }
         }
 
-        utilization.gpu as u8
+        Ok(utilization.gpu as u8)
     }
 
     /// Reads the GPU power consumption from the API.
-    pub fn get_power(&self) -> u16 {
+    pub fn get_power(&self) -> Result<u16, MonitorError> {
+        const SENSOR: &str = "GPU power (NVIDIA)";
         let mut power: u32 = 0;
         unsafe {
-            let get_power: Symbol<NvmlDeviceGetPowerUsage> = self.lib.get(b"nvmlDeviceGetPowerUsage").unwrap();
-            if get_power(self.device, &mut power as *mut u32) != 0 {
-                error!("Failed to get GPU power (NVIDIA)");
-                exit(1);
+            let get_power: Symbol<NvmlDeviceGetPowerUsage> =
+                self.lib.get(b"nvmlDeviceGetPowerUsage").map_err(|_| MonitorError::Unavailable(SENSOR))?;
+            let code = get_power(self.device, &mut power as *mut u32);
+            if code != 0 {
+                return Err(MonitorError::Nvml(SENSOR, code));
             }
         }
 
-        (power as f32 / 1000.0).round() as u16
+        Ok((power as f32 / 1000.0).round() as u16)
     }
 
     /// Reads the GPU core frequency from the API.
-    pub fn get_frequency(&self) -> u16 {
+    pub fn get_frequency(&self) -> Result<u16, MonitorError> {
+        const SENSOR: &str = "GPU core frequency (NVIDIA)";
         let mut clock: u32 = 0;
         unsafe {
-            let get_clock: Symbol<NvmlDeviceGetClockInfo> = self.lib.get(b"nvmlDeviceGetClockInfo").unwrap();
-            if get_clock(self.device, 0, &mut clock as *mut u32) != 0 {
-                error!("Failed to get GPU core frequency (NVIDIA)");
-                exit(1);
+            let get_clock: Symbol<NvmlDeviceGetClockInfo> =
+                self.lib.get(b"nvmlDeviceGetClockInfo").map_err(|_| MonitorError::Unavailable(SENSOR))?;
+            let code = get_clock(self.device, 0, &mut clock as *mut u32);
+            if code != 0 {
+                return Err(MonitorError::Nvml(SENSOR, code));
             }
         }
 
-        clock as u16
+        Ok(clock as u16)
     }
 }
+
+fn init_error() -> MonitorError {
+    MonitorError::Access("Failed to initialize NVML".to_owned())
+}
@@ -2,8 +2,8 @@
 
 mod pci_ids;
 
-use crate::error;
-use std::{fs::{read_dir, read_to_string}, process::exit};
+use crate::monitor::MonitorError;
+use std::{fs::{read_dir, read_to_string}, path::Path};
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Vendor {
@@ -42,11 +42,11 @@ pub struct PciDevice {
 fn parse_pci_addr(addr: &str) -> Option<(u16, u8, u8, u8)> {
     // PCI Address Format:
     // 0000:00:00.0 | <domain>:<bus>:<device>.<function>
-    let mut parts = addr.split(|c| c == ':' || c == '.');
+    let mut parts = addr.split([':', '.']);
     let domain = u16::from_str_radix(parts.next()?, 16).ok()?;
     let bus = u8::from_str_radix(parts.next()?, 16).ok()?;
     let device = u8::from_str_radix(parts.next()?, 16).ok()?;
-    let function = u8::from_str_radix(parts.next()?, 10).ok()?;
+    let function = parts.next()?.parse::<u8>().ok()?;
     Some((domain, bus, device, function))
 }
 
@@ -60,11 +60,9 @@ fn parse_pci_id(id: &str) -> Option<(u16, u16)> {
 }
 
 /// Gets all GPUs from the PCI bus.
-pub fn get_gpu_list() -> Vec<PciDevice> {
-    let pci_devices = read_dir("/sys/bus/pci/devices").unwrap_or_else(|_| {
-        error!("Cannot read PCI devices");
-        exit(1);
-    });
+pub fn get_gpu_list(root: &Path) -> Result<Vec<PciDevice>, MonitorError> {
+    let pci_devices =
+        read_dir(root.join("sys/bus/pci/devices")).map_err(|err| MonitorError::Read("PCI devices", err))?;
 
     let mut gpus = Vec::new();
     let gpu_names = pci_ids::get_device_names();
@@ -73,67 +71,147 @@ pub fn get_gpu_list() -> Vec<PciDevice> {
         let dir = device.unwrap();
         let uevent_file = dir.path().join("uevent");
 
-        match read_to_string(uevent_file) {
-            Ok(data) => {
-                let mut driver = None;
-                let mut pci_id = None;
-                let mut subsys_id = None;
-                for line in data.lines() {
-                    if let Some(value) = line.strip_prefix("DRIVER=") {
-                        driver = Some(value);
-                    } else if let Some(value) = line.strip_prefix("PCI_ID=") {
-                        pci_id = Some(value);
-                    } else if let Some(value) = line.strip_prefix("PCI_SUBSYS_ID=") {
-                        subsys_id = Some(value);
-                    }
+        if let Ok(data) = read_to_string(uevent_file) {
+            let mut driver = None;
+            let mut pci_id = None;
+            let mut subsys_id = None;
+            for line in data.lines() {
+                if let Some(value) = line.strip_prefix("DRIVER=") {
+                    driver = Some(value);
+                } else if let Some(value) = line.strip_prefix("PCI_ID=") {
+                    pci_id = Some(value);
+                } else if let Some(value) = line.strip_prefix("PCI_SUBSYS_ID=") {
+                    subsys_id = Some(value);
                 }
+            }
 
-                if let (Some(driver), Some(pci_id), Some(subsys_id)) = (driver, pci_id, subsys_id) {
-                    let vendor = match driver {
-                        "amdgpu" => Some(Vendor::Amd),
-                        "nvidia" => Some(Vendor::Nvidia),
-                        "xe" => Some(Vendor::Intel),
-                        "i915" => {
-                            // Check the first 2 digits of the device ID:
-                            // 56xx: Arc A-Series
-                            // E2xx: Arc B-Series
-                            if ["56", "E2"].contains(&&pci_id[5..7]) { Some(Vendor::Intel) }
-                            else { None }
-                        }
-                        _ => None,
-                    };
-                    if let Some(vendor) = vendor {
-                        let pci_addr_str = dir.file_name().to_str().unwrap().to_owned();
-                        let pci_addr = parse_pci_addr(&pci_addr_str).unwrap();
-                        let pci_id = parse_pci_id(pci_id).unwrap();
-                        let subsys_id = parse_pci_id(subsys_id).unwrap();
-                        let gpu_name = if let Some(gpu_names) = &gpu_names {
-                            // Look for subsystem ID (common on AMD devices)
-                            if let Some(name) = gpu_names.get(&(vendor, pci_id.1, Some((subsys_id.0, subsys_id.1)))) { Some(name) }
-                            // Fallback to device ID
-                            else if let Some(name) = gpu_names.get(&(vendor, pci_id.1, None)) { Some(name) }
-                            // Fallback to generic name
-                            else { None }
-                        } else { None };
-                        // Unwrap the matched device name or specify generic name
-                        let gpu_name = match gpu_name {
-                            Some(name) => format!("{} {}", vendor.name(), name.to_owned()),
-                            None => format!("{} {}", vendor.name(), if pci_addr.1 > 0 { "GPU" } else { "iGPU" })
-                        };
-                        gpus.push(
-                            PciDevice {
-                                vendor,
-                                bus: pci_addr.1,
-                                address: pci_addr_str,
-                                name: gpu_name
-                            }
-                        );
+            if let (Some(driver), Some(pci_id), Some(subsys_id)) = (driver, pci_id, subsys_id) {
+                let vendor = match driver {
+                    "amdgpu" => Some(Vendor::Amd),
+                    "nvidia" => Some(Vendor::Nvidia),
+                    "xe" => Some(Vendor::Intel),
+                    "i915" => {
+                        // Check the first 2 digits of the device ID:
+                        // 56xx: Arc A-Series
+                        // E2xx: Arc B-Series
+                        if ["56", "E2"].contains(&&pci_id[5..7]) { Some(Vendor::Intel) }
+                        else { None }
                     }
+                    _ => None,
+                };
+                if let Some(vendor) = vendor {
+                    let pci_addr_str = dir.file_name().to_str().unwrap().to_owned();
+                    let pci_addr = parse_pci_addr(&pci_addr_str).unwrap();
+                    let pci_id = parse_pci_id(pci_id).unwrap();
+                    let subsys_id = parse_pci_id(subsys_id).unwrap();
+                    let gpu_name = if let Some(gpu_names) = &gpu_names {
+                        // Look for subsystem ID (common on AMD devices)
+                        if let Some(name) = gpu_names.get(&(vendor, pci_id.1, Some((subsys_id.0, subsys_id.1)))) { Some(name) }
+                        // Fallback to device ID (or generic name if `None`)
+                        else { gpu_names.get(&(vendor, pci_id.1, None)) }
+                    } else { None };
+                    // Unwrap the matched device name or specify generic name
+                    let gpu_name = match gpu_name {
+                        Some(name) => format!("{} {}", vendor.name(), name.to_owned()),
+                        None => format!("{} {}", vendor.name(), if pci_addr.1 > 0 { "GPU" } else { "iGPU" })
+                    };
+                    gpus.push(
+                        PciDevice {
+                            vendor,
+                            bus: pci_addr.1,
+                            address: pci_addr_str,
+                            name: gpu_name
+                        }
+                    );
                 }
             }
-            Err(_) => (),
         }
     }
 
-    gpus
+    Ok(gpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::fixture::Root;
+
+    /// Writes the uevent of a PCI device.
+    fn device(root: &Root, address: &str, driver: &str, pci_id: &str, subsys_id: &str) {
+        root.write(
+            &format!("sys/bus/pci/devices/{address}/uevent"),
+            &format!("DRIVER={driver}\nPCI_CLASS=30000\nPCI_ID={pci_id}\nPCI_SUBSYS_ID={subsys_id}\n"),
+        );
+    }
+
+    /// Returns the vendor, bus and address of the GPUs found, sorted by address.
+    fn gpus(root: &Root) -> Vec<(&'static str, u8, String)> {
+        let mut gpus: Vec<_> = get_gpu_list(root.path())
+            .unwrap()
+            .into_iter()
+            .map(|gpu| {
+                // The names come from the PCI ID database of the system, if it has one
+                assert!(gpu.name.starts_with(gpu.vendor.name()));
+                (gpu.vendor.name(), gpu.bus, gpu.address)
+            })
+            .collect();
+        gpus.sort_by(|a, b| a.2.cmp(&b.2));
+        gpus
+    }
+
+    #[test]
+    fn vendors() {
+        let root = Root::new("pci-vendors");
+        device(&root, "0000:03:00.0", "amdgpu", "1002:744C", "1DA2:E471");
+        device(&root, "0000:01:00.0", "nvidia", "10DE:2684", "10DE:16F3");
+        device(&root, "0000:00:02.0", "xe", "8086:7D55", "1043:1F43");
+        assert_eq!(
+            gpus(&root),
+            [
+                ("Intel", 0, "0000:00:02.0".to_owned()),
+                ("NVIDIA", 1, "0000:01:00.0".to_owned()),
+                ("AMD", 3, "0000:03:00.0".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn intel_i915() {
+        // Only the Arc cards are supported with i915
+        let root = Root::new("pci-i915");
+        device(&root, "0000:00:02.0", "i915", "8086:46A6", "1028:0B19");
+        device(&root, "0000:04:00.0", "i915", "8086:56A0", "8086:1020");
+        device(&root, "0000:05:00.0", "i915", "8086:E20B", "8086:1100");
+        assert_eq!(
+            gpus(&root),
+            [("Intel", 4, "0000:04:00.0".to_owned()), ("Intel", 5, "0000:05:00.0".to_owned())]
+        );
+    }
+
+    #[test]
+    fn generic_names() {
+        // IDs that no database lists
+        let root = Root::new("pci-names");
+        device(&root, "0000:00:08.0", "amdgpu", "1002:FFFE", "1002:FFFE");
+        device(&root, "0000:0a:00.0", "nvidia", "10DE:FFFE", "10DE:FFFE");
+        let mut names: Vec<_> = get_gpu_list(root.path()).unwrap().into_iter().map(|gpu| gpu.name).collect();
+        names.sort();
+        assert_eq!(names, ["AMD iGPU", "NVIDIA GPU"]);
+    }
+
+    #[test]
+    fn other_devices() {
+        let root = Root::new("pci-other");
+        device(&root, "0000:00:14.0", "xhci_hcd", "8086:7AE0", "1043:8882");
+        device(&root, "0000:02:00.0", "nouveau", "10DE:2684", "10DE:16F3");
+        // Without a driver, or without the subsystem
+        root.write("sys/bus/pci/devices/0000:06:00.0/uevent", "PCI_ID=1002:744C\nPCI_SUBSYS_ID=1DA2:E471\n")
+            .write("sys/bus/pci/devices/0000:07:00.0/uevent", "DRIVER=amdgpu\nPCI_ID=1002:744C\n")
+            .write("sys/bus/pci/devices/0000:08:00.0/config", "");
+        assert!(gpus(&root).is_empty());
+
+        // Without PCI devices at all
+        let root = Root::new("pci-none");
+        assert!(matches!(get_gpu_list(root.path()), Err(MonitorError::Read(..))));
+    }
 }
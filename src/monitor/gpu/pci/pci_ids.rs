@@ -9,11 +9,14 @@ const PCI_IDS_PATHS: [&str; 3] = [
     "/var/lib/pciutils/pci.ids",
 ];
 
+/// Maps `(vendor, device_id, subsystem_id)` to the device name.
+pub type DeviceNames = HashMap<(Vendor, u16, Option<(u16, u16)>), String>;
+
 /// Returns a HashMap of Vendor, Device ID, and Subsystem ID.
 ///
 /// Format: `(vendor_name, device_id, Option<(subsystem_vendor_id, subsystem_device_id)>)`
-pub fn get_device_names() -> Option<HashMap<(Vendor, u16, Option<(u16, u16)>), String>> {
-    let mut devices: HashMap<(Vendor, u16, Option<(u16, u16)>), String> = HashMap::new();
+pub fn get_device_names() -> Option<DeviceNames> {
+    let mut devices: DeviceNames = HashMap::new();
 
     let file = PCI_IDS_PATHS.iter().find_map(|path| {
         if Path::new(path).exists() { File::open(path).ok() }
@@ -1,2 +1,340 @@
 pub mod cpu;
+#[cfg(test)]
+pub mod fixture;
 pub mod gpu;
+
+use crate::{debug, utils::resume::Detector, warning};
+use cpu::Cpu;
+use gpu::Gpu;
+use cpu_monitor::CpuInstant;
+use std::{
+    collections::HashSet,
+    fmt, io,
+    ops::BitOr,
+    sync::Mutex,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// Readings younger than the shortest update interval are shared instead of reading the sensors again.
+const SHARE_WINDOW: Duration = Duration::from_millis(100);
+
+/// Error returned when a sensor cannot be read.
+#[derive(Debug)]
+pub enum MonitorError {
+    /// The sensor file could not be read.
+    Read(&'static str, io::Error),
+    /// The sensor returned data that is not a valid number.
+    Parse(&'static str),
+    /// The NVML call returned a non-zero status code.
+    Nvml(&'static str, u16),
+    /// None of the known sensor files are available.
+    Unavailable(&'static str),
+    /// The device or library could not be accessed.
+    Access(String),
+}
+
+impl fmt::Display for MonitorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MonitorError::Read(sensor, err) => write!(f, "Failed to get {sensor}: {err}"),
+            MonitorError::Parse(sensor) => write!(f, "Failed to get {sensor}: invalid sensor data"),
+            MonitorError::Nvml(sensor, code) => write!(f, "Failed to get {sensor}: NVML error {code}"),
+            MonitorError::Unavailable(sensor) => write!(f, "Failed to get {sensor}: no supported sensor was found"),
+            MonitorError::Access(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for MonitorError {}
+
+/// System readings collected over one update interval.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub struct SystemSample {
+    /// CPU utilization in percentage.
+    pub cpu_usage: u8,
+    /// CPU temperature in °C.
+    pub cpu_temp: f32,
+    /// CPU power consumption in Watts.
+    pub cpu_power: u16,
+    /// Highest CPU core frequency in MHz.
+    pub cpu_frequency: u16,
+    /// GPU utilization in percentage.
+    pub gpu_usage: u8,
+    /// GPU temperature in °C.
+    pub gpu_temp: f32,
+    /// GPU power consumption in Watts.
+    pub gpu_power: u16,
+    /// GPU core frequency in MHz.
+    pub gpu_frequency: u16,
+}
+
+impl SystemSample {
+    /// Returns the CPU temperature in °C or °F.
+    pub fn cpu_temperature(&self, fahrenheit: bool) -> u8 {
+        convert_temp(self.cpu_temp, fahrenheit)
+    }
+
+    /// Returns the GPU temperature in °C or °F.
+    pub fn gpu_temperature(&self, fahrenheit: bool) -> u8 {
+        convert_temp(self.gpu_temp, fahrenheit)
+    }
+}
+
+/// Rounds the temperature and converts it to °F if needed.
+fn convert_temp(celsius: f32, fahrenheit: bool) -> u8 {
+    if fahrenheit {
+        (celsius * 9.0 / 5.0 + 32.0).round() as u8
+    } else {
+        celsius.round() as u8
+    }
+}
+
+/// Readings of a sample, used to only read the sensors that a display shows.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub struct Fields(u8);
+
+impl Fields {
+    pub const NONE: Fields = Fields(0);
+    pub const CPU_USAGE: Fields = Fields(1);
+    pub const CPU_TEMP: Fields = Fields(1 << 1);
+    pub const CPU_POWER: Fields = Fields(1 << 2);
+    pub const CPU_FREQUENCY: Fields = Fields(1 << 3);
+    pub const GPU_USAGE: Fields = Fields(1 << 4);
+    pub const GPU_TEMP: Fields = Fields(1 << 5);
+    pub const GPU_POWER: Fields = Fields(1 << 6);
+    pub const GPU_FREQUENCY: Fields = Fields(1 << 7);
+    /// Every CPU reading.
+    pub const CPU: Fields = Fields(0x0f);
+    /// Every GPU reading.
+    pub const GPU: Fields = Fields(0xf0);
+    pub const ALL: Fields = Fields(u8::MAX);
+
+    pub const fn contains(self, other: Fields) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Fields {
+    type Output = Fields;
+
+    fn bitor(self, other: Fields) -> Fields {
+        Fields(self.0 | other.0)
+    }
+}
+
+/// Reads the monitored values once per update interval.
+///
+/// The sampler can be shared between displays: readings requested at the same time are only taken once,
+/// and usage and power are measured since the previous reading.
+/// Only the sensors of the fields subscribed by the running displays are read.
+/// A failed reading does not stop the display: the last known value is shown until the sensor recovers.
+pub struct Sampler {
+    cpu: Cpu,
+    gpu: Gpu,
+    state: Mutex<SamplerState>,
+    /// Held while the sensors are read, so readings requested at the same time are only taken once,
+    /// without blocking `last()` during slow reads like NVML.
+    reading: Mutex<()>,
+    /// Fields of every subscription.
+    demand: Mutex<Vec<Fields>>,
+}
+
+/// Fields a display shows, read by the sampler until the subscription is dropped.
+pub struct Subscription<'a> {
+    sampler: &'a Sampler,
+    fields: Fields,
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        let mut demand = self.sampler.demand.lock().unwrap();
+        if let Some(i) = demand.iter().position(|&fields| fields == self.fields) {
+            demand.swap_remove(i);
+        }
+    }
+}
+
+struct SamplerState {
+    /// Baseline of the CPU utilization, `None` while it is not read.
+    cpu_instant: Option<CpuInstant>,
+    /// Baseline of the CPU power, 0 while it is not read.
+    cpu_energy: u64,
+    taken: Instant,
+    last: SystemSample,
+    failing: HashSet<&'static str>,
+    /// Baselines taken before a suspend are discarded, unless the clocks cannot be read.
+    resume: Option<Detector>,
+}
+
+impl Sampler {
+    pub fn new(cpu: Cpu, gpu: Gpu) -> Self {
+        let state = SamplerState {
+            cpu_instant: cpu.read_instant().ok(),
+            cpu_energy: cpu.read_energy(),
+            taken: Instant::now(),
+            last: SystemSample::default(),
+            failing: HashSet::new(),
+            resume: Detector::start().ok(),
+        };
+        Sampler { cpu, gpu, state: Mutex::new(state), reading: Mutex::new(()), demand: Mutex::new(Vec::new()) }
+    }
+
+    /// Reads the fields in every sample, until the subscription is dropped.
+    pub fn subscribe(&self, fields: Fields) -> Subscription<'_> {
+        self.demand.lock().unwrap().push(fields);
+        Subscription { sampler: self, fields }
+    }
+
+    /// Returns the fields of every subscription.
+    fn demanded(&self) -> Fields {
+        self.demand.lock().unwrap().iter().fold(Fields::NONE, |all, &fields| all | fields)
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    /// Returns the latest snapshot without taking a new reading.
+    pub fn last(&self) -> SystemSample {
+        self.state.lock().unwrap().last
+    }
+
+    /// Waits for the interval and returns a snapshot of the system.
+    ///
+    /// The fields that no display subscribed to keep their last value.
+    pub fn sample(&self, interval: Duration) -> SystemSample {
+        // Wait
+        sleep(interval);
+
+        let _reading = self.reading.lock().unwrap();
+        let fields = self.demanded();
+        let (last, baseline, energy, elapsed) = {
+            let mut state = self.state.lock().unwrap();
+
+            // Share the reading of another display
+            if state.taken.elapsed() < SHARE_WINDOW {
+                return state.last;
+            }
+
+            // The counters moved while the machine slept, measure from now on
+            if state.resume.as_mut().is_some_and(Detector::resumed) {
+                state.cpu_instant = self.cpu.read_instant().ok();
+                state.cpu_energy = self.cpu.read_energy();
+                state.taken = Instant::now();
+                return state.last;
+            }
+            (state.last, state.cpu_instant, state.cpu_energy, state.taken.elapsed())
+        };
+
+        // Step 1: read the sensors, without blocking `last()`
+        let read = |field: Fields| fields.contains(field);
+        let cpu_usage = read(Fields::CPU_USAGE).then(|| self.cpu.read_instant());
+        let cpu_energy = if read(Fields::CPU_POWER) { self.cpu.read_energy() } else { 0 };
+        let cpu_temp = read(Fields::CPU_TEMP).then(|| self.cpu.get_temp());
+        let cpu_frequency = read(Fields::CPU_FREQUENCY).then(|| self.cpu.get_frequency());
+        let gpu_usage = read(Fields::GPU_USAGE).then(|| self.gpu.get_usage());
+        let gpu_temp = read(Fields::GPU_TEMP).then(|| self.gpu.get_temp());
+        let gpu_power = read(Fields::GPU_POWER).then(|| self.gpu.get_power());
+        let gpu_frequency = read(Fields::GPU_FREQUENCY).then(|| self.gpu.get_frequency());
+
+        // Step 2: keep the last value of the fields that were not read, or whose sensor failed
+        let mut state = self.state.lock().unwrap();
+        let (cpu_instant, cpu_usage) = match cpu_usage {
+            Some(Ok(current)) => {
+                let usage = baseline.map_or(last.cpu_usage, |initial| self.cpu.get_usage(initial, current));
+                (Some(current), Some(Ok(usage)))
+            }
+            Some(Err(err)) => (None, Some(Err(err))),
+            None => (None, None),
+        };
+        // The first reading after a subscription is only the baseline of the next one
+        let cpu_power = match (energy, cpu_energy) {
+            (0, _) | (_, 0) => last.cpu_power,
+            _ => self.cpu.get_power(energy, cpu_energy, elapsed.as_millis() as u64),
+        };
+        let sample = SystemSample {
+            cpu_usage: state.resolve("cpu_usage", cpu_usage, last.cpu_usage),
+            cpu_temp: state.resolve("cpu_temp", cpu_temp, last.cpu_temp),
+            cpu_power,
+            cpu_frequency: state.resolve("cpu_frequency", cpu_frequency, last.cpu_frequency),
+            gpu_usage: state.resolve("gpu_usage", gpu_usage, last.gpu_usage),
+            gpu_temp: state.resolve("gpu_temp", gpu_temp, last.gpu_temp),
+            gpu_power: state.resolve("gpu_power", gpu_power, last.gpu_power),
+            gpu_frequency: state.resolve("gpu_frequency", gpu_frequency, last.gpu_frequency),
+        };
+
+        // The current reading is the baseline of the next one
+        state.cpu_instant = cpu_instant;
+        state.cpu_energy = cpu_energy;
+        state.taken = Instant::now();
+        state.last = sample;
+
+        debug!(
+            "CPU: {}% | {:.1}°C | {} W | {} MHz, GPU: {}% | {:.1}°C | {} W | {} MHz",
+            sample.cpu_usage,
+            sample.cpu_temp,
+            sample.cpu_power,
+            sample.cpu_frequency,
+            sample.gpu_usage,
+            sample.gpu_temp,
+            sample.gpu_power,
+            sample.gpu_frequency
+        );
+        sample
+    }
+}
+
+impl SamplerState {
+    /// Returns the new reading, or the last value if the sensor failed or was not read.
+    ///
+    /// The error is only reported once, until the sensor recovers.
+    fn resolve<T>(&mut self, field: &'static str, reading: Option<Result<T, MonitorError>>, last: T) -> T {
+        match reading {
+            None => last,
+            Some(Ok(value)) => {
+                self.failing.remove(field);
+                value
+            }
+            Some(Err(err)) => {
+                if self.failing.insert(field) {
+                    warning!(format!("{err}\nThe last value will be displayed until the sensor recovers."));
+                }
+                last
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fixture::Root, *};
+
+    #[test]
+    fn only_subscribed_fields() {
+        let root = Root::cpu("subscribed");
+        let sampler = Sampler::new(Cpu::new(root.path()), Gpu::None);
+
+        let temp = sampler.subscribe(Fields::CPU_TEMP);
+        let sample = sampler.sample(SHARE_WINDOW);
+        assert_eq!((sample.cpu_temp, sample.cpu_frequency), (45.0, 0));
+
+        // The union of the subscriptions is read
+        let frequency = sampler.subscribe(Fields::CPU_FREQUENCY);
+        let sample = sampler.sample(SHARE_WINDOW);
+        assert_eq!((sample.cpu_temp, sample.cpu_frequency), (45.0, 3600));
+
+        // A field nobody shows keeps its last value
+        drop(temp);
+        root.write("sys/class/hwmon/hwmon0/temp1_input", "50000\n").write("proc/cpuinfo", "cpu MHz\t\t: 4000.000\n");
+        let sample = sampler.sample(SHARE_WINDOW);
+        assert_eq!((sample.cpu_temp, sample.cpu_frequency), (45.0, 4000));
+
+        drop(frequency);
+        assert!(sampler.demanded() == Fields::NONE);
+    }
+}
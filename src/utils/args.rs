@@ -1,402 +1,769 @@
 use crate::{
-    devices::Mode,
+    devices::{Mode, Options},
     error,
     monitor::gpu::pci::{get_gpu_list, Vendor},
     CH510_PRODUCT_ID, CH510_VENDOR_ID, DEFAULT_VENDOR_ID,
 };
+use super::{
+    cli::{find_command, find_long, find_short, print_help, Opt, OPTIONS},
+    completions,
+    config::{Config, Section, CONFIG_PATH},
+    ctl,
+    dbus::Bus,
+    install,
+    log::Level,
+    man, socket, udev,
+};
 use colored::*;
 use hidapi::HidApi;
-use std::{collections::HashMap, env::args, process::exit, time::Duration};
+use std::{
+    collections::HashMap,
+    env::{args, var_os},
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+    process::exit,
+    slice::Iter,
+    time::Duration,
+};
 
-pub struct Args {
+/// Display settings of a single device.
+#[derive(Clone)]
+pub struct DeviceArgs {
+    pub pid: u16,
+    /// Serial number of the device, if it was selected by it in the configuration file.
+    pub serial: Option<String>,
     pub mode: Mode,
     pub secondary: Mode,
-    pub pid: u16,
-    pub gpuid: Option<(Vendor, u8)>,
     pub update: Duration,
     pub fahrenheit: bool,
     pub alarm: bool,
     pub rotate: u16,
+    pub keep_on_exit: bool,
+}
+
+impl Default for DeviceArgs {
+    fn default() -> Self {
+        DeviceArgs {
+            pid: 0,
+            serial: None,
+            mode: Mode::Default,
+            secondary: Mode::Default,
+            update: Duration::from_millis(1000),
+            fahrenheit: false,
+            alarm: false,
+            rotate: 0,
+            keep_on_exit: false,
+        }
+    }
+}
+
+impl DeviceArgs {
+    /// Returns the display options of the device.
+    pub fn options(&self) -> Options {
+        Options {
+            mode: self.mode,
+            secondary: self.secondary,
+            update: self.update,
+            fahrenheit: self.fahrenheit,
+            alarm: self.alarm,
+            rotate: self.rotate,
+            keep_on_exit: self.keep_on_exit,
+        }
+    }
+
+    /// Returns `true` if the settings belong to the device.
+    pub fn matches(&self, pid: u16, serial: Option<&str>) -> bool {
+        (self.pid == 0 || self.pid == pid) && (self.serial.is_none() || self.serial.as_deref() == serial)
+    }
+}
+
+pub struct Args {
+    /// Settings given before the first `--pid`, used by every device without its own settings.
+    pub defaults: DeviceArgs,
+    /// Devices selected with `--pid`, each followed by its own settings,
+    /// or the `[[device]]` sections of the configuration file if there is no `--pid`.
+    pub devices: Vec<DeviceArgs>,
+    pub all: bool,
+    /// Attach the selected devices when they are plugged in, instead of only at startup.
+    pub hotplug: bool,
+    /// Ask the instance that holds the lock of a device to release it.
+    pub takeover: bool,
+    pub gpuid: Option<(Vendor, u8)>,
     pub hidraw: Option<String>,
+    pub output: Option<String>,
+    pub root: PathBuf,
+    /// Configuration file given on the command line.
+    pub config: Option<PathBuf>,
+    /// Location of the control socket.
+    pub socket: PathBuf,
+    /// Bus to publish the devices and readings on.
+    pub dbus: Option<Bus>,
+    /// Least important messages that are printed.
+    pub log_level: Level,
+}
+
+/// Command given on the command line, with its arguments.
+pub enum Command {
+    /// Drives the devices, the default.
+    Run(Box<Args>),
+    /// Prints the connected devices.
+    List,
+    /// Prints the GPUs found below the root.
+    Gpus(PathBuf),
+    Help,
+    Version,
+    /// Subcommands that read their own arguments, like `ctl`.
+    Ctl(Vec<String>),
+    Install(Vec<String>),
+    UdevRules(Vec<String>),
+    Completions(Vec<String>),
+    Man(Vec<String>),
+}
+
+/// Problem with the command line, the environment, or the configuration file.
+#[derive(Debug, PartialEq)]
+pub enum ArgError {
+    InvalidCommand(String),
+    InvalidOption(String),
+    /// The option needs a value, but none was given, like `--mode` in `-mu 500`.
+    MissingValue(&'static str),
+    /// A flag was given a value, like `--alarm=1`.
+    UnexpectedValue(&'static str),
+    /// The value of the option cannot be read.
+    InvalidValue(&'static str, String),
+    /// The value of the option is outside of its range.
+    OutOfRange(&'static str, u64),
+    DuplicatePid(u16),
+    /// `--serial` was given before any `--pid`.
+    SerialWithoutPid,
+    Config(String),
+}
+
+impl Display for ArgError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ArgError::InvalidCommand(command) => write!(f, "Invalid command {command}"),
+            ArgError::InvalidOption(option) => write!(f, "Invalid option {option}"),
+            ArgError::MissingValue(long) => write!(f, "--{long} requires a value"),
+            ArgError::UnexpectedValue(long) => write!(f, "--{long} does not take a value"),
+            ArgError::InvalidValue(long, _) => match *long {
+                "dbus" => write!(f, "D-Bus must be \"system\" or \"session\""),
+                "mode" => write!(f, "Invalid display mode"),
+                "secondary" => write!(f, "Invalid secondary display mode"),
+                "pid" => write!(f, "Invalid PID"),
+                "gpuid" => write!(f, "Invalid GPUID"),
+                "update" => write!(f, "Invalid update interval"),
+                "rotate" => write!(f, "Invalid rotation value"),
+                long => write!(f, "Invalid value of --{long}"),
+            },
+            ArgError::OutOfRange(long, _) => match *long {
+                "update" => write!(f, "Update interval must be between 100 and 2000"),
+                "rotate" => write!(f, "Rotation value must be one of 90, 180, or 270"),
+                long => write!(f, "Value of --{long} is out of range"),
+            },
+            ArgError::DuplicatePid(id) => write!(f, "PID {id} is specified more than once"),
+            ArgError::SerialWithoutPid => write!(f, "--serial must follow the --pid of its device"),
+            ArgError::Config(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+/// Option given on the command line or in the environment, with its value.
+pub type Given = (&'static Opt, Option<String>);
+
+impl Command {
+    /// Reads the command line of the program, over the environment and the configuration file.
+    pub fn read() -> Result<Self, ArgError> {
+        let args: Vec<String> = args().collect();
+        if let Some(command) = forwarded(&args) {
+            return Ok(command);
+        }
+        let config = load_config(&args).map_err(ArgError::Config)?;
+        parse(&args, from_env(OPTIONS), config)
+    }
 }
 
 impl Args {
+    /// Reads the command line, and runs the commands other than `run`, which exit.
     pub fn read() -> Self {
-        let args: Vec<String> = args().collect();
-        let mut mode = Mode::Default;
-        let mut secondary = Mode::Default;
-        let mut pid = 0;
-        let mut gpuid = None;
-        let mut update = Duration::from_millis(1000);
-        let mut fahrenheit = false;
-        let mut alarm = false;
-        let mut rotate = 0;
-        let mut hidraw: Option<String> = None;
-
-        let mut i = 1;
-        while i < args.len() {
-            match args[i].as_str() {
-                "--hidraw" => {
-                    if i + 1 < args.len() {
-                        hidraw = Some(args[i + 1].clone());
-                        i += 1;
-                    } else {
-                        error!("--hidraw requires a value");
-                        exit(1);
-                    }
-                }
-                "-m" | "--mode" => {
-                    if i + 1 < args.len() {
-                        mode = match Mode::get(&args[i + 1]) {
-                            Some(mode) => mode,
-                            None => {
-                                error!("Invalid display mode");
-                                exit(1);
-                            }
-                        };
-                        i += 1;
-                    } else {
-                        error!("--mode requires a value");
-                        exit(1);
-                    }
-                }
-                "-s" | "--secondary" => {
-                    if i + 1 < args.len() {
-                        secondary = match Mode::get(&args[i + 1]) {
-                            Some(mode) => mode,
-                            None => {
-                                error!("Invalid secondary display mode");
-                                exit(1);
-                            }
-                        };
-                        i += 1;
-                    } else {
-                        error!("--secondary requires a value");
-                        exit(1);
-                    }
-                }
-                "--pid" => {
-                    if i + 1 < args.len() {
-                        match args[i + 1].parse::<u16>() {
-                            Ok(id) if id > 0 => {
-                                pid = id;
-                                i += 1;
-                            }
-                            _ => {
-                                error!("Invalid PID");
-                                exit(1);
-                            }
-                        }
-                    } else {
-                        error!("--pid requires a value");
-                        exit(1);
-                    }
-                }
-                "--gpuid" => {
-                    if i + 1 < args.len() {
-                        let mut gpuid_str = args[i + 1].split(':');
-                        let vendor = Vendor::get(gpuid_str.next().unwrap_or(""));
-                        let id = gpuid_str.next().unwrap_or("").parse::<u8>().ok();
-                        match (vendor, id) {
-                            (Some(vendor), Some(id)) => {
-                                gpuid = Some((vendor, id));
-                                i += 1;
-                            }
-                            _ => {
-                                error!("Invalid GPUID");
-                                exit(1);
-                            }
-                        }
-                    } else {
-                        error!("--gpuid requires a value");
-                        exit(1);
-                    }
-                }
-                "-u" | "--update" => {
-                    if i + 1 < args.len() {
-                        match args[i + 1].parse::<u64>() {
-                            Ok(val) if (100..=2000).contains(&val) => {
-                                update = Duration::from_millis(val);
-                                i += 1;
-                            }
-                            Ok(_) => {
-                                error!("Update interval must be between 100 and 2000");
-                                exit(1);
-                            }
-                            Err(_) => {
-                                error!("Invalid update interval");
-                                exit(1);
-                            }
-                        }
-                    } else {
-                        error!("--update requires a value");
-                        exit(1);
-                    }
-                }
-                "-f" | "--fahrenheit" => {
-                    fahrenheit = true;
-                }
-                "-a" | "--alarm" => {
-                    alarm = true;
-                }
-                "-r" | "--rotate" => {
-                    if i + 1 < args.len() {
-                        match args[i + 1].parse::<u16>() {
-                            Ok(val) if [90, 180, 270].contains(&val) => {
-                                rotate = val;
-                                i += 1;
-                            }
-                            Ok(_) => {
-                                error!("Rotation value must be one of 90, 180, or 270");
-                                exit(1);
-                            }
-                            Err(_) => {
-                                error!("Invalid rotation value");
-                                exit(1);
-                            }
-                        }
-                    } else {
-                        error!("--rotate requires a value");
-                        exit(1);
-                    }
-                }
-                "-l" | "--list" => {
-                    println!(
-                        "Device list [{} | {}]",
-                        "PID".bright_green().bold(),
-                        "Name".bright_green()
-                    );
-                    println!("-----");
-                    let api = HidApi::new().unwrap_or_else(|err| {
-                        error!(err);
-                        exit(1);
-                    });
-                    let mut products = 0;
-                    for device in api.device_list() {
-                        if device.vendor_id() == DEFAULT_VENDOR_ID {
-                            products += 1;
-                            println!(
-                                "{} | {}",
-                                device.product_id().to_string().bright_green().bold(),
-                                device.product_string().unwrap().bright_green()
-                            );
-                        } else if device.vendor_id() == CH510_VENDOR_ID
-                            && device.product_id() == CH510_PRODUCT_ID
-                        {
-                            products += 1;
-                            println!(
-                                "{} | {}",
-                                device.product_id().to_string().bright_green().bold(),
-                                "CH510-MESH-DIGITAL".bright_green()
-                            );
-                        }
-                    }
-                    if products == 0 {
-                        println!("{}", "No DeepCool device was found".bright_black().italic());
-                    }
-                    exit(0);
-                }
-                "-g" | "--gpulist" => {
-                    println!(
-                        "GPU list [{} | {} {}]",
-                        "ID".bright_green().bold(),
-                        "Name".bright_green(),
-                        "(PCI Address)".bright_black()
-                    );
-                    println!("-----");
-                    let gpus = get_gpu_list();
-                    let mut gpu_ids = HashMap::new();
-                    for gpu in &gpus {
-                        let nth = gpu_ids.entry(&gpu.vendor).or_insert(0 as u8);
-                        *nth += 1;
-                        println!(
-                            "{} | {} {}",
-                            format!("{}:{}", gpu.vendor.name().to_lowercase(), *nth)
-                                .bright_green()
-                                .bold(),
-                            gpu.name.bright_green(),
-                            format!("({})", gpu.address).bright_black(),
-                        );
-                    }
-                    if gpus.is_empty() {
-                        println!("{}", "No GPUs were found".bright_black().italic())
-                    }
-                    exit(0);
-                }
-                "-h" | "--help" => {
-                    println!("{} [OPTIONS]", "Usage: deepcool-digital-linux".bold());
-                    println!("\n{}", "Options:".bold());
-                    println!(
-                        "  {}, {} <MODE>       Change the display mode of your device",
-                        "-m".bold(),
-                        "--mode".bold()
-                    );
-                    println!(
-                        "  {}, {} <MODE>  Change the secondary display mode of your device (if supported)",
-                        "-s".bold(),
-                        "--secondary".bold()
-                    );
-                    println!(
-                        "      {} <ID>          Specify the Product ID if multiple devices are connected",
-                        "--pid".bold()
-                    );
-                    println!(
-                        "      {} <PATH>        Use a specific hidraw device (e.g. /dev/hidraw3)",
-                        "--hidraw".bold()
-                    );
-                    println!(
-                        "      {} <VENDOR:ID> Specify the nth GPU of a specific vendor to monitor (use ID 0 for integrated GPU)",
-                        "--gpuid".bold()
-                    );
-                    println!(
-                        "\n  {}, {} <MILLISEC> Change the update interval of the display [default: 1000]",
-                        "-u".bold(),
-                        "--update".bold()
-                    );
-                    println!(
-                        "  {}, {}        Change the temperature unit to °F",
-                        "-f".bold(),
-                        "--fahrenheit".bold()
-                    );
-                    println!(
-                        "  {}, {}             Enable the alarm",
-                        "-a".bold(),
-                        "--alarm".bold()
-                    );
-                    println!(
-                        "  {}, {} <DEGREE>   Rotate the display (LP Series only)",
-                        "-r".bold(),
-                        "--rotate".bold()
-                    );
-                    println!("\n{}", "Commands:".bold());
-                    println!(
-                        "  {}, {}         Print Product ID of the connected devices",
-                        "-l".bold(),
-                        "--list".bold()
-                    );
-                    println!(
-                        "  {}, {}      Print all available GPUs",
-                        "-g".bold(),
-                        "--gpulist".bold()
-                    );
-                    println!("  {}, {}         Print help", "-h".bold(), "--help".bold());
-                    println!(
-                        "  {}, {}      Print version",
-                        "-v".bold(),
-                        "--version".bold()
-                    );
-                    exit(0);
-                }
-                "-v" | "--version" => {
-                    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-                    exit(0);
-                }
-                arg if arg.starts_with('-') && arg.len() > 1 => {
-                    for c in arg.chars().skip(1) {
-                        match c {
-                            'm' => {
-                                if i + 1 < args.len() && args[i].ends_with('m') {
-                                    mode = match Mode::get(&args[i + 1]) {
-                                        Some(mode) => mode,
-                                        None => {
-                                            error!("Invalid display mode");
-                                            exit(1);
-                                        }
-                                    };
-                                    i += 1;
-                                } else {
-                                    error!("--mode requires a value");
-                                    exit(1);
-                                }
-                            }
-                            's' => {
-                                if i + 1 < args.len() && args[i].ends_with('s') {
-                                    secondary = match Mode::get(&args[i + 1]) {
-                                        Some(mode) => mode,
-                                        None => {
-                                            error!("Invalid secondary display mode");
-                                            exit(1);
-                                        }
-                                    };
-                                    i += 1;
-                                } else {
-                                    error!("--secondary requires a value");
-                                    exit(1);
-                                }
-                            }
-                            'u' => {
-                                if i + 1 < args.len() && args[i].ends_with('u') {
-                                    match args[i + 1].parse::<u64>() {
-                                        Ok(val) if (100..=2000).contains(&val) => {
-                                            update = Duration::from_millis(val);
-                                            i += 1;
-                                        }
-                                        Ok(_) => {
-                                            error!("Update interval must be between 100 and 2000");
-                                            exit(1);
-                                        }
-                                        Err(_) => {
-                                            error!("Invalid update interval");
-                                            exit(1);
-                                        }
-                                    }
-                                } else {
-                                    error!("--update requires a value");
-                                    exit(1);
-                                }
-                            }
-                            'r' => {
-                                if i + 1 < args.len() && args[i].ends_with('r') {
-                                    match args[i + 1].parse::<u16>() {
-                                        Ok(val) if [90, 180, 270].contains(&val) => {
-                                            rotate = val;
-                                            i += 1;
-                                        }
-                                        Ok(_) => {
-                                            error!("Rotation value must be one of 90, 180, or 270");
-                                            exit(1);
-                                        }
-                                        Err(_) => {
-                                            error!("Invalid rotation value");
-                                            exit(1);
-                                        }
-                                    }
-                                } else {
-                                    error!("--update requires a value");
-                                    exit(1);
-                                }
-                            }
-                            'f' => fahrenheit = true,
-                            'a' => alarm = true,
-                            _ => {
-                                if arg.starts_with("--") {
-                                    error!(format!("Invalid option {arg}"));
-                                } else {
-                                    error!(format!("Invalid option -{c}"));
-                                }
-                                exit(1);
-                            }
-                        }
-                    }
+        let command = Command::read().unwrap_or_else(|err| {
+            error!(err);
+            exit(1);
+        });
+        match command {
+            Command::Run(args) => *args,
+            Command::List => {
+                print_devices();
+                exit(0);
+            }
+            Command::Gpus(root) => {
+                print_gpus(&root);
+                exit(0);
+            }
+            Command::Help => {
+                print_help();
+                exit(0);
+            }
+            Command::Version => {
+                println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                exit(0);
+            }
+            Command::Ctl(args) => ctl::run(&args),
+            Command::Install(args) => install::run(&args),
+            Command::UdevRules(args) => udev::run(&args),
+            Command::Completions(args) => completions::run(&args),
+            Command::Man(args) => man::run(&args),
+        }
+    }
+
+    /// Reads the configuration file again, and applies the same options over it.
+    pub fn reload() -> Result<Self, String> {
+        match Command::read() {
+            Ok(Command::Run(args)) => Ok(*args),
+            Ok(_) => Err("The command line does not run the devices".to_owned()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+/// Parses the command line alone, without the environment and the configuration file,
+/// the first argument being the program.
+pub fn parse_from<I>(args: I) -> Result<Command, ArgError>
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+{
+    let args: Vec<String> = args.into_iter().map(Into::into).collect();
+    match forwarded(&args) {
+        Some(command) => Ok(command),
+        None => parse(&args, Vec::new(), None),
+    }
+}
+
+/// Returns the subcommands that read their own arguments.
+fn forwarded(args: &[String]) -> Option<Command> {
+    let rest = args.get(2..).unwrap_or_default().to_vec();
+    match args.get(1).map(String::as_str) {
+        Some("ctl") => Some(Command::Ctl(rest)),
+        Some("status") => Some(Command::Ctl([vec!["status".to_owned()], rest].concat())),
+        Some("install") => Some(Command::Install(rest)),
+        Some("udev-rules") => Some(Command::UdevRules(rest)),
+        Some("completions") => Some(Command::Completions(rest)),
+        Some("man") => Some(Command::Man(rest)),
+        _ => None,
+    }
+}
+
+/// Reads the configuration file given with `--config`, or the default one if it exists.
+fn load_config(args: &[String]) -> Result<Option<Config>, String> {
+    let given = match args.iter().position(|arg| arg == "--config" || arg.starts_with("--config=")) {
+        Some(i) => Some(PathBuf::from(match args[i].strip_prefix("--config=") {
+            Some(value) => value,
+            None => args.get(i + 1).ok_or("--config requires a value")?,
+        })),
+        None => var_os("DEEPCOOL_CONFIG").map(PathBuf::from),
+    };
+    let path = given.as_deref().unwrap_or(Path::new(CONFIG_PATH));
+    match Config::load(path) {
+        Ok(None) if given.is_some() => Err(format!("Configuration file not found: {}", path.display())),
+        Ok(config) => Ok(config),
+        Err(err) => Err(format!("Invalid configuration file {}: {err}", path.display())),
+    }
+}
+
+/// Parses the command line over the environment and the configuration, `args[0]` being the program.
+fn parse(args: &[String], env: Vec<Given>, config: Option<Config>) -> Result<Command, ArgError> {
+    // Defaults of the configuration file, the command line defaults are applied over them
+    let mut configured = DeviceArgs::default();
+    let mut devices: Vec<DeviceArgs> = Vec::new();
+    let mut all = false;
+    let mut hotplug = false;
+    let mut takeover = false;
+    let mut gpuid = None;
+    if let Some(config) = &config {
+        config.defaults.apply(&mut configured);
+        all = config.all;
+        hotplug = config.hotplug;
+        gpuid = config.gpuid;
+    }
+    let mut hidraw: Option<String> = None;
+    let mut output: Option<String> = None;
+    let mut root = PathBuf::from("/");
+    let mut config_path: Option<PathBuf> = None;
+    let mut socket: Option<PathBuf> = None;
+    let mut dbus: Option<Bus> = None;
+    let mut log_level = Level::Info;
+    let mut defaults = configured.clone();
+    // Device options given before the first `--pid`
+    let mut overrides: Vec<(&Opt, String)> = Vec::new();
+    // A device starts from its section of the configuration file, then takes the command line defaults
+    let device = |pid: u16, section: Option<&Section>, overrides: &[(&Opt, String)]| -> Result<DeviceArgs, ArgError> {
+        let mut device = DeviceArgs { pid, ..configured.clone() };
+        if let Some(section) = section {
+            section.apply(&mut device);
+        }
+        for (opt, value) in overrides {
+            set_option(&mut device, opt, value)?;
+        }
+        Ok(device)
+    };
+
+    // Step 1: find the command, `run` if it is left out
+    let mut command = "run";
+    let mut rest = args.get(1..).unwrap_or_default();
+    if let Some(first) = rest.first().filter(|arg| !arg.starts_with('-')) {
+        match find_command(first) {
+            Some(found) => command = found.name,
+            None => return Err(ArgError::InvalidCommand(first.to_owned())),
+        }
+        rest = &rest[1..];
+    }
+
+    // Step 2: the environment comes first, so the command line overrides it
+    let mut given = env;
+    given.extend(split(rest, &mut command)?);
+
+    // Step 3: apply the options
+    for (opt, value) in given {
+        let value = value.unwrap_or_default();
+        let invalid = || ArgError::InvalidValue(opt.long, value.clone());
+        match opt.long {
+            "hidraw" => hidraw = Some(value),
+            "output" => output = Some(value),
+            // Already read before the other options
+            "config" => config_path = Some(PathBuf::from(value)),
+            "all" => all = true,
+            "hotplug" => hotplug = true,
+            "takeover" => takeover = true,
+            "verbose" => log_level = Level::Debug,
+            "quiet" => log_level = Level::Error,
+            "root" => root = PathBuf::from(value),
+            "socket" => socket = Some(PathBuf::from(value)),
+            "dbus" => dbus = Some(Bus::get(&value).ok_or_else(invalid)?),
+            "pid" => match value.parse::<u16>() {
+                Ok(id) if id > 0 => {
+                    let section = config.iter().flat_map(|c| &c.devices).find(|s| s.pid == id);
+                    devices.push(device(id, section, &overrides)?);
                 }
-                _ => {
-                    error!(format!("Invalid option {}", args[i]));
-                    exit(1);
+                _ => return Err(invalid()),
+            },
+            "serial" => match devices.last_mut() {
+                Some(device) if !value.is_empty() => device.serial = Some(value),
+                Some(_) => return Err(invalid()),
+                None => return Err(ArgError::SerialWithoutPid),
+            },
+            "gpuid" => gpuid = Some(parse_gpuid(&value).ok_or_else(invalid)?),
+            // Options after `--pid` only apply to that device
+            _ => match devices.last_mut() {
+                Some(device) => set_option(device, opt, &value)?,
+                None => {
+                    set_option(&mut defaults, opt, &value)?;
+                    overrides.push((opt, value));
                 }
-            }
-            i += 1;
+            },
         }
+    }
+
+    // Identical devices are told apart by their serial number
+    for (i, device) in devices.iter().enumerate() {
+        if devices[..i].iter().any(|d| d.pid == device.pid && d.serial == device.serial) {
+            return Err(ArgError::DuplicatePid(device.pid));
+        }
+    }
+
+    // Without `--pid`, the devices of the configuration file are selected
+    if devices.is_empty() {
+        for section in config.iter().flat_map(|c| &c.devices) {
+            devices.push(device(section.pid, Some(section), &overrides)?);
+        }
+    }
 
-        Args {
-            mode,
-            secondary,
-            pid,
+    Ok(match command {
+        "list" => Command::List,
+        "gpus" => Command::Gpus(root),
+        "help" => Command::Help,
+        "version" => Command::Version,
+        _ => Command::Run(Box::new(Args {
+            defaults,
+            devices,
+            all,
+            hotplug,
+            takeover,
             gpuid,
-            update,
-            fahrenheit,
-            alarm,
-            rotate,
             hidraw,
+            output,
+            root,
+            config: config_path,
+            socket: socket.unwrap_or_else(socket::default_path),
+            dbus,
+            log_level,
+        })),
+    })
+}
+
+/// Sets an option of the device.
+fn set_option(device: &mut DeviceArgs, opt: &Opt, value: &str) -> Result<(), ArgError> {
+    let invalid = || ArgError::InvalidValue(opt.long, value.to_owned());
+    match opt.long {
+        "mode" => device.mode = Mode::get(value).ok_or_else(invalid)?,
+        "secondary" => device.secondary = Mode::get(value).ok_or_else(invalid)?,
+        "update" => match value.parse::<u64>() {
+            Ok(val) if (100..=2000).contains(&val) => device.update = Duration::from_millis(val),
+            Ok(val) => return Err(ArgError::OutOfRange(opt.long, val)),
+            Err(_) => return Err(invalid()),
+        },
+        "fahrenheit" => device.fahrenheit = true,
+        "alarm" => device.alarm = true,
+        "keep-on-exit" => device.keep_on_exit = true,
+        "rotate" => match value.parse::<u16>() {
+            Ok(val) if [90, 180, 270].contains(&val) => device.rotate = val,
+            Ok(val) => return Err(ArgError::OutOfRange(opt.long, val.into())),
+            Err(_) => return Err(invalid()),
+        },
+        long => unreachable!("--{long} is not handled"),
+    }
+    Ok(())
+}
+
+/// Returns the options of the table given by their environment variables.
+pub fn from_env(options: &'static [Opt]) -> Vec<Given> {
+    options
+        .iter()
+        // The configuration file is read before the other options
+        .filter(|opt| opt.long != "config")
+        .filter_map(|opt| {
+            let value = var_os(opt.env?)?.into_string().ok().filter(|value| !value.is_empty())?;
+            Some((opt, Some(value)))
+        })
+        .collect()
+}
+
+/// Splits the command line into options and their values, like `-fu 500` into `--fahrenheit` and `--update 500`.
+///
+/// The former options of the commands, like `--list`, replace the command.
+fn split(args: &[String], command: &mut &'static str) -> Result<Vec<Given>, ArgError> {
+    let mut given = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(found) = find_command(arg).filter(|_| arg.starts_with('-')) {
+            *command = found.name;
+            continue;
+        }
+        if !is_option(arg) {
+            return Err(ArgError::InvalidOption(arg.to_owned()));
         }
+        given.extend(read_option(arg, &mut args, &[OPTIONS])?);
+    }
+    Ok(given)
+}
+
+/// Splits the arguments of a command that reads its own, like `ctl`, into the options of the tables and the words.
+pub fn split_command(args: &[String], tables: &[&'static [Opt]]) -> Result<(Vec<Given>, Vec<String>), ArgError> {
+    let mut given = Vec::new();
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if is_option(arg) {
+            given.extend(read_option(arg, &mut args, tables)?);
+        } else {
+            words.push(arg.to_owned());
+        }
+    }
+    Ok((given, words))
+}
+
+/// Returns `true` if the argument is an option, `-` alone is a value.
+fn is_option(arg: &str) -> bool {
+    arg.starts_with('-') && arg.len() > 1
+}
+
+/// Reads the options of the argument, taking their value from the next argument if it is not given inline.
+fn read_option(arg: &str, args: &mut Iter<String>, tables: &[&'static [Opt]]) -> Result<Vec<Given>, ArgError> {
+    // `--update=500` carries its value
+    let (opts, mut inline) = if let Some(long) = arg.strip_prefix("--") {
+        let (name, inline) = match long.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (long, None),
+        };
+        let opt = find_long(tables, name).ok_or_else(|| ArgError::InvalidOption(format!("--{name}")))?;
+        (vec![opt], inline)
+    } else {
+        let opts = arg[1..]
+            .chars()
+            .map(|c| find_short(tables, c).ok_or_else(|| ArgError::InvalidOption(format!("-{c}"))))
+            .collect::<Result<Vec<_>, _>>()?;
+        (opts, None)
+    };
+
+    // Only the last one of combined short options can take a value
+    let last = opts.len() - 1;
+    let mut given = Vec::new();
+    for (n, opt) in opts.into_iter().enumerate() {
+        let value = match opt.value {
+            Some(_) if n == last => {
+                Some(inline.take().or_else(|| args.next().cloned()).ok_or(ArgError::MissingValue(opt.long))?)
+            }
+            Some(_) => return Err(ArgError::MissingValue(opt.long)),
+            None if inline.is_some() => return Err(ArgError::UnexpectedValue(opt.long)),
+            None => None,
+        };
+        given.push((opt, value));
+    }
+    Ok(given)
+}
+
+/// Prints the connected DeepCool devices with their Product ID.
+fn print_devices() {
+    println!("Device list [{} | {}]", "PID".bright_green().bold(), "Name".bright_green());
+    println!("-----");
+    let api = HidApi::new().unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+    let mut products = 0;
+    for device in api.device_list() {
+        if device.vendor_id() == DEFAULT_VENDOR_ID {
+            products += 1;
+            println!(
+                "{} | {}",
+                device.product_id().to_string().bright_green().bold(),
+                device.product_string().unwrap_or("Unknown").bright_green()
+            );
+        } else if device.vendor_id() == CH510_VENDOR_ID && device.product_id() == CH510_PRODUCT_ID {
+            products += 1;
+            println!(
+                "{} | {}",
+                device.product_id().to_string().bright_green().bold(),
+                "CH510-MESH-DIGITAL".bright_green()
+            );
+        }
+    }
+    if products == 0 {
+        println!("{}", "No DeepCool device was found".bright_black().italic());
+    }
+}
+
+/// Prints the GPUs with the ID used by `--gpuid`.
+fn print_gpus(root: &Path) {
+    println!(
+        "GPU list [{} | {} {}]",
+        "ID".bright_green().bold(),
+        "Name".bright_green(),
+        "(PCI Address)".bright_black()
+    );
+    println!("-----");
+    let gpus = get_gpu_list(root).unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+    let mut gpu_ids = HashMap::new();
+    for gpu in &gpus {
+        let nth = gpu_ids.entry(&gpu.vendor).or_insert(0_u8);
+        *nth += 1;
+        println!(
+            "{} | {} {}",
+            format!("{}:{}", gpu.vendor.name().to_lowercase(), *nth).bright_green().bold(),
+            gpu.name.bright_green(),
+            format!("({})", gpu.address).bright_black(),
+        );
+    }
+    if gpus.is_empty() {
+        println!("{}", "No GPUs were found".bright_black().italic())
+    }
+}
+
+/// Parses the `VENDOR:ID` format of `--gpuid`.
+pub fn parse_gpuid(value: &str) -> Option<(Vendor, u8)> {
+    let (vendor, id) = value.split_once(':')?;
+    Some((Vendor::get(vendor)?, id.parse::<u8>().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::cli;
+
+    /// Parses the options of `run`.
+    fn run(args: &[&str]) -> Result<Args, ArgError> {
+        match parse_from(["deepcool-digital-linux"].iter().chain(args).copied()) {
+            Ok(Command::Run(args)) => Ok(*args),
+            Ok(_) => panic!("{args:?} is not parsed as run"),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn error(args: &[&str]) -> ArgError {
+        run(args).err().expect("the arguments are invalid")
+    }
+
+    #[test]
+    fn combined_flags() {
+        let args = run(&["-fa"]).unwrap();
+        assert!(args.defaults.fahrenheit);
+        assert!(args.defaults.alarm);
+    }
+
+    #[test]
+    fn combined_flags_with_value_last() {
+        let args = run(&["-fu", "500"]).unwrap();
+        assert!(args.defaults.fahrenheit);
+        assert_eq!(args.defaults.update, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn combined_flags_with_value_inside() {
+        assert_eq!(error(&["-mu", "500"]), ArgError::MissingValue("mode"));
+        assert_eq!(error(&["-mu", "500"]).to_string(), "--mode requires a value");
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(error(&["-r"]), ArgError::MissingValue("rotate"));
+        assert_eq!(error(&["-r"]).to_string(), "--rotate requires a value");
+        assert_eq!(error(&["--mode"]), ArgError::MissingValue("mode"));
+    }
+
+    #[test]
+    fn invalid_options() {
+        assert_eq!(error(&["-x"]), ArgError::InvalidOption("-x".to_owned()));
+        assert_eq!(error(&["-fx"]), ArgError::InvalidOption("-x".to_owned()));
+        assert_eq!(error(&["--bogus"]), ArgError::InvalidOption("--bogus".to_owned()));
+        assert_eq!(error(&["-f", "cpu"]), ArgError::InvalidOption("cpu".to_owned()));
+        assert_eq!(error(&["--alarm=1"]), ArgError::UnexpectedValue("alarm"));
+    }
+
+    #[test]
+    fn update_range() {
+        assert_eq!(run(&["-u", "100"]).unwrap().defaults.update, Duration::from_millis(100));
+        assert_eq!(run(&["--update=2000"]).unwrap().defaults.update, Duration::from_millis(2000));
+        assert_eq!(error(&["-u", "99"]), ArgError::OutOfRange("update", 99));
+        assert_eq!(error(&["--update", "2001"]), ArgError::OutOfRange("update", 2001));
+        assert_eq!(error(&["-u", "fast"]), ArgError::InvalidValue("update", "fast".to_owned()));
+        assert_eq!(error(&["-u", "-1"]).to_string(), "Invalid update interval");
+    }
+
+    #[test]
+    fn rotate_range() {
+        for degree in [90, 180, 270] {
+            assert_eq!(run(&["-r", &degree.to_string()]).unwrap().defaults.rotate, degree);
+        }
+        assert_eq!(error(&["-r", "0"]), ArgError::OutOfRange("rotate", 0));
+        assert_eq!(error(&["--rotate", "45"]), ArgError::OutOfRange("rotate", 45));
+        assert_eq!(error(&["-r", "360"]).to_string(), "Rotation value must be one of 90, 180, or 270");
+        assert_eq!(error(&["-r", "left"]), ArgError::InvalidValue("rotate", "left".to_owned()));
+    }
+
+    #[test]
+    fn gpuid() {
+        assert!(run(&["--gpuid", "nvidia:1"]).unwrap().gpuid == Some((Vendor::Nvidia, 1)));
+        assert!(run(&["--gpuid", "amd:0"]).unwrap().gpuid == Some((Vendor::Amd, 0)));
+        assert!(run(&["--gpuid=intel:2"]).unwrap().gpuid == Some((Vendor::Intel, 2)));
+        assert!(run(&[]).unwrap().gpuid.is_none());
+        for value in ["nvidia", "nvidia:", "nvidia:one", "nvidia:256", "ati:1", ":1", "NVIDIA:1"] {
+            assert_eq!(error(&["--gpuid", value]), ArgError::InvalidValue("gpuid", value.to_owned()));
+        }
+    }
+
+    #[test]
+    fn options_after_pid() {
+        let args = run(&["-f", "--pid", "4", "-m", "cpu_usage", "--pid", "7", "-u", "500"]).unwrap();
+        assert!(args.defaults.fahrenheit);
+        assert_eq!(args.devices.len(), 2);
+        assert_eq!(args.devices[0].pid, 4);
+        assert!(args.devices[0].fahrenheit);
+        assert!(args.devices[0].mode == Mode::CpuUsage);
+        assert_eq!(args.devices[0].update, Duration::from_millis(1000));
+        assert_eq!(args.devices[1].pid, 7);
+        assert!(args.devices[1].mode == Mode::Default);
+        assert_eq!(args.devices[1].update, Duration::from_millis(500));
+        assert_eq!(error(&["--pid", "4", "--pid", "4"]), ArgError::DuplicatePid(4));
+        assert_eq!(error(&["--pid=4", "--pid=4"]), ArgError::DuplicatePid(4));
+        assert_eq!(error(&["--pid", "0"]), ArgError::InvalidValue("pid", "0".to_owned()));
+    }
+
+    #[test]
+    fn config_sections() {
+        let run = |args: &[&str]| {
+            let config = Config::parse("update = 2000\n[[device]]\npid = 4\nupdate = 1500\nfahrenheit = true\n");
+            let args: Vec<String> = ["deepcool-digital-linux"].iter().chain(args).map(|arg| arg.to_string()).collect();
+            match parse(&args, Vec::new(), Some(config.unwrap())) {
+                Ok(Command::Run(args)) => *args,
+                _ => panic!("{args:?} is not parsed as run"),
+            }
+        };
+
+        // The command line overrides the section of the device, which overrides the top of the file
+        let args = run(&["-u", "500", "--pid", "4"]);
+        assert_eq!(args.defaults.update, Duration::from_millis(500));
+        assert_eq!((args.devices[0].update, args.devices[0].fahrenheit), (Duration::from_millis(500), true));
+        let args = run(&["--pid", "4"]);
+        assert_eq!(args.devices[0].update, Duration::from_millis(1500));
+        let args = run(&["--pid", "4", "-u", "500"]);
+        assert_eq!(args.devices[0].update, Duration::from_millis(500));
+
+        // Without `--pid`, the devices of the file are selected the same way
+        let args = run(&["-u", "500"]);
+        assert_eq!(args.devices.len(), 1);
+        assert_eq!((args.devices[0].pid, args.devices[0].update), (4, Duration::from_millis(500)));
+        assert_eq!(run(&[]).devices[0].update, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn commands() {
+        let parse = |args: &[&str]| parse_from(["deepcool-digital-linux"].iter().chain(args).copied());
+        assert!(matches!(parse(&["run", "-f"]), Ok(Command::Run(args)) if args.defaults.fahrenheit));
+        assert!(matches!(parse(&["list"]), Ok(Command::List)));
+        assert!(matches!(parse(&["-l"]), Ok(Command::List)));
+        let gpus = parse(&["--gpulist", "--root", "/tmp"]);
+        assert!(matches!(gpus, Ok(Command::Gpus(root)) if root == Path::new("/tmp")));
+        assert!(matches!(parse(&["-f", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["version"]), Ok(Command::Version)));
+        assert!(matches!(parse(&["status", "--json"]), Ok(Command::Ctl(args)) if args == ["status", "--json"]));
+        assert!(matches!(parse(&["ctl", "off"]), Ok(Command::Ctl(args)) if args == ["off"]));
+        assert!(matches!(parse(&["bogus"]), Err(ArgError::InvalidCommand(command)) if command == "bogus"));
+        // The other commands do not validate the options of `run`
+        assert!(matches!(parse(&["completions", "-x"]), Ok(Command::Completions(args)) if args == ["-x"]));
+    }
+
+    #[test]
+    fn serials() {
+        let args = run(&["--pid", "4", "--serial", "A1", "-f", "--pid=4", "--serial=B2"]).unwrap();
+        assert_eq!(args.devices.len(), 2);
+        assert_eq!((args.devices[0].pid, args.devices[0].serial.as_deref()), (4, Some("A1")));
+        assert!(args.devices[0].fahrenheit);
+        assert_eq!((args.devices[1].pid, args.devices[1].serial.as_deref()), (4, Some("B2")));
+        assert!(!args.devices[1].fahrenheit);
+        assert!(args.devices[0].matches(4, Some("A1")) && !args.devices[0].matches(4, Some("B2")));
+        assert_eq!(error(&["--serial", "A1"]), ArgError::SerialWithoutPid);
+        assert_eq!(error(&["--pid", "4", "--serial="]), ArgError::InvalidValue("serial", String::new()));
+        assert_eq!(error(&["--pid", "4", "--serial", "A1", "--pid", "4", "--serial", "A1"]), ArgError::DuplicatePid(4));
+    }
+
+    /// Splits the arguments of the command into its options, written as `--long=value`, and its words.
+    fn split(name: &str, args: &[&str]) -> Result<(Vec<String>, Vec<String>), ArgError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let (given, words) = split_command(&args, &cli::command(name).tables())?;
+        let given = given.into_iter().map(|(opt, value)| match value {
+            Some(value) => format!("--{}={value}", opt.long),
+            None => format!("--{}", opt.long),
+        });
+        Ok((given.collect(), words))
+    }
+
+    #[test]
+    fn command_options() {
+        let (given, words) = split("ctl", &["set-mode", "--pid", "4", "cpu_usage", "--json"]).unwrap();
+        assert_eq!(given, ["--pid=4", "--json"]);
+        assert_eq!(words, ["set-mode", "cpu_usage"]);
+        let (given, words) = split("ctl", &["--socket=/tmp/deepcool.sock", "-"]).unwrap();
+        assert_eq!((given, words), (vec!["--socket=/tmp/deepcool.sock".to_owned()], vec!["-".to_owned()]));
+        // `install` also takes the options of `run`
+        let (given, words) = split("install", &["--dry-run", "-fm", "cpu_usage"]).unwrap();
+        assert_eq!(given, ["--dry-run", "--fahrenheit", "--mode=cpu_usage"]);
+        assert!(words.is_empty());
+        assert_eq!(split("ctl", &["-f"]).err(), Some(ArgError::InvalidOption("-f".to_owned())));
+        let invalid = split("udev-rules", &["--bin", "/usr/bin"]).err();
+        assert_eq!(invalid, Some(ArgError::InvalidOption("--bin".to_owned())));
+        assert_eq!(split("install", &["--bin"]).err(), Some(ArgError::MissingValue("bin")));
     }
 }
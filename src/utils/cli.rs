@@ -0,0 +1,366 @@
+//! Definition of the command line: the commands and the options of the program.
+//!
+//! The parsers, `--help`, the shell completions, and the man page are all built from these tables,
+//! including the options of the commands that read their own arguments, like `ctl`.
+
+use colored::*;
+
+/// Values offered by the shell completions.
+#[derive(Clone, Copy)]
+pub enum Complete {
+    Nothing,
+    Modes,
+    Values(&'static [&'static str]),
+    File,
+}
+
+pub struct Opt {
+    pub long: &'static str,
+    pub short: Option<char>,
+    /// Name of the value, or `None` if the option is a flag.
+    pub value: Option<&'static str>,
+    pub complete: Complete,
+    /// Variable read when the option is not given.
+    pub env: Option<&'static str>,
+    /// Help text, the lines after the first one are only shown by `--help` and the man page.
+    pub help: &'static str,
+    /// Printed after an empty line in `--help`.
+    pub separated: bool,
+}
+
+impl Opt {
+    const fn flag(long: &'static str, short: Option<char>, help: &'static str) -> Opt {
+        Opt { long, short, value: None, complete: Complete::Nothing, env: None, help, separated: false }
+    }
+
+    const fn value(long: &'static str, short: Option<char>, value: &'static str, help: &'static str) -> Opt {
+        Opt { long, short, value: Some(value), complete: Complete::Nothing, env: None, help, separated: false }
+    }
+
+    const fn complete(self, complete: Complete) -> Opt {
+        Opt { complete, ..self }
+    }
+
+    const fn env(self, env: &'static str) -> Opt {
+        Opt { env: Some(env), ..self }
+    }
+
+    const fn separated(self) -> Opt {
+        Opt { separated: true, ..self }
+    }
+
+    /// Returns the option the way it is written in `--help`, like `-m, --mode <MODE>`.
+    pub fn usage(&self) -> String {
+        let short = match self.short {
+            Some(c) => format!("-{c}, "),
+            None => "    ".to_owned(),
+        };
+        match self.value {
+            Some(value) => format!("{short}--{} <{value}>", self.long),
+            None => format!("{short}--{}", self.long),
+        }
+    }
+
+    /// Returns the first line of the help text, with the environment variable.
+    pub fn description(&self) -> String {
+        let help = self.help.lines().next().unwrap_or_default();
+        match self.env {
+            Some(env) => format!("{help} [env: {env}]"),
+            None => help.to_owned(),
+        }
+    }
+}
+
+/// Options of `run`, which the other commands ignore.
+pub const OPTIONS: &[Opt] = &[
+    Opt::value("mode", Some('m'), "MODE", "Change the display mode of your device")
+        .complete(Complete::Modes)
+        .env("DEEPCOOL_MODE"),
+    Opt::value("secondary", Some('s'), "MODE", "Change the secondary display mode of your device (if supported)")
+        .complete(Complete::Modes)
+        .env("DEEPCOOL_SECONDARY"),
+    Opt::value(
+        "pid",
+        None,
+        "ID",
+        "Specify the Product ID if multiple devices are connected\n\
+         (repeat to drive several devices, options after it apply to that device)",
+    ),
+    Opt::value("serial", None, "SERIAL", "Select the device of the last --pid by its serial number"),
+    Opt::flag("all", None, "Drive every connected DeepCool device"),
+    Opt::flag("hotplug", None, "Also drive the devices plugged in later, and drop them when removed"),
+    Opt::flag("takeover", None, "Make the running instance release the devices instead of failing"),
+    Opt::value("hidraw", None, "PATH", "Use a specific hidraw device (e.g. /dev/hidraw3)").complete(Complete::File),
+    Opt::value("output", None, "FILE", "Write the packets as hex to a file instead of a device (\"-\" for stdout)")
+        .complete(Complete::File),
+    Opt::value("config", None, "FILE", "Read the settings from a configuration file")
+        .complete(Complete::File)
+        .env("DEEPCOOL_CONFIG"),
+    Opt::value("root", None, "PATH", "Read /sys and /proc below a different root")
+        .complete(Complete::File)
+        .env("DEEPCOOL_ROOT"),
+    Opt::value("socket", None, "PATH", "Listen for control requests on a Unix socket")
+        .complete(Complete::File)
+        .env("DEEPCOOL_SOCKET"),
+    Opt::value("dbus", None, "BUS", "Publish the devices and readings on D-Bus (\"system\" or \"session\")")
+        .complete(Complete::Values(&["system", "session"]))
+        .env("DEEPCOOL_DBUS"),
+    Opt::value("gpuid", None, "VENDOR:ID", "Specify the nth GPU of a vendor to monitor (use ID 0 for integrated GPU)")
+        .env("DEEPCOOL_GPUID"),
+    Opt::value("update", Some('u'), "MILLISEC", "Change the update interval of the display [default: 1000]")
+        .env("DEEPCOOL_UPDATE")
+        .separated(),
+    Opt::flag("fahrenheit", Some('f'), "Change the temperature unit to °F"),
+    Opt::flag("alarm", Some('a'), "Enable the alarm"),
+    Opt::value("rotate", Some('r'), "DEGREE", "Rotate the display (LP Series only)")
+        .complete(Complete::Values(&["90", "180", "270"])),
+    Opt::flag("keep-on-exit", None, "Leave the last values on the display when the program exits"),
+    Opt::flag("verbose", None, "Also print the readings of every update"),
+    Opt::flag("quiet", None, "Only print errors"),
+];
+
+/// Option of every command that reads its own arguments.
+const HELP: Opt = Opt::flag("help", Some('h'), "Print help");
+
+/// Options of `ctl` and `status`.
+const CTL_OPTIONS: &[Opt] = &[
+    Opt::value("pid", None, "ID", "Only use the device with the Product ID"),
+    Opt::flag("json", None, "Print the answer as JSON"),
+    Opt::value("socket", None, "PATH", "Use a different control socket")
+        .complete(Complete::File)
+        .env("DEEPCOOL_SOCKET"),
+    HELP,
+];
+
+/// Options of `install`, the options of `run` are passed to the services.
+const INSTALL_OPTIONS: &[Opt] = &[
+    Opt::flag("dry-run", None, "Print the services instead of writing them"),
+    Opt::flag("systemd", None, "Create systemd services (detected by default)"),
+    Opt::flag("openrc", None, "Create OpenRC services (detected by default)"),
+    Opt::value("bin", None, "PATH", "Program started by the services [default: this program]").complete(Complete::File),
+    HELP,
+];
+
+/// Options of `udev-rules`.
+const UDEV_OPTIONS: &[Opt] = &[
+    Opt::flag("write", None, "Write the rules to /etc/udev/rules.d/99-deepcool-digital.rules"),
+    Opt::value("root", None, "PATH", "Read /sys below a different root")
+        .complete(Complete::File)
+        .env("DEEPCOOL_ROOT"),
+    HELP,
+];
+
+/// Word taken by a command, like `set-mode <MODE>` of `ctl`.
+pub struct Action {
+    pub name: &'static str,
+    /// Name of the value, like `<MODE>`.
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+impl Action {
+    const fn new(name: &'static str, value: Option<&'static str>, help: &'static str) -> Action {
+        Action { name, value, help }
+    }
+
+    /// Returns the action the way it is written in `--help`, like `set-mode <MODE>`.
+    pub fn usage(&self) -> String {
+        match self.value {
+            Some(value) => format!("{} <{value}>", self.name),
+            None => self.name.to_owned(),
+        }
+    }
+}
+
+/// Commands of `ctl`.
+const CTL_ACTIONS: &[Action] = &[
+    Action::new("status", None, "Print the running devices, their settings, and the last readings"),
+    Action::new("set-mode", Some("MODE"), "Change the display mode"),
+    Action::new("set-secondary", Some("MODE"), "Change the secondary display mode"),
+    Action::new("rotate", Some("DEGREE"), "Rotate the display (0, 90, 180, or 270)"),
+    Action::new("alarm", Some("on|off"), "Turn the alarm on or off"),
+    Action::new("off", None, "Turn the display off (use set-mode to turn it on again)"),
+    Action::new("reload", None, "Read the configuration file again"),
+];
+
+pub struct Command {
+    pub name: &'static str,
+    /// Name of the value, like `<SHELL>`.
+    pub value: Option<&'static str>,
+    /// Former option that runs the command, like `-l, --list`.
+    pub alias: Option<(char, &'static str)>,
+    /// Words taken by the command, like the actions of `ctl` or the shells of `completions`.
+    pub actions: &'static [Action],
+    /// Options of the command, the ones of `run` are [`OPTIONS`].
+    pub options: &'static [Opt],
+    /// The command also takes the options of `run`, like `install` which passes them to the services.
+    pub run_options: bool,
+    pub help: &'static str,
+    /// Description printed by the `--help` of the command.
+    pub about: &'static str,
+}
+
+impl Command {
+    const fn new(name: &'static str, help: &'static str) -> Command {
+        Command { name, value: None, alias: None, actions: &[], options: &[], run_options: false, help, about: "" }
+    }
+
+    const fn value(self, value: &'static str) -> Command {
+        Command { value: Some(value), ..self }
+    }
+
+    const fn alias(self, short: char, long: &'static str) -> Command {
+        Command { alias: Some((short, long)), ..self }
+    }
+
+    const fn actions(self, actions: &'static [Action]) -> Command {
+        Command { actions, ..self }
+    }
+
+    const fn options(self, options: &'static [Opt]) -> Command {
+        Command { options, ..self }
+    }
+
+    const fn run_options(self) -> Command {
+        Command { run_options: true, ..self }
+    }
+
+    const fn about(self, about: &'static str) -> Command {
+        Command { about, ..self }
+    }
+
+    /// Returns the tables of the options the command takes, its own first.
+    pub fn tables(&self) -> Vec<&'static [Opt]> {
+        let mut tables = vec![self.options];
+        if self.run_options {
+            tables.push(OPTIONS);
+        }
+        tables
+    }
+
+    /// Returns the words completed after the command: its actions, and its options.
+    pub fn words(&self) -> Vec<String> {
+        let options = self.tables().into_iter().flatten().flat_map(|opt| {
+            let short = opt.short.map(|c| format!("-{c}"));
+            short.into_iter().chain([format!("--{}", opt.long)])
+        });
+        self.actions.iter().map(|action| action.name.to_owned()).chain(options).collect()
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command::new("run", "Drive the devices with the options (default)"),
+    Command::new("list", "Print Product ID of the connected devices").alias('l', "list"),
+    Command::new("gpus", "Print all available GPUs").alias('g', "gpulist"),
+    Command::new("status", "Print the running devices and the last readings").options(CTL_OPTIONS),
+    Command::new("ctl", "Control the running program (see ctl --help)")
+        .value("COMMAND")
+        .actions(CTL_ACTIONS)
+        .options(CTL_OPTIONS)
+        .about("Changes the running program through its control socket."),
+    Command::new("install", "Create a service for every connected device (see install --help)")
+        .options(INSTALL_OPTIONS)
+        .run_options()
+        .about(
+            "Creates a service for every connected device. The other options are passed to the services:\n\
+             the ones before the first --pid apply to every device, the ones after it only to that device.",
+        ),
+    Command::new("udev-rules", "Print the udev rules for rootless mode (see udev-rules --help)")
+        .options(UDEV_OPTIONS)
+        .about(
+            "Prints the udev rules that let other users run the program on this machine:\n\
+             the DeepCool devices, the RAPL domains, and the GPU sensors only root can read.",
+        ),
+    Command::new("completions", "Print the completions of a shell (bash, zsh, or fish)")
+        .value("SHELL")
+        .actions(&[Action::new("bash", None, ""), Action::new("zsh", None, ""), Action::new("fish", None, "")]),
+    Command::new("man", "Print the man page"),
+    Command::new("help", "Print help").alias('h', "help"),
+    Command::new("version", "Print version").alias('v', "version"),
+];
+
+/// Returns the option written as `--long`, without the dashes, from the first table that has it.
+pub fn find_long(tables: &[&'static [Opt]], long: &str) -> Option<&'static Opt> {
+    tables.iter().flat_map(|table| table.iter()).find(|opt| opt.long == long)
+}
+
+pub fn find_short(tables: &[&'static [Opt]], short: char) -> Option<&'static Opt> {
+    tables.iter().flat_map(|table| table.iter()).find(|opt| opt.short == Some(short))
+}
+
+/// Returns the command with the name.
+pub fn command(name: &str) -> &'static Command {
+    COMMANDS.iter().find(|command| command.name == name).expect("unknown command")
+}
+
+/// Returns the command given by its name, or by its former option.
+pub fn find_command(arg: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| {
+        arg == command.name
+            || command.alias.is_some_and(|(short, long)| arg == format!("-{short}") || arg == format!("--{long}"))
+    })
+}
+
+pub fn print_help() {
+    println!("{} [COMMAND] [OPTIONS]", "Usage: deepcool-digital-linux".bold());
+    print_options(OPTIONS);
+    println!("\n{}", "Commands:".bold());
+    for command in COMMANDS {
+        let usage = match command.value {
+            Some(value) => format!("{} <{value}>", command.name),
+            None => command.name.to_owned(),
+        };
+        let alias = match command.alias {
+            Some((short, long)) => format!(" [alias: -{short}, --{long}]"),
+            None => String::new(),
+        };
+        let width = 20 - usage.len().min(20);
+        println!("  {}{:width$} {}{alias}", bold_words(&usage), "", command.help);
+    }
+}
+
+/// Prints the `--help` of a command that reads its own arguments.
+pub fn print_command_help(command: &Command) {
+    let value = command.value.map(|value| format!(" <{value}>")).unwrap_or_default();
+    println!("{} [OPTIONS]{value}", format!("Usage: deepcool-digital-linux {}", command.name).bold());
+    if !command.about.is_empty() {
+        println!("\n{}", command.about);
+    }
+    if !command.actions.is_empty() {
+        println!("\n{}", "Commands:".bold());
+        for action in command.actions {
+            let usage = action.usage();
+            let width = 20 - usage.len().min(20);
+            println!("  {}{:width$} {}", bold_words(&usage), "", action.help);
+        }
+    }
+    print_options(command.options);
+}
+
+fn print_options(options: &[Opt]) {
+    println!("\n{}", "Options:".bold());
+    for opt in options {
+        if opt.separated {
+            println!();
+        }
+        let usage = opt.usage();
+        let width = 24 - usage.len().min(24);
+        println!("  {}{:width$} {}", bold_words(&usage), "", opt.description());
+        for line in opt.help.lines().skip(1) {
+            println!("{:27}{line}", "");
+        }
+    }
+}
+
+/// Makes the options and commands bold, but not their values.
+fn bold_words(usage: &str) -> String {
+    usage
+        .split(' ')
+        .map(|word| match word.strip_suffix(',') {
+            _ if word.starts_with('<') => word.to_owned(),
+            Some(short) => format!("{},", short.bold()),
+            None => word.bold().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
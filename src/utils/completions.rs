@@ -0,0 +1,201 @@
+//! `completions` subcommand, which prints the completions of a shell, built from the definition of the command line.
+
+use crate::{devices::Mode, error};
+use super::cli::{Complete, Opt, COMMANDS, OPTIONS};
+use std::process::exit;
+
+const PROGRAM: &str = env!("CARGO_PKG_NAME");
+
+/// Runs the command given after `completions`, and exits.
+pub fn run(args: &[String]) -> ! {
+    let script = match args {
+        [shell] if shell == "bash" => bash(),
+        [shell] if shell == "zsh" => zsh(),
+        [shell] if shell == "fish" => fish(),
+        [] => {
+            error!("completions requires a shell (bash, zsh, or fish)");
+            exit(1);
+        }
+        [shell] => {
+            error!(format!("Unsupported shell {shell}, use bash, zsh, or fish"));
+            exit(1);
+        }
+        _ => {
+            error!("completions takes a single shell");
+            exit(1);
+        }
+    };
+    print!("{script}");
+    exit(0);
+}
+
+/// Returns the values completed after the option, `None` for files.
+fn values(opt: &Opt) -> Option<Vec<&'static str>> {
+    match opt.complete {
+        Complete::Nothing => Some(Vec::new()),
+        Complete::Modes => Some(Mode::ALL.iter().map(Mode::symbol).collect()),
+        Complete::Values(values) => Some(values.to_vec()),
+        Complete::File => None,
+    }
+}
+
+/// Returns the options of `run` and of the other commands, once each.
+fn all_options() -> Vec<&'static Opt> {
+    let mut all: Vec<&'static Opt> = Vec::new();
+    for opt in OPTIONS.iter().chain(COMMANDS.iter().flat_map(|command| command.options)) {
+        if !all.iter().any(|known| known.long == opt.long) {
+            all.push(opt);
+        }
+    }
+    all
+}
+
+/// Returns the ways to write the option, like `-m --mode`.
+fn names(opt: &Opt) -> Vec<String> {
+    let mut names: Vec<String> = opt.short.map(|c| format!("-{c}")).into_iter().collect();
+    names.push(format!("--{}", opt.long));
+    names
+}
+
+fn bash() -> String {
+    let function = format!("_{}", PROGRAM.replace('-', "_"));
+    let mut script = format!("# bash completion for {PROGRAM}\n\n{function}() {{\n");
+    script += "    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n\n";
+
+    // Step 1: the value of the previous option
+    script += "    case \"$prev\" in\n";
+    for opt in all_options().into_iter().filter(|opt| opt.value.is_some()) {
+        let action = match values(opt) {
+            Some(values) if values.is_empty() => "return".to_owned(),
+            Some(values) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return", values.join(" ")),
+            None => "COMPREPLY=($(compgen -f -- \"$cur\")); return".to_owned(),
+        };
+        script += &format!("        {})\n            {action}\n            ;;\n", names(opt).join("|"));
+    }
+    script += "    esac\n\n";
+
+    // Step 2: the words of the command
+    script += "    case \"${COMP_WORDS[1]}\" in\n";
+    for command in COMMANDS.iter().filter(|command| !command.words().is_empty()) {
+        script += &format!(
+            "        {})\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return\n            ;;\n",
+            command.name,
+            command.words().join(" ")
+        );
+    }
+    script += "    esac\n\n";
+
+    // Step 3: the commands and the options
+    let options: Vec<String> = OPTIONS.iter().flat_map(names).collect();
+    let commands: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+    script += &format!("    local words=\"{}\"\n", options.join(" "));
+    script += &format!("    [ \"$COMP_CWORD\" -eq 1 ] && words=\"{} $words\"\n", commands.join(" "));
+    script += "    COMPREPLY=($(compgen -W \"$words\" -- \"$cur\"))\n}\n\n";
+    script += &format!("complete -F {function} {PROGRAM}\n");
+    script
+}
+
+/// Escapes the text for the descriptions of `_arguments` and `_describe`.
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn zsh() -> String {
+    let function = format!("_{}", PROGRAM.replace('-', "_"));
+    let mut script = format!("#compdef {PROGRAM}\n\n{function}() {{\n");
+
+    // Step 1: the commands
+    script += "    local -a commands\n    commands=(\n";
+    for command in COMMANDS {
+        script += &format!("        '{}:{}'\n", command.name, zsh_escape(command.help));
+    }
+    script += "    )\n\n";
+
+    // Step 2: the words of the command
+    script += "    if (( CURRENT > 2 )); then\n        case $words[2] in\n";
+    for command in COMMANDS.iter().filter(|command| !command.words().is_empty()) {
+        script += &format!("            {})\n                compadd -- {}\n", command.name, command.words().join(" "));
+        script += "                return\n                ;;\n";
+    }
+    script += "        esac\n    fi\n\n";
+
+    // Step 3: the options, which can be repeated after every `--pid`
+    script += "    local state\n    _arguments -s -S \\\n";
+    for opt in OPTIONS {
+        let names = match opt.short {
+            Some(c) => format!("{{-{c},--{}}}", opt.long),
+            None => format!("--{}", opt.long),
+        };
+        let action = match (opt.value, values(opt)) {
+            (None, _) => String::new(),
+            (Some(value), Some(values)) if values.is_empty() => format!(":{value}:"),
+            (Some(value), Some(values)) => format!(":{value}:({})", values.join(" ")),
+            (Some(value), None) => format!(":{value}:_files"),
+        };
+        script += &format!("        '*'{names}'[{}]{action}' \\\n", zsh_escape(&opt.description()));
+    }
+    script += "        '1:: :->command'\n\n";
+    script += "    [[ $state == command ]] && _describe 'command' commands\n}\n\n";
+    script += &format!("{function} \"$@\"\n");
+    script
+}
+
+/// Escapes the text for a single-quoted fish string.
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish() -> String {
+    let mut script = format!("# fish completion for {PROGRAM}\n\ncomplete -c {PROGRAM} -f\n\n");
+
+    // Step 1: the commands
+    for command in COMMANDS {
+        script += &format!(
+            "complete -c {PROGRAM} -n __fish_use_subcommand -a {} -d '{}'\n",
+            command.name,
+            fish_escape(command.help)
+        );
+    }
+    script += "\n";
+
+    // Step 2: the actions and the options of the command
+    for command in COMMANDS.iter().filter(|command| !command.words().is_empty()) {
+        let condition = format!("-n '__fish_seen_subcommand_from {}'", command.name);
+        for action in command.actions {
+            script += &format!("complete -c {PROGRAM} {condition} -a {}", action.name);
+            if !action.help.is_empty() {
+                script += &format!(" -d '{}'", fish_escape(action.help));
+            }
+            script += "\n";
+        }
+        for opt in command.options {
+            script += &fish_option(opt, &condition);
+        }
+    }
+    script += "\n";
+
+    // Step 3: the options of `run`
+    for opt in OPTIONS {
+        script += &fish_option(opt, "");
+    }
+    script
+}
+
+/// Returns the completion of the option, given when the condition is met.
+fn fish_option(opt: &Opt, condition: &str) -> String {
+    let mut line = format!("complete -c {PROGRAM}");
+    if !condition.is_empty() {
+        line += &format!(" {condition}");
+    }
+    if let Some(c) = opt.short {
+        line += &format!(" -s {c}");
+    }
+    line += &format!(" -l {}", opt.long);
+    match (opt.value, values(opt)) {
+        (None, _) => (),
+        (Some(_), Some(values)) if values.is_empty() => line += " -x",
+        (Some(_), Some(values)) => line += &format!(" -x -a '{}'", values.join(" ")),
+        (Some(_), None) => line += " -r -F",
+    }
+    line + &format!(" -d '{}'\n", fish_escape(&opt.description()))
+}
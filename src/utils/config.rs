@@ -0,0 +1,553 @@
+//! Configuration file, read before the command line options so they can override it.
+//!
+//! A small subset of TOML is supported: `key = value` pairs with strings, integers, and booleans,
+//! and `[[device]]` sections for the settings of a single device.
+//!
+//! ```toml
+//! update = 1000
+//! fahrenheit = true
+//! gpuid = "nvidia:1"
+//!
+//! [[device]]
+//! pid = 16
+//! mode = "auto"
+//!
+//! [[device]]
+//! serial = "A1B2C3"
+//! mode = "gpu_temp"
+//! update = 500
+//! ```
+
+use crate::{
+    devices::{find_family, vendor_id_of, AlarmSupport, Mode},
+    monitor::gpu::pci::Vendor,
+};
+use super::args::{parse_gpuid, DeviceArgs};
+use std::{fmt, fs::read_to_string, io, path::Path, time::Duration};
+
+/// Location of the configuration file when `--config` is not given.
+pub const CONFIG_PATH: &str = "/etc/deepcool-digital-linux.toml";
+
+/// Display settings of a `[[device]]` section, or of the top of the file.
+///
+/// The missing values keep the ones given elsewhere.
+#[derive(Default)]
+pub struct Section {
+    /// Product ID of the device, 0 if the section is only keyed by serial.
+    pub pid: u16,
+    pub serial: Option<String>,
+    pub mode: Option<Mode>,
+    pub secondary: Option<Mode>,
+    pub update: Option<Duration>,
+    pub fahrenheit: Option<bool>,
+    pub alarm: Option<bool>,
+    pub rotate: Option<u16>,
+    pub keep_on_exit: Option<bool>,
+    /// Line of the section header.
+    line: usize,
+}
+
+impl Section {
+    /// Overrides the settings with the values given in the section.
+    pub fn apply(&self, args: &mut DeviceArgs) {
+        if self.pid > 0 {
+            args.pid = self.pid;
+        }
+        if self.serial.is_some() {
+            args.serial = self.serial.clone();
+        }
+        args.mode = self.mode.unwrap_or(args.mode);
+        args.secondary = self.secondary.unwrap_or(args.secondary);
+        args.update = self.update.unwrap_or(args.update);
+        args.fahrenheit = self.fahrenheit.unwrap_or(args.fahrenheit);
+        args.alarm = self.alarm.unwrap_or(args.alarm);
+        args.rotate = self.rotate.unwrap_or(args.rotate);
+        args.keep_on_exit = self.keep_on_exit.unwrap_or(args.keep_on_exit);
+    }
+
+    /// Checks the settings against the capabilities of the device, with the defaults of the file under them.
+    ///
+    /// Sections keyed only by serial are verified once the device is found.
+    /// A default the device has no use for is ignored, as it is on the command line,
+    /// but a mode it does not have is an error, since the device would not start with it.
+    fn validate(&self, defaults: &Section) -> Result<(), ConfigError> {
+        if self.pid == 0 {
+            return Ok(());
+        }
+        let error = |message: String, inherited: bool| {
+            let message = if inherited { format!("{message} (set at the top of the file)") } else { message };
+            Err(ConfigError::Line(self.line, message))
+        };
+
+        let Some(family) = find_family(vendor_id_of(self.pid), self.pid) else {
+            return error(format!("PID {} is not a supported device", self.pid), false);
+        };
+        let capabilities = family.capabilities();
+
+        // Every device can be turned off
+        if let Some((mode, inherited)) = setting(self.mode, defaults.mode).filter(|&(mode, _)| mode != Mode::Off) {
+            if capabilities.modes.is_empty() && !inherited {
+                return error(format!("the display mode of PID {} cannot be changed", self.pid), false);
+            }
+            if !capabilities.modes.is_empty() && !capabilities.modes.contains(&mode) {
+                let supported = symbols(capabilities.modes);
+                return error(
+                    format!(
+                        "display mode \"{}\" is not supported by PID {} (supported: {supported})",
+                        mode.symbol(),
+                        self.pid,
+                    ),
+                    inherited,
+                );
+            }
+        }
+        if let Some((secondary, inherited)) = setting(self.secondary, defaults.secondary) {
+            if capabilities.secondary.is_empty() && !inherited {
+                return error(format!("PID {} has no secondary display", self.pid), false);
+            }
+            if !capabilities.secondary.is_empty() && !capabilities.secondary.contains(&secondary) {
+                let supported = symbols(capabilities.secondary);
+                return error(
+                    format!(
+                        "secondary display mode \"{}\" is not supported by PID {} (supported: {supported})",
+                        secondary.symbol(),
+                        self.pid,
+                    ),
+                    inherited,
+                );
+            }
+        }
+        if self.fahrenheit == Some(true) && !capabilities.fahrenheit {
+            return error(format!("PID {} cannot display ˚F", self.pid), false);
+        }
+        if self.alarm == Some(true) && capabilities.alarm != AlarmSupport::Configurable {
+            return error(format!("the alarm of PID {} cannot be changed", self.pid), false);
+        }
+        if self.rotate.is_some_and(|r| r > 0) && !capabilities.rotate {
+            return error(format!("PID {} does not support display rotation", self.pid), false);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Config {
+    /// Settings outside of the sections, used by every device without its own settings.
+    pub defaults: Section,
+    /// Settings of the `[[device]]` sections.
+    pub devices: Vec<Section>,
+    pub gpuid: Option<(Vendor, u8)>,
+    pub all: bool,
+    pub hotplug: bool,
+}
+
+/// Error returned when the configuration file cannot be used.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(io::Error),
+    /// Invalid content at the line.
+    Line(usize, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(err) => write!(f, "{err}"),
+            ConfigError::Line(line, message) => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration file, returns `None` if it does not exist.
+    pub fn load(path: &Path) -> Result<Option<Config>, ConfigError> {
+        match read_to_string(path) {
+            Ok(text) => Config::parse(&text).map(Some),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(ConfigError::Read(err)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config {
+            defaults: Section::default(),
+            devices: Vec::new(),
+            gpuid: None,
+            all: false,
+            hotplug: false,
+        };
+
+        for (n, line) in text.lines().enumerate() {
+            let n = n + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // Section header
+            if line.starts_with('[') {
+                if line != "[[device]]" {
+                    return Err(ConfigError::Line(n, format!("unknown section {line}")));
+                }
+                config.devices.push(Section { line: n, ..Default::default() });
+                continue;
+            }
+
+            // Key-value pair
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(ConfigError::Line(n, format!("expected `key = value`, found \"{line}\"")));
+            };
+            let (key, value) = (key.trim(), parse_value(value.trim()).map_err(|err| ConfigError::Line(n, err))?);
+            let error = |message: &str| Err(ConfigError::Line(n, format!("{key}: {message}")));
+
+            let section = config.devices.last_mut().unwrap_or(&mut config.defaults);
+            let in_device = section.line > 0;
+            if in_device && matches!(key, "gpuid" | "all" | "hotplug") {
+                return error("only allowed outside of the [[device]] sections");
+            }
+            if !in_device && matches!(key, "pid" | "serial") {
+                return error("only allowed in [[device]] sections");
+            }
+
+            match (key, value) {
+                ("mode", Value::String(s)) => match Mode::get(&s) {
+                    Some(mode) => section.mode = Some(mode),
+                    None => return error("invalid display mode"),
+                },
+                ("secondary", Value::String(s)) => match Mode::get(&s) {
+                    Some(mode) => section.secondary = Some(mode),
+                    None => return error("invalid secondary display mode"),
+                },
+                ("update", Value::Integer(val)) => match val {
+                    100..=2000 => section.update = Some(Duration::from_millis(val as u64)),
+                    _ => return error("update interval must be between 100 and 2000"),
+                },
+                ("fahrenheit", Value::Boolean(b)) => section.fahrenheit = Some(b),
+                ("alarm", Value::Boolean(b)) => section.alarm = Some(b),
+                ("keep_on_exit", Value::Boolean(b)) => section.keep_on_exit = Some(b),
+                ("rotate", Value::Integer(val)) => match val {
+                    0 | 90 | 180 | 270 => section.rotate = Some(val as u16),
+                    _ => return error("rotation value must be one of 0, 90, 180, or 270"),
+                },
+                ("pid", Value::Integer(val)) => match u16::try_from(val) {
+                    Ok(pid) if pid > 0 => section.pid = pid,
+                    _ => return error("invalid PID"),
+                },
+                ("serial", Value::String(s)) => section.serial = Some(s),
+                ("gpuid", Value::String(s)) => match parse_gpuid(&s) {
+                    Some(gpuid) => config.gpuid = Some(gpuid),
+                    None => return error("invalid GPUID"),
+                },
+                ("all", Value::Boolean(b)) => config.all = b,
+                ("hotplug", Value::Boolean(b)) => config.hotplug = b,
+                (
+                    "mode" | "secondary" | "update" | "fahrenheit" | "alarm" | "rotate" | "keep_on_exit" | "pid"
+                    | "serial" | "gpuid" | "all" | "hotplug",
+                    _,
+                ) => return error("invalid value type"),
+                _ => return error("unknown setting"),
+            }
+        }
+
+        // Every device section must identify a device, and only use the features it supports
+        for (i, section) in config.devices.iter().enumerate() {
+            if section.pid == 0 && section.serial.is_none() {
+                return Err(ConfigError::Line(section.line, "[[device]] requires a pid or a serial".to_owned()));
+            }
+            if config.devices[..i].iter().any(|s| s.pid == section.pid && s.serial == section.serial) {
+                return Err(ConfigError::Line(section.line, "the device is configured more than once".to_owned()));
+            }
+            section.validate(&config.defaults)?;
+        }
+
+        Ok(config)
+    }
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+/// Parses a string, integer, or boolean value.
+fn parse_value(value: &str) -> Result<Value, String> {
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => (),
+    }
+
+    if let Some(literal) = value.strip_prefix('\'') {
+        return match literal.strip_suffix('\'') {
+            Some(s) if !s.contains('\'') => Ok(Value::String(s.to_owned())),
+            _ => Err(format!("invalid string {value}")),
+        };
+    }
+    if let Some(basic) = value.strip_prefix('"') {
+        let mut s = String::new();
+        let mut chars = basic.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if chars.as_str().is_empty() => return Ok(Value::String(s)),
+                '\\' => match chars.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    _ => return Err(format!("invalid escape sequence in {value}")),
+                },
+                '"' => break,
+                c => s.push(c),
+            }
+        }
+        return Err(format!("invalid string {value}"));
+    }
+
+    value.replace('_', "").parse::<i64>().map(Value::Integer).map_err(|_| format!("invalid value {value}"))
+}
+
+/// Removes the `#` comment from the end of the line, if it is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => (),
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Returns the value of the section, or the default of the file with `true` when the section does not set it.
+fn setting<T>(own: Option<T>, default: Option<T>) -> Option<(T, bool)> {
+    own.map(|value| (value, false)).or(default.map(|value| (value, true)))
+}
+
+/// Joins the symbols of the display modes.
+fn symbols(modes: &[Mode]) -> String {
+    modes.iter().map(|m| m.symbol()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the line and the message of the error found in the file.
+    fn error(text: &str) -> (usize, String) {
+        match Config::parse(text) {
+            Err(ConfigError::Line(line, message)) => (line, message),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("the file is accepted:\n{text}"),
+        }
+    }
+
+    /// Returns the string value.
+    fn string(value: &str) -> Result<String, String> {
+        match parse_value(value)? {
+            Value::String(s) => Ok(s),
+            _ => Err(format!("{value} is not a string")),
+        }
+    }
+
+    #[test]
+    fn values() {
+        assert!(matches!(parse_value("true"), Ok(Value::Boolean(true))));
+        assert!(matches!(parse_value("false"), Ok(Value::Boolean(false))));
+        assert!(matches!(parse_value("750"), Ok(Value::Integer(750))));
+        assert!(matches!(parse_value("1_000"), Ok(Value::Integer(1000))));
+        assert!(matches!(parse_value("-1"), Ok(Value::Integer(-1))));
+        assert_eq!(string("\"cpu_temp\"").unwrap(), "cpu_temp");
+        assert_eq!(string("\"\"").unwrap(), "");
+        assert_eq!(string("'C:\\serial'").unwrap(), "C:\\serial");
+        assert_eq!(string(r#""a \"b\" \\ c\td\n""#).unwrap(), "a \"b\" \\ c\td\n");
+
+        for invalid in ["True", "yes", "1.5", "0x10", "", "\"open", "'open", "'it's'", r#""\x""#, r#""a" b""#, "cpu"] {
+            assert!(parse_value(invalid).is_err(), "{invalid} is accepted");
+        }
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(strip_comment("update = 500 # ms"), "update = 500 ");
+        assert_eq!(strip_comment("# mode = \"auto\""), "");
+        assert_eq!(strip_comment("serial = \"A#1\" # B"), "serial = \"A#1\" ");
+        assert_eq!(strip_comment("serial = 'A#1'"), "serial = 'A#1'");
+        assert_eq!(strip_comment(r##"serial = "A\"#1" # B"##), r##"serial = "A\"#1" "##);
+        assert_eq!(strip_comment(r#"serial = "A\\" # B"#), r#"serial = "A\\" "#);
+    }
+
+    #[test]
+    fn sections() {
+        let config = Config::parse(
+            "# Every device\n\
+             update = 1_000\n\
+             fahrenheit = true\n\
+             gpuid = \"nvidia:1\"\n\
+             hotplug = true\n\
+             \n\
+             [[device]]\n\
+             pid = 16 # AK400 DIGITAL PRO\n\
+             keep_on_exit = true\n\
+             \n\
+             [[device]]\n\
+             serial = \"A1B2C3\"\n\
+             mode = \"gpu_temp\"\n\
+             secondary = 'cpu_usage'\n\
+             update = 500\n\
+             rotate = 90\n\
+             alarm = false\n",
+        )
+        .unwrap();
+        assert_eq!(config.defaults.update, Some(Duration::from_millis(1000)));
+        assert_eq!(config.defaults.fahrenheit, Some(true));
+        assert_eq!(config.defaults.mode, None);
+        assert!(config.gpuid == Some((Vendor::Nvidia, 1)));
+        assert!(config.hotplug && !config.all);
+
+        let [first, second] = &config.devices[..] else {
+            panic!("{} sections are read", config.devices.len());
+        };
+        assert_eq!((first.pid, first.serial.as_deref(), first.keep_on_exit), (16, None, Some(true)));
+        assert_eq!((second.pid, second.serial.as_deref()), (0, Some("A1B2C3")));
+        assert_eq!((second.mode, second.secondary), (Some(Mode::GpuTemperature), Some(Mode::CpuUsage)));
+        assert_eq!(second.update, Some(Duration::from_millis(500)));
+        assert_eq!((second.rotate, second.alarm), (Some(90), Some(false)));
+
+        // The sections override the defaults, which override the command line defaults
+        let mut args = DeviceArgs::default();
+        config.defaults.apply(&mut args);
+        second.apply(&mut args);
+        assert_eq!(args.serial.as_deref(), Some("A1B2C3"));
+        assert_eq!((args.mode, args.update, args.rotate), (Mode::GpuTemperature, Duration::from_millis(500), 90));
+        assert!(args.fahrenheit && !args.alarm && !args.keep_on_exit);
+
+        let empty = Config::parse("\n  # nothing\n").unwrap();
+        assert!(empty.devices.is_empty() && empty.defaults.mode.is_none() && empty.gpuid.is_none());
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert_eq!(error("update = 500\n[device]\n"), (2, "unknown section [device]".to_owned()));
+        assert_eq!(error("\nfahrenheit\n"), (2, "expected `key = value`, found \"fahrenheit\"".to_owned()));
+        assert_eq!(error("colour = \"red\""), (1, "colour: unknown setting".to_owned()));
+        assert_eq!(error("update = \"500\""), (1, "update: invalid value type".to_owned()));
+        assert_eq!(error("fahrenheit = 1"), (1, "fahrenheit: invalid value type".to_owned()));
+        assert_eq!(error("mode = cpu_temp"), (1, "invalid value cpu_temp".to_owned()));
+        assert_eq!(error("mode = \"cpu_temperature\""), (1, "mode: invalid display mode".to_owned()));
+        assert_eq!(
+            error("secondary = \"gpu\"\nsecondary = \"fan\""),
+            (2, "secondary: invalid secondary display mode".to_owned())
+        );
+        assert_eq!(error("update = 99"), (1, "update: update interval must be between 100 and 2000".to_owned()));
+        assert_eq!(error("update = 2001").0, 1);
+        assert_eq!(error("rotate = 45"), (1, "rotate: rotation value must be one of 0, 90, 180, or 270".to_owned()));
+        assert_eq!(error("gpuid = \"nvidia\""), (1, "gpuid: invalid GPUID".to_owned()));
+        assert_eq!(error("gpuid = \"radeon:1\"").0, 1);
+    }
+
+    #[test]
+    fn invalid_sections() {
+        assert_eq!(error("pid = 1"), (1, "pid: only allowed in [[device]] sections".to_owned()));
+        assert_eq!(error("serial = \"A1\""), (1, "serial: only allowed in [[device]] sections".to_owned()));
+        assert_eq!(
+            error("[[device]]\npid = 1\nall = true"),
+            (3, "all: only allowed outside of the [[device]] sections".to_owned())
+        );
+        assert_eq!(error("[[device]]\npid = 1\ngpuid = \"amd:0\"").0, 3);
+        assert_eq!(error("[[device]]\npid = 0"), (2, "pid: invalid PID".to_owned()));
+        assert_eq!(error("[[device]]\npid = 65536"), (2, "pid: invalid PID".to_owned()));
+        assert_eq!(error("[[device]]\nmode = \"auto\""), (1, "[[device]] requires a pid or a serial".to_owned()));
+        assert_eq!(
+            error("[[device]]\npid = 1\n\n[[device]]\npid = 1\n"),
+            (4, "the device is configured more than once".to_owned())
+        );
+        // The same PID is allowed for devices told apart by their serial
+        let config = Config::parse("[[device]]\npid = 1\nserial = \"A\"\n[[device]]\npid = 1\n").unwrap();
+        assert_eq!(config.devices.len(), 2);
+    }
+
+    #[test]
+    fn capabilities() {
+        assert_eq!(error("[[device]]\npid = 99"), (1, "PID 99 is not a supported device".to_owned()));
+        assert_eq!(
+            error("[[device]]\npid = 1\nmode = \"gpu_temp\""),
+            (1, "display mode \"gpu_temp\" is not supported by PID 1 (supported: auto, cpu_temp, cpu_usage)".to_owned())
+        );
+        assert_eq!(
+            error("[[device]]\npid = 16\nmode = \"auto\""),
+            (1, "the display mode of PID 16 cannot be changed".to_owned())
+        );
+        assert_eq!(
+            error("[[device]]\npid = 1\nsecondary = \"gpu_temp\""),
+            (1, "PID 1 has no secondary display".to_owned())
+        );
+        assert_eq!(error("[[device]]\npid = 5\nsecondary = \"cpu_temp\"").0, 1);
+        assert_eq!(error("[[device]]\npid = 5\nalarm = true"), (1, "the alarm of PID 5 cannot be changed".to_owned()));
+        assert_eq!(
+            error("[[device]]\npid = 1\nrotate = 90"),
+            (1, "PID 1 does not support display rotation".to_owned())
+        );
+
+        // Every device can be turned off, and the settings it supports are accepted
+        assert!(Config::parse("[[device]]\npid = 16\nmode = \"off\"").is_ok());
+        assert!(Config::parse("[[device]]\npid = 5\nmode = \"cpu_usage\"\nsecondary = \"gpu_usage\"").is_ok());
+        assert!(Config::parse("[[device]]\npid = 12\nrotate = 180\nfahrenheit = true").is_ok());
+        // The devices keyed by serial are verified once they are found
+        assert!(Config::parse("[[device]]\nserial = \"A1\"\nmode = \"psu\"\nrotate = 90").is_ok());
+    }
+
+    #[test]
+    fn defaults() {
+        // The defaults are verified against every device they apply to
+        assert_eq!(
+            error("mode = \"gpu_temp\"\n\n[[device]]\npid = 1\n"),
+            (
+                3,
+                "display mode \"gpu_temp\" is not supported by PID 1 (supported: auto, cpu_temp, cpu_usage) \
+                 (set at the top of the file)"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            error("secondary = \"cpu_temp\"\n[[device]]\npid = 5\n"),
+            (
+                2,
+                "secondary display mode \"cpu_temp\" is not supported by PID 5 (supported: gpu_temp, gpu_usage) \
+                 (set at the top of the file)"
+                    .to_owned()
+            )
+        );
+        // Unless the section overrides them
+        assert!(Config::parse("mode = \"gpu_temp\"\n[[device]]\npid = 1\nmode = \"auto\"\n").is_ok());
+        // The settings the device has no use for are ignored, as on the command line
+        let config = "mode = \"auto\"\nsecondary = \"gpu_temp\"\nrotate = 90\n[[device]]\npid = 16\n";
+        assert!(Config::parse(config).is_ok());
+        assert!(Config::parse("alarm = true\n[[device]]\npid = 5\n").is_ok());
+        // Without sections, they are verified once the devices are found
+        assert!(Config::parse("mode = \"psu\"\nsecondary = \"cpu_freq\"").is_ok());
+    }
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join(format!("deepcool-config-{}.toml", std::process::id()));
+        assert!(matches!(Config::load(&path), Ok(None)));
+
+        std::fs::write(&path, "update = 100\n").unwrap();
+        let config = Config::load(&path).unwrap().unwrap();
+        assert_eq!(config.defaults.update, Some(Duration::from_millis(100)));
+
+        std::fs::write(&path, "update = 100\nupdate = 10\n").unwrap();
+        let message = Config::load(&path).err().unwrap().to_string();
+        assert_eq!(message, "line 2: update: update interval must be between 100 and 2000");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
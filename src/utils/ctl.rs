@@ -0,0 +1,209 @@
+//! `ctl` subcommand, which changes the running program through its control socket
+//! instead of starting a new instance.
+
+use crate::{devices::Mode, error};
+use super::{
+    args::{from_env, split_command},
+    cli::{self, print_command_help},
+    json::{object, Value},
+    socket,
+};
+use colored::*;
+use std::{path::PathBuf, process::exit};
+
+/// Runs the command given after `ctl`, and exits.
+pub fn run(args: &[String]) -> ! {
+    let definition = cli::command("ctl");
+    let mut json = false;
+    let mut path: Option<PathBuf> = None;
+    let mut pid: Option<u16> = None;
+
+    let (given, values) = split_command(args, &definition.tables()).unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+    for (opt, value) in from_env(definition.options).into_iter().chain(given) {
+        let value = value.unwrap_or_default();
+        match opt.long {
+            "json" => json = true,
+            "socket" => path = Some(PathBuf::from(value)),
+            "pid" => match value.parse::<u16>() {
+                Ok(id) if id > 0 => pid = Some(id),
+                _ => {
+                    error!("Invalid PID");
+                    exit(1);
+                }
+            },
+            "help" => {
+                print_command_help(definition);
+                exit(0);
+            }
+            long => unreachable!("--{long} is not handled"),
+        }
+    }
+    let values: Vec<&str> = values.iter().map(String::as_str).collect();
+
+    // Step 1: build the request
+    let Some((&command, values)) = values.split_first() else {
+        error!("ctl requires a command (see ctl --help)");
+        exit(1);
+    };
+    let request = match (command, values) {
+        ("status", []) => object([("command", "status".into())]),
+        ("reload", []) if pid.is_none() => object([("command", "reload".into())]),
+        ("reload", []) => {
+            error!("--pid cannot be used with reload");
+            exit(1);
+        }
+        ("off", []) => set(pid, "mode", "off".into()),
+        ("set-mode", [mode]) => match Mode::get(mode) {
+            Some(_) => set(pid, "mode", (*mode).into()),
+            None => {
+                error!("Invalid display mode");
+                exit(1);
+            }
+        },
+        ("set-secondary", [mode]) => match Mode::get(mode) {
+            Some(_) => set(pid, "secondary", (*mode).into()),
+            None => {
+                error!("Invalid secondary display mode");
+                exit(1);
+            }
+        },
+        ("rotate", [degree]) => match degree.parse::<u16>() {
+            Ok(val) if [0, 90, 180, 270].contains(&val) => set(pid, "rotate", val.into()),
+            Ok(_) => {
+                error!("Rotation value must be one of 0, 90, 180, or 270");
+                exit(1);
+            }
+            Err(_) => {
+                error!("Invalid rotation value");
+                exit(1);
+            }
+        },
+        ("alarm", [state]) => match *state {
+            "on" => set(pid, "alarm", true.into()),
+            "off" => set(pid, "alarm", false.into()),
+            _ => {
+                error!("The alarm must be \"on\" or \"off\"");
+                exit(1);
+            }
+        },
+        ("status" | "reload" | "off", _) => {
+            error!(format!("{command} does not take a value"));
+            exit(1);
+        }
+        ("set-mode" | "set-secondary" | "rotate" | "alarm", _) => {
+            error!(format!("{command} requires a single value"));
+            exit(1);
+        }
+        _ => {
+            error!(format!("Invalid command {command}"));
+            exit(1);
+        }
+    };
+
+    // Step 2: send it to the running program
+    let path = path.unwrap_or_else(socket::default_path);
+    let answer = socket::send(&path, &request).unwrap_or_else(|err| {
+        error!(
+            "Failed to reach the running program at {}: {err}\n\
+             Make sure it is running, and that you have permission to use its socket.",
+            path.display()
+        );
+        exit(1);
+    });
+
+    // Step 3: print the answer
+    if json {
+        println!("{answer}");
+    }
+    if answer.get("ok").and_then(Value::as_bool) != Some(true) {
+        if !json {
+            error!(answer.get("error").and_then(Value::as_str).unwrap_or("Unknown error"));
+        }
+        exit(1);
+    }
+    if !json {
+        match command {
+            "status" => print_status(&answer, pid),
+            "reload" => println!("Configuration reloaded"),
+            _ => println!("Settings changed"),
+        }
+    }
+    exit(0);
+}
+
+/// Builds a `set` request that changes a single setting.
+fn set(pid: Option<u16>, key: &str, value: Value) -> Value {
+    let mut members = vec![("command".to_owned(), "set".into())];
+    if let Some(pid) = pid {
+        members.push(("pid".to_owned(), pid.into()));
+    }
+    members.push((key.to_owned(), value));
+    Value::Object(members)
+}
+
+/// Returns the member as text, without the quotes of strings.
+fn text(value: &Value, key: &str) -> String {
+    match value.get(key) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    }
+}
+
+/// Prints the running devices like `--list` and their settings like the program does at startup.
+fn print_status(answer: &Value, pid: Option<u16>) {
+    println!("Device list [{} | {}]", "PID".bright_green().bold(), "Name".bright_green());
+    println!("-----");
+    let devices = answer.get("devices").and_then(Value::as_array).unwrap_or_default();
+    let mut shown = 0;
+    for device in devices {
+        if pid.is_some_and(|pid| device.get("pid").and_then(Value::as_u64) != Some(pid as u64)) {
+            continue;
+        }
+        shown += 1;
+        println!("{} | {}", text(device, "pid").bright_green().bold(), text(device, "name").bright_green());
+        let (mode, secondary) = (text(device, "mode"), text(device, "secondary"));
+        if secondary.is_empty() {
+            println!("  DISP. MODE: {}", mode.bright_cyan());
+        } else {
+            println!("  DISP. MODE: {} | {}", mode.bright_cyan(), secondary.bright_cyan());
+        }
+        match device.get("rotate").and_then(Value::as_u64) {
+            Some(0) => println!("  ROTATION:   {}", "none".bright_black()),
+            Some(r) => println!("  ROTATION:   {}", format!("{r}°").bright_cyan()),
+            None => (),
+        }
+        let unit = if device.get("fahrenheit").and_then(Value::as_bool) == Some(true) { "°F" } else { "°C" };
+        println!("  TEMP. UNIT: {}", unit.bright_cyan());
+        match device.get("alarm").and_then(Value::as_bool) {
+            Some(true) => println!("  ALARM:      {}", "on".bright_green()),
+            Some(false) => println!("  ALARM:      {}", "off".bright_red()),
+            None => println!("  ALARM:      {}", "not supported".bright_black().italic()),
+        }
+        println!("  UPDATE:     {}", format!("{}ms", text(device, "update")).bright_cyan());
+    }
+    if shown == 0 {
+        println!("{}", "No device is running".bright_black().italic());
+    }
+
+    let readings = answer.get("readings").cloned().unwrap_or(Value::Null);
+    let reading = |key: &str, unit: &str| format!("{}{unit}", text(&readings, key)).bright_cyan();
+    println!("-----");
+    println!(
+        "CPU: {} | {} | {} | {}",
+        reading("cpu_usage", "%"),
+        reading("cpu_temp", "°C"),
+        reading("cpu_power", " W"),
+        reading("cpu_frequency", " MHz")
+    );
+    println!(
+        "GPU: {} | {} | {} | {}",
+        reading("gpu_usage", "%"),
+        reading("gpu_temp", "°C"),
+        reading("gpu_power", " W"),
+        reading("gpu_frequency", " MHz")
+    );
+}
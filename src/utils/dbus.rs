@@ -0,0 +1,550 @@
+//! D-Bus service, publishing the running devices and the readings of the monitors.
+//!
+//! - `/io/github/nortank12/DeepCoolDigital` implements `io.github.nortank12.DeepCoolDigital.Monitor`,
+//!   the last CPU and GPU readings.
+//! - `/io/github/nortank12/DeepCoolDigital/Device<N>` implements `io.github.nortank12.DeepCoolDigital.Device`
+//!   for every running device, numbered in the order they were found.
+//!
+//! The objects are kept in sync with the devices and the readings once per second,
+//! and every changed property is announced with `org.freedesktop.DBus.Properties.PropertiesChanged`.
+
+use crate::{
+    devices::{control::Slot, AlarmSupport, Mode, Options},
+    monitor::{Fields, Sampler, SystemSample},
+    warning,
+};
+use super::socket::Change;
+use std::{
+    sync::{Arc, Mutex},
+    thread::sleep,
+    time::Duration,
+};
+use zbus::{blocking::connection, block_on, fdo, interface};
+
+/// Well-known name of the service.
+pub const BUS_NAME: &str = "io.github.nortank12.DeepCoolDigital";
+const ROOT_PATH: &str = "/io/github/nortank12/DeepCoolDigital";
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Copy)]
+pub enum Bus {
+    System,
+    Session,
+}
+
+impl Bus {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Bus::System => "system",
+            Bus::Session => "session",
+        }
+    }
+
+    pub fn get(name: &str) -> Option<Bus> {
+        match name {
+            "system" => Some(Bus::System),
+            "session" => Some(Bus::Session),
+            _ => None,
+        }
+    }
+}
+
+/// Restarts the displays with the changed settings, or returns why it is not possible.
+pub type Update = fn(&Change, &[&Arc<Slot>]) -> Result<(), String>;
+
+/// Last readings of the monitors.
+struct Monitor {
+    sample: SystemSample,
+}
+
+#[interface(name = "io.github.nortank12.DeepCoolDigital.Monitor")]
+impl Monitor {
+    /// CPU utilization in percentage.
+    #[zbus(property)]
+    fn cpu_usage(&self) -> u8 {
+        self.sample.cpu_usage
+    }
+
+    /// CPU temperature in °C.
+    #[zbus(property)]
+    fn cpu_temperature(&self) -> f64 {
+        round(self.sample.cpu_temp)
+    }
+
+    /// CPU power consumption in Watts.
+    #[zbus(property)]
+    fn cpu_power(&self) -> u16 {
+        self.sample.cpu_power
+    }
+
+    /// Highest CPU core frequency in MHz.
+    #[zbus(property)]
+    fn cpu_frequency(&self) -> u16 {
+        self.sample.cpu_frequency
+    }
+
+    /// GPU utilization in percentage.
+    #[zbus(property)]
+    fn gpu_usage(&self) -> u8 {
+        self.sample.gpu_usage
+    }
+
+    /// GPU temperature in °C.
+    #[zbus(property)]
+    fn gpu_temperature(&self) -> f64 {
+        round(self.sample.gpu_temp)
+    }
+
+    /// GPU power consumption in Watts.
+    #[zbus(property)]
+    fn gpu_power(&self) -> u16 {
+        self.sample.gpu_power
+    }
+
+    /// GPU core frequency in MHz.
+    #[zbus(property)]
+    fn gpu_frequency(&self) -> u16 {
+        self.sample.gpu_frequency
+    }
+}
+
+/// Rounds the temperature to a tenth of a degree.
+fn round(celsius: f32) -> f64 {
+    (celsius as f64 * 10.0).round() / 10.0
+}
+
+/// Running device.
+struct Device {
+    slot: Arc<Slot>,
+    update: Update,
+}
+
+impl Device {
+    fn change(&self, change: Change) -> fdo::Result<()> {
+        (self.update)(&change, &[&self.slot]).map_err(fdo::Error::Failed)
+    }
+}
+
+#[interface(name = "io.github.nortank12.DeepCoolDigital.Device")]
+impl Device {
+    /// Changes the display mode, like `--mode`.
+    fn set_mode(&self, mode: &str) -> fdo::Result<()> {
+        let mode = Mode::get(mode).ok_or_else(|| fdo::Error::InvalidArgs(format!("invalid display mode \"{mode}\"")))?;
+        self.change(Change { mode: Some(mode), ..Default::default() })
+    }
+
+    /// Changes the secondary display mode, like `--secondary`.
+    fn set_secondary(&self, mode: &str) -> fdo::Result<()> {
+        let mode = Mode::get(mode)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("invalid secondary display mode \"{mode}\"")))?;
+        self.change(Change { secondary: Some(mode), ..Default::default() })
+    }
+
+    /// Keeps the display dark, until another display mode is set.
+    fn blank(&self) -> fdo::Result<()> {
+        self.change(Change { mode: Some(Mode::Off), ..Default::default() })
+    }
+
+    #[zbus(property)]
+    fn name(&self) -> String {
+        self.slot.family.name().to_owned()
+    }
+
+    #[zbus(property)]
+    fn pid(&self) -> u16 {
+        self.slot.pid
+    }
+
+    /// Serial number, empty if the device has none.
+    #[zbus(property)]
+    fn serial(&self) -> String {
+        self.slot.serial.clone().unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    fn mode(&self) -> String {
+        self.slot.resolved_options().mode.symbol().to_owned()
+    }
+
+    /// Secondary display mode, empty if the device has no secondary display.
+    #[zbus(property)]
+    fn secondary(&self) -> String {
+        self.slot.resolved_options().secondary.symbol().to_owned()
+    }
+
+    /// Rotation of the display in degrees.
+    #[zbus(property)]
+    fn rotation(&self) -> u16 {
+        self.slot.resolved_options().rotate
+    }
+
+    #[zbus(property)]
+    fn fahrenheit(&self) -> bool {
+        self.slot.resolved_options().fahrenheit && self.slot.family.capabilities().fahrenheit
+    }
+
+    /// `true` if the alarm is active, including the alarms hard-coded in the device.
+    #[zbus(property)]
+    fn alarm(&self) -> bool {
+        match self.slot.family.capabilities().alarm {
+            AlarmSupport::Configurable => self.slot.resolved_options().alarm,
+            AlarmSupport::HardCoded => true,
+            AlarmSupport::NotSupported => false,
+        }
+    }
+
+    /// Update interval of the display in milliseconds.
+    #[zbus(property)]
+    fn update_interval(&self) -> u32 {
+        self.slot.resolved_options().update.as_millis() as u32
+    }
+
+    #[zbus(property)]
+    fn supported_modes(&self) -> Vec<String> {
+        let capabilities = self.slot.family.capabilities();
+        let modes = if capabilities.modes.is_empty() {
+            std::slice::from_ref(&capabilities.default_mode)
+        } else {
+            capabilities.modes
+        };
+        modes.iter().chain([&Mode::Off]).map(|m| m.symbol().to_owned()).collect()
+    }
+
+    #[zbus(property)]
+    fn supported_secondary_modes(&self) -> Vec<String> {
+        self.slot.family.capabilities().secondary.iter().map(|m| m.symbol().to_owned()).collect()
+    }
+
+    #[zbus(property)]
+    fn supports_rotation(&self) -> bool {
+        self.slot.family.capabilities().rotate
+    }
+
+    #[zbus(property)]
+    fn supports_fahrenheit(&self) -> bool {
+        self.slot.family.capabilities().fahrenheit
+    }
+
+    /// `configurable`, `hard-coded`, or `not-supported`.
+    #[zbus(property)]
+    fn alarm_support(&self) -> String {
+        match self.slot.family.capabilities().alarm {
+            AlarmSupport::Configurable => "configurable",
+            AlarmSupport::HardCoded => "hard-coded",
+            AlarmSupport::NotSupported => "not-supported",
+        }
+        .to_owned()
+    }
+}
+
+/// Device object published on the bus, with the options last announced.
+struct Published {
+    slot: Arc<Slot>,
+    path: String,
+    options: Options,
+}
+
+pub struct Service {
+    connection: zbus::blocking::Connection,
+}
+
+impl Service {
+    /// Connects to the bus, and requests the name of the service.
+    pub fn connect(bus: Bus) -> zbus::Result<Service> {
+        let builder = match bus {
+            Bus::System => connection::Builder::system()?,
+            Bus::Session => connection::Builder::session()?,
+        };
+        Service::serve(builder.name(BUS_NAME)?)
+    }
+
+    /// Publishes the readings on the connection being built.
+    fn serve(builder: connection::Builder) -> zbus::Result<Service> {
+        let connection = builder.serve_at(ROOT_PATH, Monitor { sample: SystemSample::default() })?.build()?;
+        Ok(Service { connection })
+    }
+
+    /// Keeps the objects in sync with the running devices and the readings, until the program exits.
+    pub fn run(&self, slots: &Mutex<Vec<Arc<Slot>>>, sampler: &Sampler, update: Update) {
+        // Every reading is published, not only the ones on the displays
+        let _demand = sampler.subscribe(Fields::ALL);
+        let mut published = Vec::new();
+        let mut count = 0;
+        loop {
+            if let Err(err) = self.sync(&mut published, &mut count, slots, sampler, update) {
+                warning!(format!("The D-Bus service stopped: {err}"));
+                return;
+            }
+            sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn sync(
+        &self,
+        published: &mut Vec<Published>,
+        count: &mut usize,
+        slots: &Mutex<Vec<Arc<Slot>>>,
+        sampler: &Sampler,
+        update: Update,
+    ) -> zbus::Result<()> {
+        let server = self.connection.object_server();
+        let running = slots.lock().unwrap().clone();
+
+        // Step 1: remove the objects of the stopped devices
+        let (kept, stopped): (Vec<_>, Vec<_>) =
+            published.drain(..).partition(|p| running.iter().any(|slot| Arc::ptr_eq(slot, &p.slot)));
+        *published = kept;
+        for object in stopped {
+            server.remove::<Device, _>(object.path.as_str())?;
+        }
+
+        // Step 2: publish the new devices
+        for slot in running {
+            if published.iter().any(|p| Arc::ptr_eq(&slot, &p.slot)) {
+                continue;
+            }
+            *count += 1;
+            let path = format!("{ROOT_PATH}/Device{count}");
+            server.at(path.as_str(), Device { slot: slot.clone(), update })?;
+            published.push(Published { options: slot.resolved_options(), slot, path });
+        }
+
+        // Step 3: announce the changed settings
+        for object in published.iter_mut() {
+            let options = object.slot.resolved_options();
+            if options == object.options {
+                continue;
+            }
+            let previous = std::mem::replace(&mut object.options, options);
+            let iface = server.interface::<_, Device>(object.path.as_str())?;
+            let (device, emitter) = (iface.get(), iface.signal_emitter());
+            block_on(async {
+                if options.mode != previous.mode {
+                    device.mode_changed(emitter).await?;
+                }
+                if options.secondary != previous.secondary {
+                    device.secondary_changed(emitter).await?;
+                }
+                if options.rotate != previous.rotate {
+                    device.rotation_changed(emitter).await?;
+                }
+                if options.fahrenheit != previous.fahrenheit {
+                    device.fahrenheit_changed(emitter).await?;
+                }
+                if options.alarm != previous.alarm {
+                    device.alarm_changed(emitter).await?;
+                }
+                if options.update != previous.update {
+                    device.update_interval_changed(emitter).await?;
+                }
+                zbus::Result::Ok(())
+            })?;
+        }
+
+        // Step 4: announce the new readings
+        let sample = sampler.last();
+        let iface = server.interface::<_, Monitor>(ROOT_PATH)?;
+        let previous = std::mem::replace(&mut iface.get_mut().sample, sample);
+        if sample != previous {
+            let (monitor, emitter) = (iface.get(), iface.signal_emitter());
+            block_on(async {
+                if sample.cpu_usage != previous.cpu_usage {
+                    monitor.cpu_usage_changed(emitter).await?;
+                }
+                if sample.cpu_temp != previous.cpu_temp {
+                    monitor.cpu_temperature_changed(emitter).await?;
+                }
+                if sample.cpu_power != previous.cpu_power {
+                    monitor.cpu_power_changed(emitter).await?;
+                }
+                if sample.cpu_frequency != previous.cpu_frequency {
+                    monitor.cpu_frequency_changed(emitter).await?;
+                }
+                if sample.gpu_usage != previous.gpu_usage {
+                    monitor.gpu_usage_changed(emitter).await?;
+                }
+                if sample.gpu_temp != previous.gpu_temp {
+                    monitor.gpu_temperature_changed(emitter).await?;
+                }
+                if sample.gpu_power != previous.gpu_power {
+                    monitor.gpu_power_changed(emitter).await?;
+                }
+                if sample.gpu_frequency != previous.gpu_frequency {
+                    monitor.gpu_frequency_changed(emitter).await?;
+                }
+                zbus::Result::Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        devices::{ch_series, Device as _, Family},
+        monitor::{cpu::Cpu, fixture::Root, gpu::Gpu},
+    };
+    use std::{collections::HashMap, os::unix::net::UnixStream, sync::mpsc, thread};
+    use zbus::{
+        blocking::{Connection, MessageIterator},
+        message,
+        zvariant::OwnedValue,
+        Guid, MatchRule,
+    };
+
+    const DEVICE: &str = "io.github.nortank12.DeepCoolDigital.Device";
+    const MONITOR: &str = "io.github.nortank12.DeepCoolDigital.Monitor";
+
+    /// Restarts the display of the test device with the changed modes.
+    fn update(change: &Change, targets: &[&Arc<Slot>]) -> Result<(), String> {
+        for slot in targets {
+            let mut options = slot.options();
+            options.mode = change.mode.unwrap_or(options.mode);
+            options.secondary = change.secondary.unwrap_or(options.secondary);
+            let options = ch_series::Display::verify(&options).map_err(|err| err.to_string())?;
+            slot.replace(Box::new(ch_series::Display::new(&options)), options);
+        }
+        Ok(())
+    }
+
+    /// Reads a property of the object.
+    fn get<T: TryFrom<OwnedValue>>(client: &Connection, path: &str, interface: &str, name: &str) -> zbus::Result<T> {
+        let properties = Some("org.freedesktop.DBus.Properties");
+        let reply = client.call_method(None::<&str>, path, properties, "Get", &(interface, name))?;
+        let value: OwnedValue = reply.body().deserialize()?;
+        T::try_from(value).map_err(|_| zbus::Error::InvalidField)
+    }
+
+    /// Calls a method of the device.
+    fn call(client: &Connection, path: &str, method: &str, argument: &str) -> zbus::Result<()> {
+        client.call_method(None::<&str>, path, Some(DEVICE), method, &argument).map(|_| ())
+    }
+
+    /// Property changes announced by the service: the object, and the new values.
+    type Changes = mpsc::Receiver<(String, HashMap<String, OwnedValue>)>;
+
+    /// Collects the `PropertiesChanged` signals received by the client.
+    fn listen(client: &Connection) -> Changes {
+        let rule = MatchRule::builder()
+            .msg_type(message::Type::Signal)
+            .interface("org.freedesktop.DBus.Properties")
+            .unwrap()
+            .member("PropertiesChanged")
+            .unwrap()
+            .build();
+        let signals = MessageIterator::for_match_rule(rule, client, None).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // Until the service closes the connection
+            for signal in signals.map_while(Result::ok) {
+                let path = signal.header().path().unwrap().to_string();
+                let (_, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                    signal.body().deserialize().unwrap();
+                if sender.send((path, changed)).is_err() {
+                    return;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Waits for the property of the object to be announced, and returns its new value.
+    fn changed<T: TryFrom<OwnedValue>>(changes: &Changes, path: &str, name: &str) -> T {
+        loop {
+            let Ok((object, mut values)) = changes.recv_timeout(Duration::from_secs(5)) else {
+                panic!("{name} of {path} did not change");
+            };
+            if let Some(value) = values.remove(name).filter(|_| object == path) {
+                return T::try_from(value).unwrap_or_else(|_| panic!("invalid value of {name}"));
+            }
+        }
+    }
+
+    #[test]
+    fn service() {
+        let root = Root::cpu("dbus");
+        let sampler = Sampler::new(Cpu::new(root.path()), Gpu::None);
+        let options = Options {
+            mode: Mode::Default,
+            secondary: Mode::Default,
+            update: Duration::from_millis(1000),
+            fahrenheit: false,
+            alarm: false,
+            rotate: 0,
+            keep_on_exit: false,
+        };
+        let slot = Arc::new(Slot::new(5, Some("A1B2C3".to_owned()), Family::ChSeries, options));
+        let slots = Mutex::new(vec![slot.clone()]);
+
+        // The service and a client at the two ends of a private connection
+        let (server, client) = UnixStream::pair().unwrap();
+        let service = thread::spawn(move || {
+            Service::serve(connection::Builder::async_io_unix_stream(server).server(Guid::generate())?.p2p())
+        });
+        let client = connection::Builder::async_io_unix_stream(client).p2p().build().unwrap();
+        let service = service.join().unwrap().unwrap();
+        let changes = listen(&client);
+
+        let _demand = sampler.subscribe(Fields::ALL);
+        sampler.sample(Duration::from_millis(100));
+        let (mut published, mut count) = (Vec::new(), 0);
+        service.sync(&mut published, &mut count, &slots, &sampler, update).unwrap();
+
+        // Step 1: the readings and the running device are published
+        assert_eq!(changed::<f64>(&changes, ROOT_PATH, "CpuTemperature"), 45.0);
+        assert_eq!(get::<f64>(&client, ROOT_PATH, MONITOR, "CpuTemperature").unwrap(), 45.0);
+        assert_eq!(get::<u16>(&client, ROOT_PATH, MONITOR, "CpuFrequency").unwrap(), 3600);
+        assert_eq!(get::<u16>(&client, ROOT_PATH, MONITOR, "GpuPower").unwrap(), 0);
+
+        let device = format!("{ROOT_PATH}/Device1");
+        assert_eq!(get::<u16>(&client, &device, DEVICE, "Pid").unwrap(), 5);
+        assert_eq!(get::<String>(&client, &device, DEVICE, "Name").unwrap(), "CH Series");
+        assert_eq!(get::<String>(&client, &device, DEVICE, "Serial").unwrap(), "A1B2C3");
+        assert_eq!(get::<String>(&client, &device, DEVICE, "Mode").unwrap(), "cpu_temp");
+        let secondary = slot.resolved_options().secondary.symbol();
+        assert_eq!(get::<String>(&client, &device, DEVICE, "Secondary").unwrap(), secondary);
+        assert_eq!(get::<u32>(&client, &device, DEVICE, "UpdateInterval").unwrap(), 1000);
+        assert!(!get::<bool>(&client, &device, DEVICE, "Alarm").unwrap());
+        assert_eq!(get::<String>(&client, &device, DEVICE, "AlarmSupport").unwrap(), "not-supported");
+        assert_eq!(
+            get::<Vec<String>>(&client, &device, DEVICE, "SupportedModes").unwrap(),
+            ["auto", "cpu_temp", "cpu_usage", "off"]
+        );
+        assert_eq!(
+            get::<Vec<String>>(&client, &device, DEVICE, "SupportedSecondaryModes").unwrap(),
+            ["gpu_temp", "gpu_usage"]
+        );
+
+        // Step 2: a new display mode is applied, and announced
+        call(&client, &device, "SetMode", "cpu_usage").unwrap();
+        service.sync(&mut published, &mut count, &slots, &sampler, update).unwrap();
+        assert_eq!(changed::<String>(&changes, &device, "Mode"), "cpu_usage");
+        assert_eq!(get::<String>(&client, &device, DEVICE, "Mode").unwrap(), "cpu_usage");
+
+        match call(&client, &device, "SetMode", "fan") {
+            Err(zbus::Error::MethodError(name, ..)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.InvalidArgs");
+            }
+            _ => panic!("an invalid display mode is accepted"),
+        }
+        assert!(call(&client, &device, "SetSecondary", "cpu_temp").is_err());
+        assert_eq!(slot.options().mode, Mode::CpuUsage);
+
+        // Step 3: a new reading is announced
+        root.write("sys/class/hwmon/hwmon0/temp1_input", "50500\n");
+        sampler.sample(Duration::from_millis(100));
+        service.sync(&mut published, &mut count, &slots, &sampler, update).unwrap();
+        assert_eq!(changed::<f64>(&changes, ROOT_PATH, "CpuTemperature"), 50.5);
+
+        // Step 4: the object of a stopped device is removed, the next one gets a new number
+        slots.lock().unwrap().clear();
+        service.sync(&mut published, &mut count, &slots, &sampler, update).unwrap();
+        assert!(get::<u16>(&client, &device, DEVICE, "Pid").is_err());
+        slots.lock().unwrap().push(slot);
+        service.sync(&mut published, &mut count, &slots, &sampler, update).unwrap();
+        assert_eq!(get::<u16>(&client, &format!("{ROOT_PATH}/Device2"), DEVICE, "Pid").unwrap(), 5);
+    }
+}
@@ -0,0 +1,442 @@
+//! `install` subcommand, which creates a service for every connected device,
+//! started with the options given for that device.
+
+use crate::{devices::is_deepcool, error, CH510_PRODUCT_ID, CH510_VENDOR_ID};
+use super::{
+    args::{parse_from, split_command, Args, Command, DeviceArgs},
+    cli::{self, print_command_help},
+    log::Level,
+    socket,
+};
+use colored::*;
+use hidapi::HidApi;
+use std::{
+    env::current_exe,
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Init {
+    Systemd,
+    OpenRc,
+}
+
+impl Init {
+    /// Returns the init system that runs the machine.
+    fn detect() -> Option<Init> {
+        if Path::new("/run/systemd/system").is_dir() {
+            Some(Init::Systemd)
+        } else if Path::new("/sbin/openrc-run").exists() {
+            Some(Init::OpenRc)
+        } else {
+            None
+        }
+    }
+
+    fn path(self, name: &str) -> PathBuf {
+        match self {
+            Init::Systemd => PathBuf::from(format!("/etc/systemd/system/{name}.service")),
+            Init::OpenRc => PathBuf::from(format!("/etc/init.d/{name}")),
+        }
+    }
+}
+
+/// Connected device, told apart from identical ones by its serial number.
+struct Connected {
+    pid: u16,
+    serial: Option<String>,
+    product: String,
+}
+
+/// Service of a single device.
+struct Service {
+    name: String,
+    product: String,
+    /// Options of the program, without the program itself.
+    args: Vec<String>,
+}
+
+impl Service {
+    /// Returns the content of the service file.
+    fn render(&self, init: Init, program: &Path) -> String {
+        match init {
+            Init::Systemd => {
+                let command = [program.to_string_lossy().into_owned()]
+                    .iter()
+                    .chain(&self.args)
+                    .map(|arg| systemd_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!(
+                    "[Unit]\n\
+                     Description=DeepCool Digital ({})\n\
+                     \n\
+                     [Service]\n\
+                     Type=notify\n\
+                     ExecStart={command}\n\
+                     Restart=on-failure\n\
+                     RestartSec=5s\n\
+                     WatchdogSec=30s\n\
+                     \n\
+                     [Install]\n\
+                     WantedBy=multi-user.target\n",
+                    self.product
+                )
+            }
+            Init::OpenRc => {
+                let args = self.args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+                format!(
+                    "#!/sbin/openrc-run\n\
+                     \n\
+                     description=\"DeepCool Digital ({})\"\n\
+                     command={}\n\
+                     command_args=\"{}\"\n\
+                     command_background=1\n\
+                     pidfile=\"/run/{}.pid\"\n",
+                    self.product,
+                    shell_quote(&program.to_string_lossy()),
+                    args.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "\\$").replace('`', "\\`"),
+                    self.name
+                )
+            }
+        }
+    }
+}
+
+/// Runs the command given after `install`, and exits.
+pub fn run(args: &[String]) -> ! {
+    let definition = cli::command("install");
+    let mut init = None;
+    let mut dry_run = false;
+    let mut program: Option<PathBuf> = None;
+    let mut options: Vec<String> = Vec::new();
+
+    let (given, words) = split_command(args, &definition.tables()).unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+    if let Some(word) = words.first() {
+        error!(format!("Invalid option {word}"));
+        exit(1);
+    }
+    for (opt, value) in given {
+        match opt.long {
+            "dry-run" => dry_run = true,
+            "systemd" => init = Some(Init::Systemd),
+            "openrc" => init = Some(Init::OpenRc),
+            "bin" => program = value.map(PathBuf::from),
+            "help" => {
+                print_command_help(definition);
+                exit(0);
+            }
+            // The options of the devices are passed to the services
+            long => options.push(match value {
+                Some(value) => format!("--{long}={value}"),
+                None => format!("--{long}"),
+            }),
+        }
+    }
+
+    // Step 1: check the options like the services will
+    let parsed = match parse_from([vec![String::new()], options].concat()) {
+        Ok(Command::Run(parsed)) => parsed,
+        Ok(_) => {
+            error!("Only the options of the devices can be used with install");
+            exit(1);
+        }
+        Err(err) => {
+            error!(err);
+            exit(1);
+        }
+    };
+    let init = init.or_else(Init::detect).unwrap_or_else(|| {
+        error!("No supported init system was found, use --systemd or --openrc");
+        exit(1);
+    });
+    let program = program.or_else(|| current_exe().ok()).unwrap_or_else(|| {
+        error!("Failed to locate the program, use --bin");
+        exit(1);
+    });
+
+    // Step 2: find the connected devices like `run`
+    let api = HidApi::new().unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+    let mut found: Vec<Connected> = Vec::new();
+    for device in api.device_list() {
+        let (vid, pid) = (device.vendor_id(), device.product_id());
+        let serial = device.serial_number().filter(|s| !s.is_empty()).map(str::to_owned);
+        // Devices with several interfaces are listed more than once
+        if !is_deepcool(vid, pid) || found.iter().any(|d| d.pid == pid && d.serial == serial) {
+            continue;
+        }
+        let product = if vid == CH510_VENDOR_ID && pid == CH510_PRODUCT_ID {
+            "CH510-MESH-DIGITAL".to_owned()
+        } else {
+            device.product_string().unwrap_or("Unknown").to_owned()
+        };
+        found.push(Connected { pid, serial, product });
+    }
+
+    // Step 3: give every device the settings it was selected with
+    let services = services(&parsed, &found).unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+
+    // Step 4: print or write the services
+    for service in &services {
+        let path = init.path(&service.name);
+        let content = service.render(init, &program);
+        if dry_run {
+            println!("{}", format!("# {}", path.display()).bright_black());
+            println!("{content}");
+            continue;
+        }
+        if let Err(err) = write(&path, &content, init) {
+            error!(format!("Failed to write {}: {err}", path.display()));
+            exit(1);
+        }
+        println!("Service created: {}", path.display().to_string().bright_green());
+    }
+    if !dry_run {
+        let names: Vec<&str> = services.iter().map(|s| s.name.as_str()).collect();
+        println!("\nEnable the services with:");
+        match init {
+            Init::Systemd => println!("  sudo systemctl enable --now {}", names.join(" ")),
+            Init::OpenRc => {
+                for name in names {
+                    println!("  sudo rc-update add {name} default && sudo rc-service {name} start");
+                }
+            }
+        }
+    }
+    exit(0);
+}
+
+/// Returns the service of every selected device, with the options that select it and apply its settings.
+fn services(parsed: &Args, found: &[Connected]) -> Result<Vec<Service>, String> {
+    // Every service drives a single device
+    if parsed.all || parsed.hotplug || parsed.hidraw.is_some() || parsed.output.is_some() {
+        return Err("--all, --hotplug, --hidraw, and --output cannot be used with install".to_owned());
+    }
+    if parsed.socket != socket::default_path() {
+        return Err("--socket cannot be used with install, every service gets its own".to_owned());
+    }
+    let connected = |d: &&DeviceArgs| found.iter().any(|f| d.matches(f.pid, f.serial.as_deref()));
+    if let Some(missing) = parsed.devices.iter().find(|d| !connected(d)) {
+        let pid = missing.pid;
+        return Err(match &missing.serial {
+            Some(serial) => {
+                format!("No DeepCool device was found with the specified PID ({pid}) and serial ({serial})")
+            }
+            None => format!("No DeepCool device was found with the specified PID ({pid})"),
+        });
+    }
+    if found.is_empty() {
+        return Err("No DeepCool device was found".to_owned());
+    }
+
+    // Options of the program, the same for every service
+    let mut common: Vec<String> = Vec::new();
+    if let Some(config) = &parsed.config {
+        common.extend(["--config".to_owned(), config.to_string_lossy().into_owned()]);
+    }
+    if parsed.root != Path::new("/") {
+        common.extend(["--root".to_owned(), parsed.root.to_string_lossy().into_owned()]);
+    }
+    if let Some((vendor, id)) = parsed.gpuid {
+        common.extend(["--gpuid".to_owned(), format!("{}:{id}", vendor.name().to_lowercase())]);
+    }
+    if let Some(bus) = parsed.dbus {
+        common.extend(["--dbus".to_owned(), bus.name().to_owned()]);
+    }
+    if parsed.takeover {
+        common.push("--takeover".to_owned());
+    }
+    match parsed.log_level {
+        Level::Debug => common.push("--verbose".to_owned()),
+        Level::Error => common.push("--quiet".to_owned()),
+        _ => (),
+    }
+
+    let mut services: Vec<Service> = Vec::new();
+    for device in found {
+        // Without `--pid`, every device gets the options
+        let settings = match parsed.devices.iter().find(|d| d.matches(device.pid, device.serial.as_deref())) {
+            Some(settings) => settings,
+            None if parsed.devices.is_empty() => &parsed.defaults,
+            None => continue,
+        };
+
+        let mut name = format!("deepcool-digital-{}", slug(&device.product));
+        if services.iter().any(|s| s.name == name) {
+            name = match &device.serial {
+                Some(serial) => format!("{name}-{}", slug(serial)),
+                None => format!("{name}-{}", device.pid),
+            };
+        }
+        let mut args = common.clone();
+        args.extend(device_options(&DeviceArgs { pid: device.pid, serial: device.serial.clone(), ..settings.clone() }));
+        // Every service needs its own control socket
+        args.extend(["--socket".to_owned(), format!("/run/{name}.sock")]);
+        services.push(Service { name, product: device.product.clone(), args });
+    }
+    Ok(services)
+}
+
+/// Returns the options that select the device and apply the settings that differ from the defaults.
+fn device_options(device: &DeviceArgs) -> Vec<String> {
+    let defaults = DeviceArgs::default();
+    let mut args = vec!["--pid".to_owned(), device.pid.to_string()];
+    if let Some(serial) = &device.serial {
+        args.extend(["--serial".to_owned(), serial.clone()]);
+    }
+    if device.mode != defaults.mode {
+        args.extend(["--mode".to_owned(), device.mode.symbol().to_owned()]);
+    }
+    if device.secondary != defaults.secondary {
+        args.extend(["--secondary".to_owned(), device.secondary.symbol().to_owned()]);
+    }
+    if device.update != defaults.update {
+        args.extend(["--update".to_owned(), device.update.as_millis().to_string()]);
+    }
+    if device.rotate != defaults.rotate {
+        args.extend(["--rotate".to_owned(), device.rotate.to_string()]);
+    }
+    for (set, flag) in [
+        (device.fahrenheit, "--fahrenheit"),
+        (device.alarm, "--alarm"),
+        (device.keep_on_exit, "--keep-on-exit"),
+    ] {
+        if set {
+            args.push(flag.to_owned());
+        }
+    }
+    args
+}
+
+/// Writes the service file, executable for OpenRC.
+fn write(path: &Path, content: &str, init: Init) -> io::Result<()> {
+    fs::write(path, content)?;
+    if init == Init::OpenRc {
+        fs::set_permissions(path, Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// Turns the product name into a service name, like `ak500s-digital`.
+fn slug(product: &str) -> String {
+    let slug: String =
+        product.to_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    let words: Vec<&str> = slug.split('-').filter(|w| !w.is_empty()).collect();
+    if words.is_empty() { "device".to_owned() } else { words.join("-") }
+}
+
+/// Quotes an argument of `ExecStart=` if needed.
+fn systemd_quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || "\"'\\;$%".contains(c)) {
+        return arg.to_owned();
+    }
+    let escaped = arg.replace('\\', "\\\\").replace('"', "\\\"").replace('$', "$$").replace('%', "%%");
+    format!("\"{escaped}\"")
+}
+
+/// Quotes an argument for the shell if needed.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@+,".contains(c)) {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        match parse_from([""].iter().chain(args).copied()) {
+            Ok(Command::Run(args)) => *args,
+            _ => panic!("{args:?} is not parsed as run"),
+        }
+    }
+
+    fn connected(pid: u16, serial: Option<&str>, product: &str) -> Connected {
+        Connected { pid, serial: serial.map(str::to_owned), product: product.to_owned() }
+    }
+
+    /// Returns the name and the options of every service.
+    fn units(args: &[&str], found: &[Connected]) -> Result<Vec<(String, String)>, String> {
+        let services = services(&parse(args), found)?;
+        Ok(services.into_iter().map(|s| (s.name, s.args.join(" "))).collect())
+    }
+
+    #[test]
+    fn pid_forms() {
+        let found = [connected(16, None, "LP360"), connected(4, None, "AK620-DIGITAL")];
+        let expected = vec![("deepcool-digital-lp360".to_owned(), "--pid 16 --mode gpu_temp --fahrenheit \
+            --socket /run/deepcool-digital-lp360.sock".to_owned())];
+        assert_eq!(units(&["-f", "--pid=16", "-m", "gpu_temp"], &found), Ok(expected.clone()));
+        assert_eq!(units(&["--fahrenheit", "--pid", "16", "--mode=gpu_temp"], &found), Ok(expected));
+    }
+
+    #[test]
+    fn every_device_without_pid() {
+        let found = [connected(16, None, "LP360"), connected(4, None, "AK620-DIGITAL")];
+        assert_eq!(units(&["-u", "500", "--gpuid", "nvidia:1"], &found), Ok(vec![
+            (
+                "deepcool-digital-lp360".to_owned(),
+                "--gpuid nvidia:1 --pid 16 --update 500 --socket /run/deepcool-digital-lp360.sock".to_owned(),
+            ),
+            (
+                "deepcool-digital-ak620-digital".to_owned(),
+                "--gpuid nvidia:1 --pid 4 --update 500 --socket /run/deepcool-digital-ak620-digital.sock".to_owned(),
+            ),
+        ]));
+    }
+
+    #[test]
+    fn identical_devices() {
+        let found = [connected(4, Some("A1"), "AK620-DIGITAL"), connected(4, Some("B2"), "AK620-DIGITAL")];
+        let units = units(&["--pid", "4", "--serial", "B2", "-a", "--pid", "4", "--serial", "A1"], &found).unwrap();
+        assert_eq!(units, [
+            (
+                "deepcool-digital-ak620-digital".to_owned(),
+                "--pid 4 --serial A1 --socket /run/deepcool-digital-ak620-digital.sock".to_owned(),
+            ),
+            (
+                "deepcool-digital-ak620-digital-b2".to_owned(),
+                "--pid 4 --serial B2 --alarm --socket /run/deepcool-digital-ak620-digital-b2.sock".to_owned(),
+            ),
+        ]);
+    }
+
+    #[test]
+    fn invalid_selections() {
+        let found = [connected(4, Some("A1"), "AK620-DIGITAL")];
+        let err = units(&["--pid", "16"], &found).unwrap_err();
+        assert_eq!(err, "No DeepCool device was found with the specified PID (16)");
+        assert!(units(&["--pid", "4", "--serial", "B2"], &found).unwrap_err().contains("serial (B2)"));
+        assert!(units(&["--all"], &found).is_err());
+        assert!(units(&["--hidraw", "/dev/hidraw3"], &found).is_err());
+        assert_eq!(units(&[], &[]).unwrap_err(), "No DeepCool device was found");
+    }
+
+    #[test]
+    fn render() {
+        let service = Service {
+            name: "deepcool-digital-lp360".to_owned(),
+            product: "LP360".to_owned(),
+            args: vec!["--pid".to_owned(), "16".to_owned(), "--serial".to_owned(), "A 1$".to_owned()],
+        };
+        let systemd = service.render(Init::Systemd, Path::new("/usr/bin/deepcool-digital-linux"));
+        assert!(systemd.contains("\nExecStart=/usr/bin/deepcool-digital-linux --pid 16 --serial \"A 1$$\"\n"));
+        let openrc = service.render(Init::OpenRc, Path::new("/usr/bin/deepcool-digital-linux"));
+        assert!(openrc.contains("\ncommand_args=\"--pid 16 --serial 'A 1\\$'\"\n"));
+    }
+}
@@ -0,0 +1,369 @@
+//! Minimal JSON values for the control socket: parsed from a single line, and written on a single line.
+
+use std::fmt::{self, Write};
+
+/// Deepest nesting of arrays and objects, so a hostile request cannot exhaust the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in their original order.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the member of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if it is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= u64::MAX as f64 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Parses a complete JSON text.
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { text: text.as_bytes(), pos: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos < parser.text.len() {
+            return Err(format!("unexpected data at position {}", parser.pos));
+        }
+        Ok(value)
+    }
+}
+
+/// Builds an object from `(key, value)` pairs.
+pub fn object<const N: usize>(members: [(&str, Value); N]) -> Value {
+    Value::Object(members.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+macro_rules! from_number {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Value {
+            fn from(value: $t) -> Self {
+                Value::Number(value as f64)
+            }
+        })*
+    };
+}
+from_number!(u8, u16, u32, u64, usize, f32);
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) if n.is_finite() => write!(f, "{n}"),
+            Value::Number(_) => f.write_str("null"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    /// Arrays and objects the position is in.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while self.text.get(self.pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(format!("invalid value at position {}", self.pos))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.whitespace();
+        match self.text.get(self.pos) {
+            Some(b'n') => self.expect("null", Value::Null),
+            Some(b't') => self.expect("true", Value::Bool(true)),
+            Some(b'f') => self.expect("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[' | b'{') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(format!("too deeply nested at position {}", self.pos));
+                }
+                self.depth += 1;
+                let value = if self.text[self.pos] == b'[' { self.array() } else { self.object() };
+                self.depth -= 1;
+                value
+            }
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                while self.text.get(self.pos).is_some_and(|b| b"+-.eE0123456789".contains(b)) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or("");
+                number.parse().map(Value::Number).map_err(|_| format!("invalid number at position {start}"))
+            }
+            Some(_) => Err(format!("invalid value at position {}", self.pos)),
+            None => Err("unexpected end of data".to_owned()),
+        }
+    }
+
+    /// Reads an array, the position is at its opening bracket.
+    fn array(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.text.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(format!("expected ',' or ']' at position {}", self.pos)),
+            }
+        }
+    }
+
+    /// Reads an object, the position is at its opening brace.
+    fn object(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.whitespace();
+        if self.text.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.whitespace();
+            if self.text.get(self.pos) != Some(&b'"') {
+                return Err(format!("expected a key at position {}", self.pos));
+            }
+            let key = self.string()?;
+            self.whitespace();
+            if self.text.get(self.pos) != Some(&b':') {
+                return Err(format!("expected ':' at position {}", self.pos));
+            }
+            self.pos += 1;
+            members.push((key, self.value()?));
+            self.whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(format!("expected ',' or '}}' at position {}", self.pos)),
+            }
+        }
+    }
+
+    /// Reads the 4 hex digits of a `\u` escape, `None` if they are not a number.
+    fn hex(&self, at: usize) -> Result<Option<u32>, String> {
+        let hex = self.text.get(at..at + 4).ok_or("invalid escape sequence")?;
+        Ok(std::str::from_utf8(hex).ok().and_then(|h| u32::from_str_radix(h, 16).ok()))
+    }
+
+    /// Reads a string, the position is at its opening quote.
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.text.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(bytes).map_err(|_| "invalid UTF-8 in string".to_owned());
+                }
+                Some(b'\\') => {
+                    let escaped = match self.text.get(self.pos + 1) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let high = self.hex(self.pos + 2)?;
+                            self.pos += 4;
+                            // Characters beyond U+FFFF are escaped as a surrogate pair
+                            let code = match (high, self.text.get(self.pos + 2..self.pos + 4)) {
+                                (Some(high @ 0xd800..=0xdbff), Some(b"\\u")) => match self.hex(self.pos + 4) {
+                                    Ok(Some(low @ 0xdc00..=0xdfff)) => {
+                                        self.pos += 6;
+                                        Some(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+                                    }
+                                    _ => Some(high),
+                                },
+                                _ => high,
+                            };
+                            code.and_then(char::from_u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(format!("invalid escape sequence at position {}", self.pos)),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                    self.pos += 2;
+                }
+                Some(&byte) => {
+                    bytes.push(byte);
+                    self.pos += 1;
+                }
+                None => return Err("unterminated string".to_owned()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let text = r#" {"a": [1, -2.5, 1e3], "b": {"c": null}, "d": true, "e": false, "f": ""} "#;
+        let value = Value::parse(text).unwrap();
+        assert_eq!(value.get("a").and_then(Value::as_array).map(<[Value]>::len), Some(3));
+        assert_eq!(value.get("a").and_then(Value::as_array).map(|a| a[2].as_u64()), Some(Some(1000)));
+        assert!(value.get("b").and_then(|b| b.get("c")) == Some(&Value::Null));
+        assert_eq!(value.get("d").and_then(Value::as_bool), Some(true));
+        assert_eq!(value.get("f").and_then(Value::as_str), Some(""));
+        // Members keep their order when written back
+        assert_eq!(value.to_string(), r#"{"a":[1,-2.5,1000],"b":{"c":null},"d":true,"e":false,"f":""}"#);
+    }
+
+    #[test]
+    fn escapes() {
+        let value = Value::parse(r#""q\" b\\ s\/ \n\r\t \u00e9 é \ud83d\ude00 😀 \ud83d""#).unwrap();
+        // A lone surrogate is not a character
+        assert_eq!(value.as_str(), Some("q\" b\\ s/ \n\r\t é é 😀 😀 \u{fffd}"));
+        // Written back on a single line
+        assert_eq!(Value::from("a\"b\\c\nd\u{1}").to_string(), r#""a\"b\\c\nd\u0001""#);
+        assert!(Value::parse(&Value::from("\n\u{1}😀").to_string()) == Ok(Value::from("\n\u{1}😀")));
+    }
+
+    #[test]
+    fn malformed() {
+        for text in [
+            "", " ", "{", "[1,", "[1 2]", r#"{"a" 1}"#, r#"{a: 1}"#, r#"{"a":1,}"#, "nul", "tru", "-", "1.2.3",
+            r#""open"#, r#""\x""#, r#""\u12""#, "{} {}", "[]]",
+        ] {
+            assert!(Value::parse(text).is_err(), "{text:?} was parsed");
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Value::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Value::parse(&nested(MAX_DEPTH + 1)).is_err());
+        // Deep enough to overflow the stack without the limit
+        assert!(Value::parse(&"[{\"a\":".repeat(100_000)).is_err());
+    }
+}
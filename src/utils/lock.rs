@@ -0,0 +1,244 @@
+//! Advisory locks of the driven devices under `/run`, so two instances never write to the same display.
+//!
+//! Every device has a lock file named after its serial number, or its hidraw node without one.
+//! It is held with `flock` as long as the device is driven, and tells the process that holds it
+//! and its control socket, so another instance can report it or ask it to release the device.
+
+use super::{
+    json::{object, Value},
+    socket,
+};
+use std::{
+    env::var_os,
+    fmt::{self, Display, Formatter},
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Write},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{DirBuilderExt, OpenOptionsExt},
+    },
+    path::{Path, PathBuf},
+    process,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Location of the locks when the program runs as root.
+pub const LOCK_DIR: &str = "/run/deepcool-digital-linux";
+
+/// Longest time to wait for the running instance to release the device.
+///
+/// Its display loop returns after the current update, or at once while it waits to reconnect.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the name of the lock of a device, like `3633-0004-A1B2C3` or `hidraw3`.
+pub fn key(vid: u16, pid: u16, serial: Option<&str>, path: &str) -> String {
+    let key = match serial.filter(|s| !s.is_empty()) {
+        Some(serial) => format!("{vid:04x}-{pid:04x}-{serial}"),
+        None => path.rsplit('/').next().unwrap_or(path).to_owned(),
+    };
+    key.chars().map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect()
+}
+
+/// Returns the directory of the locks.
+///
+/// Other users put them in their runtime directory, since they cannot write to `/run`.
+fn dir() -> PathBuf {
+    match var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !is_root() => Path::new(&dir).join("deepcool-digital-linux"),
+        _ => PathBuf::from(LOCK_DIR),
+    }
+}
+
+fn is_root() -> bool {
+    // SAFETY: `geteuid` cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Takes the lock of the file without waiting, returns `false` if another process holds it.
+fn try_flock(file: &File) -> io::Result<bool> {
+    // SAFETY: the file descriptor stays open during the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    match io::Error::last_os_error() {
+        err if err.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(false),
+        err => Err(err),
+    }
+}
+
+/// Process that holds the lock of a device.
+#[derive(Debug)]
+pub struct Holder {
+    pub pid: Option<u32>,
+    /// Control socket of the process, to ask it to release the device.
+    pub socket: Option<PathBuf>,
+}
+
+impl Holder {
+    /// Reads the process written to the lock file.
+    fn read(path: &Path) -> Holder {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let mut lines = content.lines();
+        Holder {
+            pid: lines.next().and_then(|pid| pid.parse().ok()),
+            socket: lines.next().filter(|socket| !socket.is_empty()).map(PathBuf::from),
+        }
+    }
+}
+
+impl Display for Holder {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Some(pid) = self.pid else {
+            return write!(f, "another process");
+        };
+        write!(f, "process {pid}")?;
+        // Name of the program, and the service that started it
+        let name = fs::read_to_string(format!("/proc/{pid}/comm")).ok();
+        let unit = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok().and_then(|cgroup| {
+            let path = cgroup.lines().next()?.rsplit(':').next()?.to_owned();
+            path.split('/').rev().find(|part| part.ends_with(".service")).map(str::to_owned)
+        });
+        match (name, unit) {
+            (Some(name), Some(unit)) => write!(f, " ({}, {unit})", name.trim()),
+            (Some(name), None) => write!(f, " ({})", name.trim()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    Held(Holder),
+    /// The holder did not release the device when asked to.
+    Refused(Holder, String),
+    Io(io::Error),
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            LockError::Held(holder) => write!(
+                f,
+                "it is already driven by {holder}\n\
+                 Stop it, or use --takeover to make it release the device."
+            ),
+            LockError::Refused(holder, err) => write!(f, "failed to take it over from {holder}: {err}"),
+            LockError::Io(err) => write!(f, "failed to lock it: {err}"),
+        }
+    }
+}
+
+impl From<io::Error> for LockError {
+    fn from(err: io::Error) -> Self {
+        LockError::Io(err)
+    }
+}
+
+/// Lock of a device, released when it is dropped.
+pub struct Lock {
+    pub key: String,
+    /// The lock lasts as long as the file is open.
+    _file: File,
+}
+
+impl Lock {
+    /// Takes the lock of the device, writing the process and its control socket to it.
+    pub fn acquire(key: &str, socket: &Path) -> Result<Lock, LockError> {
+        // Instances of root hold their locks in `/run`, which other users can only read
+        let system = Path::new(LOCK_DIR).join(key);
+        if !is_root() {
+            if let Ok(file) = File::open(&system) {
+                if !try_flock(&file)? {
+                    return Err(LockError::Held(Holder::read(&system)));
+                }
+            }
+        }
+        Lock::acquire_in(&dir(), key, socket)
+    }
+
+    /// Takes the lock of the device in the directory.
+    pub fn acquire_in(dir: &Path, key: &str, socket: &Path) -> Result<Lock, LockError> {
+        DirBuilder::new().recursive(true).mode(0o755).create(dir)?;
+        let path = dir.join(key);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o644).open(&path)?;
+        if !try_flock(&file)? {
+            return Err(LockError::Held(Holder::read(&path)));
+        }
+        file.set_len(0)?;
+        write!(file, "{}\n{}\n", process::id(), socket.display())?;
+        Ok(Lock { key: key.to_owned(), _file: file })
+    }
+
+    /// Takes the lock of the device, asking the running instance to release it first if it holds it.
+    pub fn take_over(key: &str, socket: &Path) -> Result<Lock, LockError> {
+        let holder = match Lock::acquire(key, socket) {
+            Err(LockError::Held(holder)) => holder,
+            result => return result,
+        };
+        let Some(path) = holder.socket.clone() else {
+            return Err(LockError::Refused(holder, "it has no control socket".to_owned()));
+        };
+        let request = object([("command", "release".into()), ("device", key.into())]);
+        match socket::send(&path, &request) {
+            Ok(answer) if answer.get("ok").and_then(Value::as_bool) == Some(true) => (),
+            Ok(answer) => {
+                let err = answer.get("error").and_then(Value::as_str).unwrap_or("unknown error").to_owned();
+                return Err(LockError::Refused(holder, err));
+            }
+            Err(err) => return Err(LockError::Refused(holder, format!("{}: {err}", path.display()))),
+        }
+
+        // The display loop of the holder returns after its current update
+        let timeout = Instant::now() + TAKEOVER_TIMEOUT;
+        loop {
+            match Lock::acquire(key, socket) {
+                Err(LockError::Held(holder)) if Instant::now() >= timeout => {
+                    return Err(LockError::Refused(holder, "the device was not released in time".to_owned()));
+                }
+                Err(LockError::Held(_)) => thread::sleep(Duration::from_millis(50)),
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    /// Returns an empty directory for the locks of a test.
+    fn lock_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("deepcool-lock-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(key(0x3633, 4, Some("A1B2C3"), "/dev/hidraw3"), "3633-0004-A1B2C3");
+        assert_eq!(key(0x3633, 4, Some(""), "/dev/hidraw3"), "hidraw3");
+        assert_eq!(key(0x3633, 4, None, "/dev/hidraw3"), "hidraw3");
+        assert_eq!(key(0x3633, 4, Some("A/B C"), "/dev/hidraw3"), "3633-0004-A_B_C");
+    }
+
+    #[test]
+    fn held_until_dropped() {
+        let dir = lock_dir("held");
+        let socket = Path::new("/tmp/deepcool.sock");
+        let lock = Lock::acquire_in(&dir, "hidraw3", socket).unwrap();
+        match Lock::acquire_in(&dir, "hidraw3", socket) {
+            Err(LockError::Held(holder)) => {
+                assert_eq!(holder.pid, Some(process::id()));
+                assert_eq!(holder.socket.as_deref(), Some(socket));
+            }
+            _ => panic!("the lock is taken twice"),
+        }
+        // Other devices are not affected
+        assert!(Lock::acquire_in(&dir, "hidraw4", socket).is_ok());
+        drop(lock);
+        assert!(Lock::acquire_in(&dir, "hidraw3", socket).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
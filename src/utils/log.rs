@@ -0,0 +1,294 @@
+//! Levelled messages of the program, printed with `error!`, `warning!`, `info!`, and `debug!`.
+//!
+//! Errors and warnings go to stderr, the other messages to stdout, unless stdout carries the hex dump of `--output -`.
+//! Colors are only used on a terminal without `NO_COLOR`, and the lines written to journald
+//! start with their syslog priority (like `<4>`), so they keep their severity in the journal.
+//! A warning or error repeated within a minute is only counted, and printed again with the count
+//! once the minute is over, or when the program exits.
+
+use std::{
+    collections::HashMap,
+    env::var_os,
+    fmt::Display,
+    io::{stderr, stdout, IsTerminal, Write},
+    mem::MaybeUninit,
+    os::fd::{AsRawFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        LazyLock, Mutex, Once, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Shortest time between two identical warnings or errors.
+const REPEAT_WINDOW: Duration = Duration::from_secs(60);
+/// Time between two checks for the counts to print.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// Returns the syslog priority of the level.
+    const fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warning => 4,
+            Level::Info => 6,
+            Level::Debug => 7,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+/// Every message goes to stderr.
+static ONLY_STDERR: AtomicBool = AtomicBool::new(false);
+static REPEATS: LazyLock<Mutex<Repeats>> = LazyLock::new(Mutex::default);
+/// Starts the thread that prints the counts, with the first message held back.
+static FLUSHER: Once = Once::new();
+
+/// Warnings and errors printed recently, with their level and the number of times they were held back since.
+#[derive(Default)]
+struct Repeats(HashMap<String, (Level, Instant, u32)>);
+
+impl Repeats {
+    /// Returns the message to print, with the number of times it was held back,
+    /// or `None` if it was printed within the window.
+    fn check(&mut self, level: Level, message: &str, now: Instant) -> Option<String> {
+        match self.0.get_mut(message) {
+            Some((_, printed, count)) if now.duration_since(*printed) < REPEAT_WINDOW => {
+                *count += 1;
+                None
+            }
+            Some((_, printed, count)) => {
+                *printed = now;
+                Some(counted(message, std::mem::take(count)))
+            }
+            None => {
+                self.0.insert(message.to_owned(), (level, now, 0));
+                Some(message.to_owned())
+            }
+        }
+    }
+
+    /// Returns the messages held back in a window that ended, with their count, and forgets the ended windows.
+    fn expired(&mut self, now: Instant) -> Vec<(Level, String)> {
+        let mut expired = Vec::new();
+        self.0.retain(|message, (level, printed, count)| {
+            if now.duration_since(*printed) < REPEAT_WINDOW {
+                return true;
+            }
+            if *count > 0 {
+                expired.push((*level, counted(message, *count)));
+            }
+            false
+        });
+        expired
+    }
+
+    /// Returns every message held back, with its count, and forgets them all.
+    fn drain(&mut self) -> Vec<(Level, String)> {
+        let drained = self.0.drain().filter(|(_, (_, _, count))| *count > 0);
+        drained.map(|(message, (level, _, count))| (level, counted(&message, count))).collect()
+    }
+}
+
+/// Adds the number of times the message was held back to its first line.
+fn counted(message: &str, count: u32) -> String {
+    let mut message = message.to_owned();
+    if count > 0 {
+        let end = message.find('\n').unwrap_or(message.len());
+        message.insert_str(end, &format!(" (repeated {count} more times)"));
+    }
+    message
+}
+
+/// Where the messages go, detected before the first one is printed.
+struct Output {
+    /// stderr is a terminal, and colors are not disabled.
+    color: bool,
+    /// stdout is read by journald.
+    stdout_journal: bool,
+    /// stderr is read by journald.
+    stderr_journal: bool,
+}
+
+impl Output {
+    fn detect() -> Self {
+        Output {
+            color: stderr().is_terminal() && var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            stdout_journal: is_journal(stdout().as_raw_fd()),
+            stderr_journal: is_journal(stderr().as_raw_fd()),
+        }
+    }
+}
+
+/// Returns `true` if the file descriptor is the stream that systemd connected to journald.
+fn is_journal(fd: RawFd) -> bool {
+    let Some(stream) = var_os("JOURNAL_STREAM") else {
+        return false;
+    };
+    let Some((dev, ino)) = stream.to_str().and_then(|stream| stream.split_once(':')) else {
+        return false;
+    };
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    // SAFETY: `stat` is written by `fstat` before it is read
+    let stat = unsafe {
+        if libc::fstat(fd, stat.as_mut_ptr()) != 0 {
+            return false;
+        }
+        stat.assume_init()
+    };
+    dev.parse() == Ok(stat.st_dev) && ino.parse() == Ok(stat.st_ino)
+}
+
+/// Sets the least important level that is printed.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Prints every message to stderr, so stdout only carries the output of the program.
+pub fn use_stderr() {
+    ONLY_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prints the message if its level is enabled.
+///
+/// The lines after the first one are aligned under its text, like the hints of an error.
+pub fn write(level: Level, message: &dyn Display) {
+    if !enabled(level) {
+        return;
+    }
+    let message = message.to_string();
+
+    // Warnings and errors of every update interval would flood the output
+    if level <= Level::Warning {
+        let checked = REPEATS.lock().unwrap().check(level, &message, Instant::now());
+        match checked {
+            Some(message) => print(level, &message),
+            None => FLUSHER.call_once(|| {
+                thread::spawn(|| loop {
+                    thread::sleep(FLUSH_INTERVAL);
+                    flush();
+                });
+            }),
+        }
+    } else {
+        print(level, &message);
+    }
+}
+
+/// Prints the count of the messages held back in a window that ended.
+fn flush() {
+    let expired = REPEATS.lock().unwrap().expired(Instant::now());
+    for (level, message) in expired {
+        print(level, &message);
+    }
+}
+
+/// Prints the count of every message held back, before the program exits.
+pub fn flush_all() {
+    let held = REPEATS.lock().unwrap().drain();
+    for (level, message) in held {
+        print(level, &message);
+    }
+}
+
+fn print(level: Level, message: &str) {
+    let output = OUTPUT.get_or_init(Output::detect);
+    let (label, color) = match level {
+        Level::Error => ("Error!", "\x1b[31m"),
+        Level::Warning => ("Warning!", "\x1b[33m"),
+        Level::Info | Level::Debug => ("", ""),
+    };
+    let to_stderr = level <= Level::Warning || ONLY_STDERR.load(Ordering::Relaxed);
+    let journal = if to_stderr { output.stderr_journal } else { output.stdout_journal };
+    let prefix = if journal { format!("<{}>", level.priority()) } else { String::new() };
+    // The lines after the first one start under its text
+    let indent = if label.is_empty() { 0 } else { label.len() + 1 };
+
+    let mut text = String::new();
+    for (n, line) in message.split('\n').enumerate() {
+        // Blank lines only space out the terminal
+        if journal && line.is_empty() {
+            continue;
+        }
+        text += &prefix;
+        text += &match (n, label) {
+            (0, "") => line.to_owned(),
+            (0, _) if output.color => format!("\x1b[1m{color}{label}\x1b[39m {line}\x1b[0m"),
+            (0, _) => format!("{label} {line}"),
+            _ => format!("{:indent$}{line}", ""),
+        };
+        text.push('\n');
+    }
+
+    // A message is written at once, so the messages of several threads do not mix
+    let _ = if to_stderr {
+        stderr().lock().write_all(text.as_bytes())
+    } else {
+        stdout().lock().write_all(text.as_bytes())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated() {
+        let mut repeats = Repeats::default();
+        let start = Instant::now();
+        let message = "Failed to read the GPU\nIs the driver loaded?";
+        assert_eq!(repeats.check(Level::Warning, message, start).as_deref(), Some(message));
+        for n in 1..=3 {
+            assert_eq!(repeats.check(Level::Warning, message, start + Duration::from_secs(n)), None);
+        }
+        // The message comes back after the window, with its count on the first line
+        let again = repeats.check(Level::Warning, message, start + REPEAT_WINDOW);
+        assert_eq!(again.as_deref(), Some("Failed to read the GPU (repeated 3 more times)\nIs the driver loaded?"));
+        assert_eq!(repeats.check(Level::Warning, message, start + REPEAT_WINDOW * 2).as_deref(), Some(message));
+    }
+
+    #[test]
+    fn expired() {
+        let mut repeats = Repeats::default();
+        let start = Instant::now();
+        repeats.check(Level::Error, "Lost the device", start);
+        repeats.check(Level::Error, "Lost the device", start + Duration::from_secs(1));
+        repeats.check(Level::Warning, "Printed once", start);
+        assert!(repeats.expired(start + REPEAT_WINDOW / 2).is_empty());
+        // The count is printed when the window ends, even if the message never comes back
+        let expired = repeats.expired(start + REPEAT_WINDOW);
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].0 == Level::Error);
+        assert_eq!(expired[0].1, "Lost the device (repeated 1 more times)");
+        assert!(repeats.0.is_empty());
+        let again = repeats.check(Level::Error, "Lost the device", start + REPEAT_WINDOW);
+        assert_eq!(again.as_deref(), Some("Lost the device"));
+    }
+
+    #[test]
+    fn drained() {
+        let mut repeats = Repeats::default();
+        let start = Instant::now();
+        repeats.check(Level::Warning, "Printed once", start);
+        repeats.check(Level::Warning, "Held back", start);
+        repeats.check(Level::Warning, "Held back", start);
+        let drained = repeats.drain();
+        assert_eq!(drained.len(), 1);
+        assert_eq!(drained[0].1, "Held back (repeated 1 more times)");
+        assert!(repeats.0.is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+//! `man` subcommand, which prints the man page, built from the definition of the command line.
+
+use crate::{devices::Mode, error};
+use super::{
+    cli::{Opt, COMMANDS, OPTIONS},
+    config::CONFIG_PATH,
+    socket::SOCKET_PATH,
+};
+use std::process::exit;
+
+/// Runs the command given after `man`, and exits.
+pub fn run(args: &[String]) -> ! {
+    if let Some(arg) = args.first() {
+        error!(format!("Invalid option {arg}"));
+        exit(1);
+    }
+    print!("{}", page());
+    exit(0);
+}
+
+/// Escapes the text for roff, keeping it from being read as a request.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') { format!("\\&{text}") } else { text }
+}
+
+/// Returns the entry of the option, for a tagged paragraph.
+fn option(opt: &Opt) -> String {
+    let mut entry = ".TP\n".to_owned();
+    if let Some(short) = opt.short {
+        entry += &format!("\\fB\\-{short}\\fR, ");
+    }
+    entry += &format!("\\fB\\-\\-{}\\fR", escape(opt.long));
+    if let Some(value) = opt.value {
+        entry += &format!(" \\fI{value}\\fR");
+    }
+    entry += "\n";
+    for line in opt.help.lines() {
+        entry += &format!("{}\n", escape(line));
+    }
+    entry
+}
+
+fn page() -> String {
+    let name = env!("CARGO_PKG_NAME");
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{name} {}\" \"User Commands\"\n",
+        name.to_uppercase(),
+        env!("CARGO_PKG_VERSION")
+    );
+    page += &format!(".SH NAME\n{} \\- drive the displays of DeepCool coolers and cases\n", escape(name));
+    page += &format!(".SH SYNOPSIS\n.B {}\n[\\fICOMMAND\\fR] [\\fIOPTIONS\\fR]\n", escape(name));
+    page += ".SH DESCRIPTION\n";
+    page += "Shows the CPU and GPU readings on the displays of DeepCool devices, until it is stopped.\n";
+    page += "The options given before the first \\fB\\-\\-pid\\fR apply to every device, \
+             the ones after it only to that device.\n";
+
+    // Step 1: the commands
+    page += ".SH COMMANDS\n";
+    for command in COMMANDS {
+        page += &format!(".TP\n\\fB{}\\fR", escape(command.name));
+        if let Some(value) = command.value {
+            page += &format!(" \\fI{value}\\fR");
+        }
+        page += &format!("\n{}\n", escape(command.help));
+        if let Some((short, long)) = command.alias {
+            page += &format!(".br\nAlso given as \\fB\\-{short}\\fR or \\fB\\-\\-{}\\fR.\n", escape(long));
+        }
+        // Its own commands and options, indented under it
+        let actions: Vec<_> = command.actions.iter().filter(|action| !action.help.is_empty()).collect();
+        if actions.is_empty() && command.options.is_empty() {
+            continue;
+        }
+        page += ".RS\n";
+        for action in actions {
+            page += &format!(".TP\n\\fB{}\\fR", escape(action.name));
+            if let Some(value) = action.value {
+                page += &format!(" \\fI{}\\fR", escape(value));
+            }
+            page += &format!("\n{}\n", escape(action.help));
+        }
+        for opt in command.options {
+            page += &option(opt);
+        }
+        if command.run_options {
+            page += ".PP\nIt also takes the options below, and passes them to the services.\n";
+        }
+        page += ".RE\n";
+    }
+
+    // Step 2: the options
+    page += ".SH OPTIONS\n";
+    for opt in OPTIONS {
+        page += &option(opt);
+    }
+
+    // Step 3: the values and the environment
+    let modes: Vec<&str> = Mode::ALL.iter().map(Mode::symbol).collect();
+    page += ".SH DISPLAY MODES\n";
+    page += &format!("{}.\n", escape(&modes.join(", ")));
+    page += "Every device supports \\fBoff\\fR, the other modes depend on the device.\n";
+    page += ".SH ENVIRONMENT\n";
+    let mut envs: Vec<&str> = Vec::new();
+    for opt in OPTIONS.iter().chain(COMMANDS.iter().flat_map(|command| command.options)) {
+        if let Some(env) = opt.env.filter(|env| !envs.contains(env)) {
+            envs.push(env);
+            page += &format!(".TP\n.B {env}\nUsed when \\fB\\-\\-{}\\fR is not given.\n", escape(opt.long));
+        }
+    }
+    page += ".TP\n.B NO_COLOR\nDisables the colors of the messages.\n";
+    page += ".SH FILES\n";
+    page += &format!(".TP\n.I {}\nDefault configuration file.\n", escape(CONFIG_PATH));
+    page += &format!(".TP\n.I {}\nControl socket when the program runs as root.\n", escape(SOCKET_PATH));
+    page += ".SH SEE ALSO\n";
+    page += &format!("{}\n", escape("https://github.com/Nortank12/deepcool-digital-linux"));
+    page
+}
@@ -1,18 +1,57 @@
 pub mod args;
+pub mod cli;
+pub mod completions;
+pub mod config;
+pub mod ctl;
+pub mod dbus;
+pub mod install;
+pub mod json;
+pub mod lock;
+pub mod log;
+pub mod man;
+pub mod resume;
+pub mod signals;
+pub mod socket;
 pub mod status;
+pub mod systemd;
+pub mod udev;
+
+#[macro_export]
+macro_rules! error {
+    ($input:expr) => {
+        $crate::utils::log::write($crate::utils::log::Level::Error, &$input)
+    };
+    ($format:literal, $($arg:tt)+) => {
+        $crate::utils::log::write($crate::utils::log::Level::Error, &format!($format, $($arg)+))
+    };
+}
 
 #[macro_export]
 macro_rules! warning {
     ($input:expr) => {
-        use colored::*;
-        eprintln!("{}", format!("{} {}", "Warning!".yellow(), $input).bold());
+        $crate::utils::log::write($crate::utils::log::Level::Warning, &$input)
+    };
+    ($format:literal, $($arg:tt)+) => {
+        $crate::utils::log::write($crate::utils::log::Level::Warning, &format!($format, $($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($input:expr) => {
+        $crate::utils::log::write($crate::utils::log::Level::Info, &$input)
+    };
+    ($format:literal, $($arg:tt)+) => {
+        $crate::utils::log::write($crate::utils::log::Level::Info, &format!($format, $($arg)+))
     };
 }
 
 #[macro_export]
-macro_rules! error {
+macro_rules! debug {
     ($input:expr) => {
-        use colored::*;
-        eprintln!("{}", format!("{} {}", "Error!".red(), $input).bold());
+        $crate::utils::log::write($crate::utils::log::Level::Debug, &$input)
+    };
+    ($format:literal, $($arg:tt)+) => {
+        $crate::utils::log::write($crate::utils::log::Level::Debug, &format!($format, $($arg)+))
     };
 }
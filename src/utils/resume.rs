@@ -0,0 +1,46 @@
+//! Detection of the resume from suspend.
+//!
+//! The monotonic clock stops while the machine sleeps, but the boot time clock keeps running,
+//! so the gap between them grows by the time spent asleep.
+//! Unlike the wall clock, neither of them jumps when the time is set.
+
+use std::{io, time::Duration};
+
+/// Shortest sleep that is detected, longer than any delay between reading the two clocks.
+const MIN_SLEEP: Duration = Duration::from_secs(2);
+
+pub struct Detector {
+    asleep: Duration,
+}
+
+impl Detector {
+    /// Starts watching for a resume, fails if the clocks cannot be read.
+    pub fn start() -> io::Result<Self> {
+        Ok(Detector { asleep: asleep()? })
+    }
+
+    /// Returns `true` if the machine slept since the previous call.
+    pub fn resumed(&mut self) -> bool {
+        // A failed reading is ignored, the next one is compared to the last good one
+        let Ok(asleep) = asleep() else {
+            return false;
+        };
+        let slept = asleep.saturating_sub(self.asleep) >= MIN_SLEEP;
+        self.asleep = asleep;
+        slept
+    }
+}
+
+/// Returns the time the machine spent asleep since it booted.
+fn asleep() -> io::Result<Duration> {
+    Ok(clock(libc::CLOCK_BOOTTIME)?.saturating_sub(clock(libc::CLOCK_MONOTONIC)?))
+}
+
+fn clock(id: libc::clockid_t) -> io::Result<Duration> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `time` is a valid timespec
+    if unsafe { libc::clock_gettime(id, &mut time) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
@@ -0,0 +1,50 @@
+//! Process signals, received synchronously by a dedicated thread instead of a signal handler.
+
+use std::{io, mem::MaybeUninit};
+
+pub enum Signal {
+    /// `SIGHUP`, reload the configuration.
+    Reload,
+    /// `SIGINT` or `SIGTERM`, blank the displays and exit.
+    Terminate,
+}
+
+/// Returns the set of the handled signals.
+fn signal_set() -> libc::sigset_t {
+    // SAFETY: the set is initialized by `sigemptyset` before it is read
+    unsafe {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGHUP);
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGINT);
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGTERM);
+        set.assume_init()
+    }
+}
+
+/// Blocks the handled signals, so they wait for `wait` instead of killing the process.
+///
+/// Must be called before any thread is spawned, the threads inherit the signal mask.
+pub fn block() -> io::Result<()> {
+    let set = signal_set();
+    // SAFETY: the set is valid, and the old mask is not requested
+    match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// Waits until one of the handled signals is received.
+pub fn wait() -> io::Result<Signal> {
+    let set = signal_set();
+    let mut signal = 0;
+    // SAFETY: both pointers are valid for the duration of the call
+    match unsafe { libc::sigwait(&set, &mut signal) } {
+        0 => match signal {
+            libc::SIGHUP => Ok(Signal::Reload),
+            libc::SIGINT | libc::SIGTERM => Ok(Signal::Terminate),
+            _ => Err(io::Error::other(format!("unexpected signal {signal}"))),
+        },
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
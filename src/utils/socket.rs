@@ -0,0 +1,267 @@
+//! Control socket of the running program: a Unix domain socket that takes one JSON request per line,
+//! and answers each one with a single line.
+//!
+//! ```text
+//! {"command":"status"}
+//! {"command":"set","pid":16,"mode":"gpu_temp","secondary":"gpu_usage","rotate":90,"alarm":true}
+//! {"command":"reload"}
+//! {"command":"release","device":"3633-0004-A1B2C3"}
+//! ```
+//!
+//! Every answer has an `ok` member, and an `error` member when the request failed.
+//! `status` also returns the running `devices` and the last `readings`.
+//! `set` changes the devices with the given `pid`, or every device without it.
+//! `release` stops driving the device with the given lock, so another instance can take it over.
+
+use crate::devices::Mode;
+use super::json::{object, Value};
+use std::{
+    env::var_os,
+    fs::remove_file,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// Location of the socket when the program runs as root.
+pub const SOCKET_PATH: &str = "/run/deepcool-digital-linux.sock";
+
+/// Longest time to wait for a client to send its request.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest time to wait for the answer, the devices may be relaunched first.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Returns the location of the socket when `--socket` is not given.
+///
+/// Other users put it in their runtime directory, since they cannot write to `/run`.
+pub fn default_path() -> PathBuf {
+    // SAFETY: `geteuid` cannot fail
+    let root = unsafe { libc::geteuid() } == 0;
+    match var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !root => Path::new(&dir).join("deepcool-digital-linux.sock"),
+        _ => PathBuf::from(SOCKET_PATH),
+    }
+}
+
+pub enum Request {
+    /// Returns the settings of the running devices and the last readings.
+    Status,
+    /// Changes the settings of the running devices.
+    Set(Change),
+    /// Reads the configuration file again, like `SIGHUP`.
+    Reload,
+    /// Stops driving the device with the lock, for `--takeover`.
+    Release(String),
+}
+
+/// Settings to change, the missing ones are kept.
+#[derive(Default)]
+pub struct Change {
+    /// Product ID of the device to change, every device if not given.
+    pub pid: Option<u16>,
+    pub mode: Option<Mode>,
+    pub secondary: Option<Mode>,
+    pub rotate: Option<u16>,
+    pub alarm: Option<bool>,
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Request, String> {
+        let request = Value::parse(line).map_err(|err| format!("invalid JSON: {err}"))?;
+        let Value::Object(members) = &request else {
+            return Err("the request must be a JSON object".to_owned());
+        };
+
+        let command = request.get("command").and_then(Value::as_str).ok_or("missing command")?;
+        let allowed: &[&str] = match command {
+            "status" | "reload" => &["command"],
+            "set" => &["command", "pid", "mode", "secondary", "rotate", "alarm"],
+            "release" => &["command", "device"],
+            _ => return Err(format!("unknown command \"{command}\"")),
+        };
+        if let Some((key, _)) = members.iter().find(|(key, _)| !allowed.contains(&key.as_str())) {
+            return Err(format!("unknown member \"{key}\""));
+        }
+
+        match command {
+            "status" => Ok(Request::Status),
+            "reload" => Ok(Request::Reload),
+            "release" => match request.get("device").and_then(Value::as_str) {
+                Some(key) => Ok(Request::Release(key.to_owned())),
+                None => Err("missing device".to_owned()),
+            },
+            _ => {
+                let mode = |key: &str, name: &str| match request.get(key) {
+                    None => Ok(None),
+                    Some(value) => value.as_str().and_then(Mode::get).map(Some).ok_or(format!("invalid {name}")),
+                };
+                let change = Change {
+                    pid: match request.get("pid") {
+                        None => None,
+                        Some(value) => match value.as_u64().and_then(|pid| u16::try_from(pid).ok()) {
+                            Some(pid) if pid > 0 => Some(pid),
+                            _ => return Err("invalid PID".to_owned()),
+                        },
+                    },
+                    mode: mode("mode", "display mode")?,
+                    secondary: mode("secondary", "secondary display mode")?,
+                    rotate: match request.get("rotate") {
+                        None => None,
+                        Some(value) => match value.as_u64() {
+                            Some(val @ (0 | 90 | 180 | 270)) => Some(val as u16),
+                            _ => return Err("rotation value must be one of 0, 90, 180, or 270".to_owned()),
+                        },
+                    },
+                    alarm: match request.get("alarm") {
+                        None => None,
+                        Some(value) => Some(value.as_bool().ok_or("alarm must be true or false")?),
+                    },
+                };
+                Ok(Request::Set(change))
+            }
+        }
+    }
+}
+
+/// Listening control socket, removed when the program exits.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Server {
+    /// Creates the socket, replacing the one left behind by a program that did not exit cleanly.
+    pub fn bind(path: &Path) -> io::Result<Server> {
+        let listener = match UnixListener::bind(path) {
+            Err(err) if err.kind() == ErrorKind::AddrInUse => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(io::Error::new(ErrorKind::AddrInUse, "another instance is already running"));
+                }
+                remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        Ok(Server { listener, path: path.to_owned() })
+    }
+
+    /// Answers every client on its own thread, until the program exits.
+    pub fn serve(&self, handle: impl Fn(Request) -> Result<Value, String> + Sync) {
+        thread::scope(|scope| {
+            for stream in self.listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                // A client that stays connected without sending anything does not hold up the others
+                let handle = &handle;
+                scope.spawn(move || answer(stream, handle));
+            }
+        });
+    }
+
+    /// Removes the socket file.
+    pub fn remove(&self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+/// Answers every request of the client, until it closes the connection.
+fn answer(stream: UnixStream, handle: &impl Fn(Request) -> Result<Value, String>) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match Request::parse(&line).and_then(handle) {
+            Ok(Value::Object(mut members)) => {
+                members.insert(0, ("ok".to_owned(), Value::Bool(true)));
+                Value::Object(members)
+            }
+            Ok(_) => object([("ok", true.into())]),
+            Err(err) => object([("ok", false.into()), ("error", err.into())]),
+        };
+        writeln!(writer, "{response}")?;
+    }
+    Ok(())
+}
+
+/// Sends a request to the running program, and returns its answer.
+pub fn send(path: &Path, request: &Value) -> io::Result<Value> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(ANSWER_TIMEOUT))?;
+    writeln!(stream, "{request}")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Value::parse(&line).map_err(|err| io::Error::new(ErrorKind::InvalidData, format!("invalid answer: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        assert!(matches!(Request::parse(r#"{"command":"status"}"#), Ok(Request::Status)));
+        assert!(matches!(Request::parse(r#" { "command" : "reload" } "#), Ok(Request::Reload)));
+        assert!(matches!(
+            Request::parse(r#"{"command":"release","device":"3633-0004-A1\"B2"}"#),
+            Ok(Request::Release(key)) if key == "3633-0004-A1\"B2"
+        ));
+        let Ok(Request::Set(change)) =
+            Request::parse(r#"{"command":"set","pid":16,"mode":"gpu_temp","rotate":90,"alarm":true}"#)
+        else {
+            panic!("the change was not parsed");
+        };
+        assert_eq!(change.pid, Some(16));
+        assert!(change.mode == Some(Mode::GpuTemperature) && change.secondary.is_none());
+        assert_eq!((change.rotate, change.alarm), (Some(90), Some(true)));
+    }
+
+    #[test]
+    fn invalid_requests() {
+        for (line, err) in [
+            ("", "invalid JSON: unexpected end of data"),
+            (r#"{"command":"status""#, "invalid JSON: expected ',' or '}' at position 19"),
+            (r#"["status"]"#, "the request must be a JSON object"),
+            (r#"{"mode":"auto"}"#, "missing command"),
+            (r#"{"command":"stop"}"#, "unknown command \"stop\""),
+            (r#"{"command":"status","pid":16}"#, "unknown member \"pid\""),
+            (r#"{"command":"release"}"#, "missing device"),
+            (r#"{"command":"set","pid":0}"#, "invalid PID"),
+            (r#"{"command":"set","pid":70000}"#, "invalid PID"),
+            (r#"{"command":"set","mode":"warp"}"#, "invalid display mode"),
+            (r#"{"command":"set","secondary":1}"#, "invalid secondary display mode"),
+            (r#"{"command":"set","rotate":45}"#, "rotation value must be one of 0, 90, 180, or 270"),
+            (r#"{"command":"set","alarm":"on"}"#, "alarm must be true or false"),
+        ] {
+            assert_eq!(Request::parse(line).err().as_deref(), Some(err), "{line}");
+        }
+        let nested = format!(r#"{{"command":"status","x":{}{}}}"#, "[".repeat(1000), "]".repeat(1000));
+        assert!(Request::parse(&nested).err().is_some_and(|err| err.starts_with("invalid JSON: too deeply nested")));
+    }
+
+    #[test]
+    fn concurrent_clients() {
+        let path = std::env::temp_dir().join(format!("deepcool-socket-{}.sock", std::process::id()));
+        let server = Server::bind(&path).unwrap();
+        thread::spawn(move || server.serve(|_| Ok(object([("devices", Value::Array(Vec::new()))]))));
+
+        // The first client keeps its connection open without sending anything
+        let _idle = UnixStream::connect(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        // Shorter than the time the server waits for the idle client
+        client.set_read_timeout(Some(CLIENT_TIMEOUT / 5)).unwrap();
+        writeln!(client, r#"{{"command":"status"}}"#).unwrap();
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        let answer = Value::parse(&line).unwrap();
+        assert_eq!(answer.get("ok").and_then(Value::as_bool), Some(true));
+        assert!(answer.get("devices").is_some());
+        let _ = remove_file(&path);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Mode;
+use crate::{info, Mode};
 use colored::*;
 use std::time::Duration;
 
@@ -8,6 +8,14 @@ pub enum TemperatureUnit {
 }
 
 impl TemperatureUnit {
+    pub const fn get(fahrenheit: bool) -> Self {
+        if fahrenheit {
+            TemperatureUnit::Fahrenheit
+        } else {
+            TemperatureUnit::Celsius
+        }
+    }
+
     const fn symbol(&self) -> &'static str {
         match self {
             TemperatureUnit::Celsius => "°C",
@@ -29,53 +37,56 @@ pub struct Alarm {
     pub temp_warning: u8,
 }
 
-pub fn print_device_status(
-    mode: &Mode,
-    secondary: Option<&Mode>,
-    rotation: Option<u16>,
-    temp_unit: TemperatureUnit,
-    alarm: Alarm,
-    update: Duration,
-) {
-    println!("-----");
+/// Active settings of a display.
+pub struct Status {
+    pub mode: Mode,
+    pub secondary: Option<Mode>,
+    pub rotation: Option<u16>,
+    pub temp_unit: TemperatureUnit,
+    pub alarm: Alarm,
+    pub update: Duration,
+}
+
+pub fn print_device_status(status: &Status) {
+    let Status { mode, secondary, rotation, temp_unit, alarm, update } = status;
+    info!("-----");
     match secondary {
-        Some(s) => println!("DISP. MODE: {} | {}", mode.symbol().bright_cyan(), s.symbol().bright_cyan()),
-        None => println!("DISP. MODE: {}", mode.symbol().bright_cyan()),
+        Some(s) => info!("DISP. MODE: {} | {}", mode.symbol().bright_cyan(), s.symbol().bright_cyan()),
+        None => info!("DISP. MODE: {}", mode.symbol().bright_cyan()),
     }
-    if let Some(r) = rotation {
+    if let Some(r) = *rotation {
         if r > 0 {
-            println!("ROTATION:   {}", format!("{r}°").bright_cyan());
+            info!("ROTATION:   {}", format!("{r}°").bright_cyan());
         } else {
-            println!("ROTATION:   {}", "none".bright_black());
+            info!("ROTATION:   {}", "none".bright_black());
         }
     }
-    println!("TEMP. UNIT: {}", temp_unit.symbol().bright_cyan());
+    info!("TEMP. UNIT: {}", temp_unit.symbol().bright_cyan());
     match alarm.state {
         AlarmState::Auto => {
             if alarm.temp_warning > 0 {
-                println!(
+                info!(
                     "ALARM:      {} | {} [warning: {}]",
                     "auto".bright_green(),
                     (alarm.temp_limit.to_string() + temp_unit.symbol()).bright_cyan(),
                     (alarm.temp_warning.to_string() + temp_unit.symbol()).bright_cyan()
                 );
             } else {
-                println!(
+                info!(
                     "ALARM:      {} | {}",
                     "auto".bright_green(),
                     (alarm.temp_limit.to_string() + temp_unit.symbol()).bright_cyan()
                 );
             }
         }
-        AlarmState::On => println!(
+        AlarmState::On => info!(
             "ALARM:      {} | {}",
             "on".bright_green(),
             (alarm.temp_limit.to_string() + temp_unit.symbol()).bright_cyan()
         ),
-        AlarmState::Off => println!("ALARM:      {}", "off".bright_red()),
-        AlarmState::NotSupported => println!("ALARM:      {}", "not supported".bright_black().italic()),
+        AlarmState::Off => info!("ALARM:      {}", "off".bright_red()),
+        AlarmState::NotSupported => info!("ALARM:      {}", "not supported".bright_black().italic()),
     }
-    println!("-----");
-    println!("Update interval: {}", format!("{:?}", update).bright_cyan());
-    println!("\nPress {} to terminate", "Ctrl+C".bold());
+    info!("-----");
+    info!("Update interval: {}", format!("{:?}", update).bright_cyan());
 }
@@ -0,0 +1,44 @@
+//! Notifications of the service manager, sent to the socket given by systemd in `NOTIFY_SOCKET`.
+//!
+//! Without that variable, the program was not started by a `Type=notify` unit and nothing is sent.
+
+use std::{
+    env::var,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    time::Duration,
+};
+
+/// Returns `true` if the service manager waits for notifications.
+pub fn enabled() -> bool {
+    var("NOTIFY_SOCKET").is_ok()
+}
+
+/// Sends the `KEY=value` assignments to the service manager, like `READY=1`.
+pub fn notify(state: &str) -> io::Result<()> {
+    let Ok(path) = var("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    // Abstract sockets start with `@`
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(&path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+/// Returns the watchdog timeout set with `WatchdogSec=`, if it applies to this process.
+pub fn watchdog_timeout() -> Option<Duration> {
+    if let Ok(pid) = var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|timeout| !timeout.is_zero())
+}
@@ -0,0 +1,166 @@
+//! `udev-rules` subcommand, which prints the udev rules needed to run the program without root privilege
+//! on this machine, or installs them.
+
+use crate::{
+    devices::{CH510_PRODUCT_ID, CH510_VENDOR_ID, DEFAULT_VENDOR_ID},
+    error,
+    monitor::gpu::pci::{get_gpu_list, Vendor},
+};
+use super::{
+    args::{from_env, split_command},
+    cli::{self, print_command_help},
+};
+use colored::*;
+use std::{
+    fs::{self, read_dir},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+/// Location of the rules written with `--write`.
+const RULES_PATH: &str = "/etc/udev/rules.d/99-deepcool-digital.rules";
+
+/// Runs the command given after `udev-rules`, and exits.
+pub fn run(args: &[String]) -> ! {
+    let definition = cli::command("udev-rules");
+    let mut write = false;
+    let mut root = PathBuf::from("/");
+
+    let (given, words) = split_command(args, &definition.tables()).unwrap_or_else(|err| {
+        error!(err);
+        exit(1);
+    });
+    if let Some(word) = words.first() {
+        error!(format!("Invalid option {word}"));
+        exit(1);
+    }
+    for (opt, value) in from_env(definition.options).into_iter().chain(given) {
+        match opt.long {
+            "write" => write = true,
+            "root" => root = PathBuf::from(value.unwrap_or_default()),
+            "help" => {
+                print_command_help(definition);
+                exit(0);
+            }
+            long => unreachable!("--{long} is not handled"),
+        }
+    }
+
+    let rules = render(&root);
+    if !write {
+        print!("{rules}");
+        exit(0);
+    }
+    if let Err(err) = fs::write(RULES_PATH, rules) {
+        error!(format!("Failed to write {RULES_PATH}: {err}"));
+        exit(1);
+    }
+    println!("Rules created: {}", RULES_PATH.bright_green());
+    println!("\nApply them without rebooting with:");
+    println!("  sudo udevadm control --reload && sudo udevadm trigger");
+    exit(0);
+}
+
+/// Returns the rules for the devices, the RAPL domains, and the GPUs found below the root.
+fn render(root: &Path) -> String {
+    let mut rules = format!("# Generated by {} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    // Step 1: the displays
+    rules += "\n# DeepCool HID raw devices\n";
+    rules += &format!("SUBSYSTEM==\"hidraw\", ATTRS{{idVendor}}==\"{DEFAULT_VENDOR_ID:04x}\", MODE=\"0666\"\n");
+    rules += "\n# CH510 MESH DIGITAL\n";
+    rules += &format!(
+        "SUBSYSTEM==\"hidraw\", ATTRS{{idVendor}}==\"{CH510_VENDOR_ID:04x}\", \
+         ATTRS{{idProduct}}==\"{CH510_PRODUCT_ID:04x}\", MODE=\"0666\"\n"
+    );
+
+    // Step 2: the energy counters of the CPU packages, only root can read them by default
+    let domains = rapl_domains(root);
+    if !domains.is_empty() {
+        rules += "\n# RAPL energy usage files\n";
+    }
+    for domain in domains {
+        rules += &format!(
+            "ACTION==\"add\", SUBSYSTEM==\"powercap\", KERNEL==\"{domain}\", \
+             RUN+=\"/bin/chmod 444 /sys/class/powercap/{domain}/energy_uj\"\n"
+        );
+    }
+
+    // Step 3: the GPU sensors that other users cannot read
+    for gpu in get_gpu_list(root).unwrap_or_default() {
+        // NVML reads the NVIDIA GPUs through /dev/nvidia*, which every user can open
+        if gpu.vendor == Vendor::Nvidia {
+            continue;
+        }
+        let device = root.join("sys/bus/pci/devices").join(&gpu.address);
+        let mut lines = Vec::new();
+
+        let files = restricted(&device, |name| name == "gpu_busy_percent");
+        if !files.is_empty() {
+            let paths: Vec<String> =
+                files.iter().map(|file| format!("/sys/bus/pci/devices/{}/{file}", gpu.address)).collect();
+            lines.push(format!(
+                "ACTION==\"add\", SUBSYSTEM==\"drm\", KERNEL==\"card*\", KERNELS==\"{}\", \
+                 RUN+=\"/bin/chmod 444 {}\"",
+                gpu.address,
+                paths.join(" ")
+            ));
+        }
+        // The number of the hwmon directory can change on every boot
+        for hwmon in read_dir(device.join("hwmon")).into_iter().flatten().flatten() {
+            let files = restricted(&hwmon.path(), is_sensor);
+            if files.is_empty() {
+                continue;
+            }
+            let paths: Vec<String> = files.iter().map(|file| format!("/sys%p/{file}")).collect();
+            lines.push(format!(
+                "ACTION==\"add\", SUBSYSTEM==\"hwmon\", KERNELS==\"{}\", RUN+=\"/bin/chmod 444 {}\"",
+                gpu.address,
+                paths.join(" ")
+            ));
+        }
+
+        if !lines.is_empty() {
+            rules += &format!("\n# {} ({})\n", gpu.name, gpu.address);
+            rules += &(lines.join("\n") + "\n");
+        }
+    }
+
+    rules
+}
+
+/// Returns the RAPL domains of the CPU packages, like `intel-rapl:0`.
+fn rapl_domains(root: &Path) -> Vec<String> {
+    let mut domains: Vec<String> = read_dir(root.join("sys/class/powercap"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        // The subdomains (`intel-rapl:0:0`) are not read
+        .filter(|name| name.strip_prefix("intel-rapl:").is_some_and(|n| n.parse::<u8>().is_ok()))
+        .filter(|name| root.join("sys/class/powercap").join(name).join("energy_uj").exists())
+        .collect();
+    domains.sort();
+    domains
+}
+
+/// Returns the names of the files in the directory accepted by the filter, that other users cannot read.
+fn restricted(dir: &Path, filter: impl Fn(&str) -> bool) -> Vec<String> {
+    let mut files: Vec<String> = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| filter(&entry.file_name().to_string_lossy()))
+        .filter(|entry| entry.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o004 == 0))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Returns `true` for the hwmon readings of the GPU monitors, like `power1_average`.
+fn is_sensor(name: &str) -> bool {
+    ["temp", "power", "freq", "energy"].iter().any(|prefix| name.starts_with(prefix))
+        && (name.ends_with("_input") || name.ends_with("_average"))
+}